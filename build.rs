@@ -0,0 +1,15 @@
+fn main() {
+	// see ffi; apps include the header, while the crate itself doesn't need it
+	#[cfg(feature = "headers")]
+	{
+		let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+		let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir)).unwrap();
+
+		println!("cargo:rerun-if-changed=src/ffi.rs");
+		println!("cargo:rerun-if-changed=cbindgen.toml");
+
+		cbindgen::generate_with_config(&dir, config)
+			.expect("failed to generate bindings")
+			.write_to_file(format!("{}/include/qvault.h", dir));
+	}
+}
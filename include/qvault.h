@@ -0,0 +1,189 @@
+#ifndef QVAULT_H
+#define QVAULT_H
+
+/* generated with `cargo build --features headers`; do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * What every call returns; the network callback returns one as well, as a u32.
+ */
+typedef enum QvStatus {
+  QvStatus_Ok = 0,
+  QvStatus_NotFound,
+  QvStatus_NoNetwork,
+  QvStatus_NoAccess,
+  QvStatus_BadOperation,
+  QvStatus_BadEncoding,
+  QvStatus_Locked,
+  QvStatus_Io,
+  QvStatus_BadDigest,
+  QvStatus_Unlisted,
+  QvStatus_RolledBack,
+  /**
+   * Returned by the network callback along with the stored node, see Network::save_node.
+   */
+  QvStatus_Conflict,
+  /**
+   * A null pointer or a string of bad utf-8.
+   */
+  QvStatus_BadArgument,
+  /**
+   * A bug in the library; the protocol it happened in is not to be used anymore, other than to
+   * be freed.
+   */
+  QvStatus_Panic,
+} QvStatus;
+
+typedef struct QvProtocol QvProtocol;
+
+/**
+ * Filled by the network callback with qvault_response_set; owned by the library.
+ */
+typedef struct QvResponse QvResponse;
+
+/**
+ * The app's side of Network.
+ *
+ * call is to perform the json-encoded request and, if it succeeds, set the response; it's
+ * expected to return, as a u32, Ok, Conflict (with the stored node as the response), NotFound,
+ * NoAccess, BadOperation or BadEncoding, if the server rejects the request, Io, if it fails to
+ * store it, or NoNetwork; any other status is taken as NoNetwork. req and resp are only valid for
+ * the duration of the call.
+ *
+ * Once qvault_protocol_new or qvault_protocol_unlock succeeds, the protocol owns ctx and calls
+ * release, if set, with it when freed; should they fail, ctx remains the app's to release.
+ */
+typedef struct QvNet {
+  void *ctx;
+  uint32_t (*call)(void *ctx, const uint8_t *req, uintptr_t req_len, struct QvResponse *resp);
+  void (*release)(void *ctx);
+} QvNet;
+
+/**
+ * A node of a DirInfo, released along with it.
+ */
+typedef struct QvNodeInfo {
+  uint64_t id;
+  uint64_t created_at;
+  uint64_t size;
+  bool is_dir;
+  char *name;
+  /**
+   * Null for dirs and files with no extension.
+   */
+  char *ext;
+} QvNodeInfo;
+
+/**
+ * A listed dir, owned by the app once returned; release it with qvault_dir_free.
+ */
+typedef struct QvDirInfo {
+  char *name;
+  struct QvNodeInfo *items;
+  uintptr_t items_len;
+  /**
+   * Grand-grandparent, grandparent, parent.
+   */
+  struct QvNodeInfo *breadcrumbs;
+  uintptr_t breadcrumbs_len;
+} QvDirInfo;
+
+/**
+ * Bytes owned by the app once returned; release them with qvault_buf_free.
+ */
+typedef struct QvBuf {
+  uint8_t *ptr;
+  uintptr_t len;
+} QvBuf;
+
+/**
+ * Starts a protocol for a regular user; ident_priv is a wire-encoded identity::Private, locked a
+ * wire-encoded LockedUser. On success, out is to be passed to qvault_protocol_migrate and
+ * released with qvault_protocol_free; see Net for who releases net.
+ */
+enum QvStatus qvault_protocol_new(const uint8_t *ident_priv,
+                                  uintptr_t ident_priv_len,
+                                  const uint8_t *locked,
+                                  uintptr_t locked_len,
+                                  struct QvNet net,
+                                  struct QvProtocol **out);
+
+/**
+ * Starts a protocol for an admin, whose private key is locked with a password; see
+ * Protocol::unlock. On success, out is to be passed to qvault_protocol_migrate and released with
+ * qvault_protocol_free; see Net for who releases net.
+ */
+enum QvStatus qvault_protocol_unlock(const uint8_t *locked,
+                                     uintptr_t locked_len,
+                                     const char *pass,
+                                     struct QvNet net,
+                                     struct QvProtocol **out);
+
+/**
+ * Saves what qvault_protocol_new or qvault_protocol_unlock found in outdated layouts: own nodes,
+ * received shares and the password lock; see Protocol::migrate. To be called once started, and
+ * again later, should it fail; on success, out is set to how many were saved.
+ */
+enum QvStatus qvault_protocol_migrate(struct QvProtocol *protocol, uintptr_t *out);
+
+/**
+ * Releases a protocol along with its Net; protocol may be null.
+ */
+void qvault_protocol_free(struct QvProtocol *protocol);
+
+/**
+ * Lists the current dir; on success, out is to be released with qvault_dir_free.
+ */
+enum QvStatus qvault_ls_cur(struct QvProtocol *protocol, struct QvDirInfo **out);
+
+/**
+ * Enters a dir and lists it; on success, out is to be released with qvault_dir_free.
+ */
+enum QvStatus qvault_cd_to_dir(struct QvProtocol *protocol, uint64_t id, struct QvDirInfo **out);
+
+/**
+ * Leaves the current dir and lists its parent; on success, out is to be released with
+ * qvault_dir_free.
+ */
+enum QvStatus qvault_go_back(struct QvProtocol *protocol, struct QvDirInfo **out);
+
+/**
+ * Fetches a chunk through the network callback and decrypts it; on success, out is to be released
+ * with qvault_buf_free.
+ */
+enum QvStatus qvault_download_chunk(struct QvProtocol *protocol,
+                                    uint64_t file_id,
+                                    uint64_t chunk_idx,
+                                    struct QvBuf *out);
+
+/**
+ * Decrypts a chunk fetched by the app itself, eg streamed or cached; ct remains the app's. On
+ * success, out is to be released with qvault_buf_free.
+ */
+enum QvStatus qvault_decrypt_chunk(struct QvProtocol *protocol,
+                                   uint64_t file_id,
+                                   uint64_t chunk_idx,
+                                   const uint8_t *ct,
+                                   uintptr_t ct_len,
+                                   struct QvBuf *out);
+
+/**
+ * Copies bytes into a response, so ptr remains the app's; to be called by the network callback.
+ */
+void qvault_response_set(struct QvResponse *resp, const uint8_t *ptr, uintptr_t len);
+
+/**
+ * Releases a dir returned by the library, its nodes included; dir may be null.
+ */
+void qvault_dir_free(struct QvDirInfo *dir);
+
+/**
+ * Releases bytes returned by the library.
+ */
+void qvault_buf_free(struct QvBuf buf);
+
+#endif /* QVAULT_H */
@@ -4,15 +4,20 @@ use aes_gcm::{
 	Aes256Gcm,
 };
 use rand::rngs::OsRng;
-use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use zeroize::{Zeroize, Zeroizing};
 
-use crate::hkdf;
+use crate::{
+	env::Rng,
+	hkdf,
+	wire::{self, Format},
+};
 
 pub const KEY_SIZE: usize = 32;
 pub const IV_SIZE: usize = 12;
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Key {
 	#[serde(
 		serialize_with = "serialize_array_base64::<_, KEY_SIZE>",
@@ -25,8 +30,12 @@ impl Key {
 	pub const SIZE: usize = KEY_SIZE;
 
 	pub fn generate() -> Self {
+		Self::generate_with(&mut OsRng)
+	}
+
+	pub fn generate_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
 		let mut key = [0u8; Self::SIZE];
-		OsRng.fill_bytes(&mut key);
+		rng.fill_bytes(&mut key);
 		Self { bytes: key }
 	}
 
@@ -35,6 +44,24 @@ impl Key {
 	}
 }
 
+impl PartialEq for Key {
+	fn eq(&self, other: &Self) -> bool {
+		self.bytes.ct_eq(&other.bytes).into()
+	}
+}
+
+impl std::fmt::Debug for Key {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Key").finish_non_exhaustive()
+	}
+}
+
+impl Drop for Key {
+	fn drop(&mut self) {
+		self.bytes.zeroize();
+	}
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Iv {
 	#[serde(
@@ -48,8 +75,12 @@ impl Iv {
 	pub const SIZE: usize = IV_SIZE;
 
 	pub fn generate() -> Self {
+		Self::generate_with(&mut OsRng)
+	}
+
+	pub fn generate_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
 		let mut iv = [0u8; Self::SIZE];
-		OsRng.fill_bytes(&mut iv);
+		rng.fill_bytes(&mut iv);
 		Self { bytes: iv }
 	}
 
@@ -64,6 +95,17 @@ pub enum Error {
 	WrongKeyIvSize,
 }
 
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Error::WrongKeyMaterial => "wrong key material",
+			Error::WrongKeyIvSize => "wrong key or iv size",
+		})
+	}
+}
+
+impl std::error::Error for Error {}
+
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Aes {
 	pub key: Key,
@@ -72,7 +114,13 @@ pub struct Aes {
 
 impl Aes {
 	pub fn new() -> Self {
-		Self::new_with_key_iv(Key::generate(), Iv::generate())
+		Self::generate_with(&mut OsRng)
+	}
+
+	pub fn generate_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
+		let key = Key::generate_with(rng);
+
+		Self::new_with_key_iv(key, Iv::generate_with(rng))
 	}
 
 	pub fn new_with_key(key: Key) -> Self {
@@ -101,9 +149,14 @@ impl Aes {
 	where
 		T: Serialize,
 	{
-		let serialized = serde_json::to_vec(&pt).unwrap();
+		self.encrypt_serializable_as(pt, Format::Json)
+	}
 
-		self.encrypt(&serialized)
+	pub fn encrypt_serializable_as<T>(&self, pt: T, format: Format) -> Vec<u8>
+	where
+		T: Serialize,
+	{
+		self.encrypt(&wire::encode(&pt, format))
 	}
 
 	pub fn decrypt(&self, ct: &[u8]) -> Result<Vec<u8>, Error> {
@@ -114,45 +167,50 @@ impl Aes {
 			.map_err(|_| Error::WrongKeyMaterial)
 	}
 
-	fn key_for_chunk_idx(&self, idx: u32) -> Self {
-		let chunk_key = hkdf::Hkdf::from_ikm(&self.as_bytes())
-			.expand::<{ Key::SIZE + Iv::SIZE }>(&idx.to_be_bytes());
+	pub(crate) fn key_for_chunk_idx(&self, idx: u64) -> Self {
+		let hkdf = hkdf::Hkdf::from_ikm(self.as_bytes().as_slice());
+		// chunks below 4 GiB used to be indexed by u32, so keep deriving their keys the same way
+		let chunk_key = if let Ok(idx) = u32::try_from(idx) {
+			hkdf.expand::<{ Key::SIZE + Iv::SIZE }>(&idx.to_be_bytes())
+		} else {
+			hkdf.expand::<{ Key::SIZE + Iv::SIZE }>(&idx.to_be_bytes())
+		};
 
 		Aes::from(&chunk_key)
 	}
 
-	pub fn chunk_encrypt(&self, idx: u32, pt: &[u8]) -> Vec<u8> {
+	pub fn chunk_encrypt(&self, idx: u64, pt: &[u8]) -> Vec<u8> {
 		let aes = self.key_for_chunk_idx(idx);
 
 		aes.encrypt(pt)
 	}
 
-	pub async fn chunk_encrypt_async(&self, idx: u32, pt: &[u8]) -> Vec<u8> {
+	pub async fn chunk_encrypt_async(&self, idx: u64, pt: &[u8]) -> Vec<u8> {
 		let aes = self.key_for_chunk_idx(idx);
 
 		aes.encrypt_async(pt).await
 	}
 
-	pub fn chunk_decrypt(&self, idx: u32, ct: &[u8]) -> Result<Vec<u8>, Error> {
+	pub fn chunk_decrypt(&self, idx: u64, ct: &[u8]) -> Result<Vec<u8>, Error> {
 		let aes = self.key_for_chunk_idx(idx);
 
 		aes.decrypt(ct)
 	}
 
-	pub async fn chunk_decrypt_async(&self, idx: u32, ct: &[u8]) -> Result<Vec<u8>, Error> {
+	pub async fn chunk_decrypt_async(&self, idx: u64, ct: &[u8]) -> Result<Vec<u8>, Error> {
 		let aes = self.key_for_chunk_idx(idx);
 
 		aes.decrypt_async(ct).await
 	}
 
-	pub fn as_bytes(&self) -> [u8; Key::SIZE + Iv::SIZE] {
-		[
-			self.key.as_bytes().as_slice(),
-			self.iv.as_bytes().as_slice(),
-		]
-		.concat()
-		.try_into()
-		.unwrap()
+	// wiped when dropped, so copy out with care
+	pub fn as_bytes(&self) -> Zeroizing<[u8; Key::SIZE + Iv::SIZE]> {
+		let mut bytes = Zeroizing::new([0u8; Key::SIZE + Iv::SIZE]);
+
+		bytes[..Key::SIZE].copy_from_slice(self.key.as_bytes());
+		bytes[Key::SIZE..].copy_from_slice(self.iv.as_bytes());
+
+		bytes
 	}
 }
 
@@ -193,6 +251,7 @@ mod tests {
 	use rand::{rngs::OsRng, RngCore};
 
 	use super::{Aes, Error, Iv, Key};
+	use crate::hkdf;
 
 	#[test]
 	fn test_encrypt_decrypt() {
@@ -287,8 +346,8 @@ mod tests {
 				(i + 1) * pt_chunk_len
 			};
 			let chunk = &msg[start..end];
-			let encrypted_chunk = aes.chunk_encrypt(i as u32, chunk);
-			let decrypted_chunk = aes.chunk_decrypt(i as u32, &encrypted_chunk).unwrap();
+			let encrypted_chunk = aes.chunk_encrypt(i as u64, chunk);
+			let decrypted_chunk = aes.chunk_decrypt(i as u64, &encrypted_chunk).unwrap();
 
 			assert_eq!(chunk, decrypted_chunk);
 
@@ -304,11 +363,57 @@ mod tests {
 			} else {
 				(i + 1) * ct_chunk_len
 			};
-			let chunk = aes.chunk_decrypt(i as u32, &ct[start..end]).unwrap();
+			let chunk = aes.chunk_decrypt(i as u64, &ct[start..end]).unwrap();
 
 			pt.extend(chunk.into_iter());
 		}
 
 		assert_eq!(msg.to_vec(), pt);
 	}
+
+	#[test]
+	fn test_chunk_keys_compatible_with_u32_indices() {
+		let aes = Aes::new();
+		let legacy_key = |idx: u32| {
+			Aes::from(
+				&hkdf::Hkdf::from_ikm(aes.as_bytes().as_slice())
+					.expand::<{ Key::SIZE + Iv::SIZE }>(&idx.to_be_bytes()),
+			)
+		};
+
+		assert_eq!(aes.key_for_chunk_idx(0), legacy_key(0));
+		assert_eq!(aes.key_for_chunk_idx(12345), legacy_key(12345));
+		assert_eq!(aes.key_for_chunk_idx(u32::MAX as u64), legacy_key(u32::MAX));
+		assert_ne!(aes.key_for_chunk_idx(u32::MAX as u64 + 1), legacy_key(0));
+		assert_ne!(
+			aes.key_for_chunk_idx(u32::MAX as u64 + 1),
+			aes.key_for_chunk_idx(1 << 33)
+		);
+	}
+
+	#[test]
+	fn test_chunk_encrypt_decrypt_beyond_u32() {
+		let aes = Aes::new();
+		let idx = u32::MAX as u64 + 7;
+		let ct = aes.chunk_encrypt(idx, b"chunk");
+
+		assert_eq!(aes.chunk_decrypt(idx, &ct), Ok(b"chunk".to_vec()));
+		assert!(aes.chunk_decrypt(7, &ct).is_err());
+	}
+
+	#[test]
+	fn test_debug_does_not_leak_key() {
+		let aes = Aes::new_with_key_iv(
+			Key {
+				bytes: [12u8; Key::SIZE],
+			},
+			Iv {
+				bytes: [34u8; Iv::SIZE],
+			},
+		);
+		let debug = format!("{:?}", aes);
+
+		assert!(debug.contains("Key { .. }"));
+		assert!(!debug.contains("12, 12"));
+	}
 }
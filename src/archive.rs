@@ -0,0 +1,547 @@
+// a self-contained copy of a subtree, eg for legal hold handovers or migrations between tenants:
+// its names and verified file contents, locked with a password and nothing else, so that no key of
+// the vault it came from travels along; see Protocol::export and Protocol::import
+//
+// it's streamed rather than held whole: a header with a fresh master key locked by the password,
+// then records sealed under that key, each with a key of its own for its position so that none can
+// be dropped, reordered or repeated unnoticed; an item per node, parents first, a file's followed
+// by its contents in chunks, and an end record, so that a truncated archive doesn't open either
+
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	aes_gcm::Aes,
+	base64_blobs::{deserialize_vec_base64, serialize_vec_base64},
+	encrypted::Encrypted,
+	env::Rng,
+	error::{Cause, Stage},
+	id::Uid,
+	password_lock,
+	protocol::Error,
+	vault::CHUNK_SIZE,
+	wire::{self, Format},
+};
+
+pub(crate) const VERSION: u32 = 1;
+
+// the most plaintext a chunk record carries
+const MAX_CHUNK: usize = CHUNK_SIZE as usize;
+// a full chunk's record once encoded and sealed, with room to spare for an item's
+const MAX_RECORD: usize = MAX_CHUNK + 64 * 1024;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub(crate) struct Chunk(
+	#[serde(
+		serialize_with = "serialize_vec_base64",
+		deserialize_with = "deserialize_vec_base64"
+	)]
+	pub Vec<u8>,
+);
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+	version: u32,
+	// the key records are sealed under, locked with the password
+	master_key: Encrypted,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) enum Kind {
+	Dir,
+	// followed by its contents in chunk records, verified against its digest on export
+	File { ext: String, size: u64 },
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) struct Item {
+	// the index of its dir, which comes first; None for the root
+	pub parent: Option<usize>,
+	pub name: String,
+	pub kind: Kind,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub(crate) enum Record {
+	Item(Item),
+	Chunk(Chunk),
+	End,
+}
+
+// writes an archive of id record by record; unless finished, it won't open
+pub(crate) struct Sealer<'a, W: Write> {
+	out: &'a mut W,
+	master_key: Aes,
+	// the next record's position
+	idx: u64,
+}
+
+// the password's key derivation is the only thing to fail locking, so it gets a stage of its own
+// rather than passing for a failure to open
+pub(crate) fn seal<'a, W, R>(
+	out: &'a mut W,
+	id: Uid,
+	pass: &str,
+	rng: &mut R,
+) -> Result<Sealer<'a, W>, Error>
+where
+	W: Write,
+	R: Rng + ?Sized,
+{
+	let master_key = Aes::generate_with(rng);
+	let header = Header {
+		version: VERSION,
+		master_key: password_lock::lock_master_key_with(&master_key, pass, rng).map_err(|e| {
+			Error::Locked {
+				id,
+				stage: Stage::Encrypt,
+				cause: Cause::new(e),
+			}
+		})?,
+	};
+
+	write_framed(out, &wire::encode(&header, Format::Cbor))?;
+
+	Ok(Sealer {
+		out,
+		master_key,
+		idx: 0,
+	})
+}
+
+impl<W: Write> Sealer<'_, W> {
+	pub fn item(&mut self, item: Item) -> Result<(), Error> {
+		self.record(&Record::Item(item))
+	}
+
+	// a file's contents, in as many records as it takes
+	pub fn contents(&mut self, pt: &[u8]) -> Result<(), Error> {
+		for chunk in pt.chunks(MAX_CHUNK) {
+			self.record(&Record::Chunk(Chunk(chunk.to_vec())))?;
+		}
+
+		Ok(())
+	}
+
+	pub fn finish(mut self) -> Result<(), Error> {
+		self.record(&Record::End)?;
+		self.out.flush().map_err(io_err)
+	}
+
+	fn record(&mut self, record: &Record) -> Result<(), Error> {
+		let ct = self
+			.master_key
+			.chunk_encrypt(self.idx, &wire::encode(record, Format::Cbor));
+
+		self.idx += 1;
+
+		write_framed(self.out, &ct)
+	}
+}
+
+// reads an archive record by record, each authenticated as it's read; there's no id to blame
+// before an item is imported, so errors refer to where it's imported to
+pub(crate) struct Opener<'a, R: Read> {
+	src: &'a mut R,
+	master_key: Aes,
+	target_id: Uid,
+	// the next record's position, and the bytes read up to it past the header
+	idx: u64,
+	read: u64,
+}
+
+pub(crate) fn open<'a, R: Read>(
+	src: &'a mut R,
+	pass: &str,
+	target_id: Uid,
+) -> Result<Opener<'a, R>, Error> {
+	let header = read_framed(src, target_id)?.ok_or(Error::Locked {
+		id: target_id,
+		stage: Stage::Decode,
+		cause: Cause::default(),
+	})?;
+	let header: Header = wire::decode(&header).map_err(Error::BadEncoding)?;
+
+	if header.version > VERSION {
+		return Err(Error::Locked {
+			id: target_id,
+			stage: Stage::Version,
+			cause: Cause::default(),
+		});
+	}
+
+	let master_key =
+		password_lock::decrypt_master_key(&header.master_key, pass).map_err(|e| Error::Locked {
+			id: target_id,
+			stage: Stage::Decrypt,
+			cause: Cause::new(e),
+		})?;
+
+	Ok(Opener {
+		src,
+		master_key,
+		target_id,
+		idx: 0,
+		read: 0,
+	})
+}
+
+impl<'a, R: Read> Opener<'a, R> {
+	pub fn next(&mut self) -> Result<Record, Error> {
+		let ct = read_framed(self.src, self.target_id)?.ok_or_else(|| self.bad_layout())?;
+
+		self.read += 4 + ct.len() as u64;
+
+		let pt = self
+			.master_key
+			.chunk_decrypt(self.idx, &ct)
+			.map_err(|e| Error::Locked {
+				id: self.target_id,
+				stage: Stage::Decrypt,
+				cause: Cause::new(e),
+			})?;
+
+		self.idx += 1;
+
+		wire::decode(&pt).map_err(|e| Error::Locked {
+			id: self.target_id,
+			stage: Stage::Decode,
+			cause: Cause::new(e),
+		})
+	}
+
+	// the next item, or None past the last one
+	pub fn next_item(&mut self) -> Result<Option<Item>, Error> {
+		match self.next()? {
+			Record::Item(item) => Ok(Some(item)),
+			Record::End => Ok(None),
+			Record::Chunk(_) => Err(self.bad_layout()),
+		}
+	}
+
+	// the contents of the file just read, size bytes long
+	pub fn contents(&mut self, size: u64) -> Contents<'_, 'a, R> {
+		Contents {
+			archive: self,
+			remaining: size,
+			chunk: Vec::new(),
+			pos: 0,
+		}
+	}
+
+	// reads through the whole archive, checking that it's laid out as written: items parented by
+	// dirs before them, each file followed by exactly its contents, and nothing after the end
+	pub fn verify(&mut self) -> Result<(), Error> {
+		// whether each item so far is a dir
+		let mut dirs = Vec::new();
+		// the contents still expected of the last file
+		let mut remaining = 0u64;
+
+		loop {
+			match self.next()? {
+				Record::Item(item) if remaining == 0 => {
+					let parented = match item.parent {
+						None => dirs.is_empty(),
+						Some(idx) => dirs.get(idx) == Some(&true),
+					};
+
+					if !parented {
+						return Err(self.bad_layout());
+					}
+
+					if let Kind::File { size, .. } = item.kind {
+						remaining = size;
+					}
+
+					dirs.push(item.kind == Kind::Dir);
+				}
+				Record::Chunk(Chunk(pt)) if !pt.is_empty() && pt.len() as u64 <= remaining => {
+					remaining -= pt.len() as u64
+				}
+				Record::End if remaining == 0 && !dirs.is_empty() => break,
+				_ => return Err(self.bad_layout()),
+			}
+		}
+
+		match read_framed(self.src, self.target_id)? {
+			None => Ok(()),
+			Some(_) => Err(self.bad_layout()),
+		}
+	}
+
+	fn bad_layout(&self) -> Error {
+		Error::Locked {
+			id: self.target_id,
+			stage: Stage::Decode,
+			cause: Cause::default(),
+		}
+	}
+}
+
+impl<R: Read + Seek> Opener<'_, R> {
+	// back to the first record, eg to import what's been verified
+	pub fn rewind(&mut self) -> Result<(), Error> {
+		self.src
+			.seek(SeekFrom::Current(-(self.read as i64)))
+			.map_err(io_err)?;
+		self.idx = 0;
+		self.read = 0;
+
+		Ok(())
+	}
+}
+
+// a file's contents, read from its chunk records as they're needed; see Opener::contents
+pub(crate) struct Contents<'o, 'a, R: Read> {
+	archive: &'o mut Opener<'a, R>,
+	// the contents not read from the archive yet
+	remaining: u64,
+	chunk: Vec<u8>,
+	pos: usize,
+}
+
+impl<R: Read> Read for Contents<'_, '_, R> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		if self.pos == self.chunk.len() {
+			if self.remaining == 0 {
+				return Ok(0);
+			}
+
+			match self.archive.next() {
+				Ok(Record::Chunk(Chunk(pt)))
+					if !pt.is_empty() && pt.len() as u64 <= self.remaining =>
+				{
+					self.remaining -= pt.len() as u64;
+					self.chunk = pt;
+					self.pos = 0;
+				}
+				Ok(_) => return Err(std::io::Error::other(self.archive.bad_layout())),
+				Err(e) => return Err(std::io::Error::other(e)),
+			}
+		}
+
+		let n = buf.len().min(self.chunk.len() - self.pos);
+
+		buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+		self.pos += n;
+
+		Ok(n)
+	}
+}
+
+fn io_err(e: std::io::Error) -> Error {
+	Error::Io(e.to_string())
+}
+
+// a record prefixed with its length
+fn write_framed<W: Write>(out: &mut W, bytes: &[u8]) -> Result<(), Error> {
+	out.write_all(&(bytes.len() as u32).to_be_bytes())
+		.and_then(|_| out.write_all(bytes))
+		.map_err(io_err)
+}
+
+// the next record, or None at the end of src; a truncated record or one longer than any written,
+// eg of a corrupted length, fails to decode rather than being allocated
+fn read_framed<R: Read>(src: &mut R, target_id: Uid) -> Result<Option<Vec<u8>>, Error> {
+	let bad_layout = |e: std::io::Error| Error::Locked {
+		id: target_id,
+		stage: Stage::Decode,
+		cause: Cause::new(e),
+	};
+	let mut len = [0u8; 4];
+	let mut filled = 0;
+
+	while filled < len.len() {
+		match src.read(&mut len[filled..]) {
+			Ok(0) if filled == 0 => return Ok(None),
+			Ok(0) => return Err(bad_layout(std::io::ErrorKind::UnexpectedEof.into())),
+			Ok(n) => filled += n,
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(io_err(e)),
+		}
+	}
+
+	let len = u32::from_be_bytes(len) as usize;
+
+	if len > MAX_RECORD {
+		return Err(bad_layout(std::io::ErrorKind::InvalidData.into()));
+	}
+
+	let mut bytes = vec![0u8; len];
+
+	src.read_exact(&mut bytes).map_err(|e| match e.kind() {
+		std::io::ErrorKind::UnexpectedEof => bad_layout(e),
+		_ => io_err(e),
+	})?;
+
+	Ok(Some(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use futures::executor::block_on;
+
+	use super::{open, Chunk, Kind, Record, MAX_CHUNK};
+	use crate::{
+		backend::Backend,
+		error::Stage,
+		fixtures::{login, register_god},
+		id::Uid,
+		protocol::{Error, Protocol},
+		seeds::ROOT_ID,
+		wire::{self, Format},
+	};
+
+	// a tenant of its own
+	fn god_of(backend: &Backend) -> Protocol {
+		login(backend, &register_god(backend))
+	}
+
+	#[test]
+	fn test_export_import() {
+		let (src, dst) = (Backend::new(), Backend::new());
+		let mut protocol = god_of(&src);
+		let docs = block_on(protocol.mkdir(Uid::new(ROOT_ID), "docs")).unwrap();
+		let drafts = block_on(protocol.mkdir(docs.id(), "drafts")).unwrap();
+		let content = b"the quick brown fox".to_vec();
+		let fox = block_on(protocol.upload_file(
+			drafts.id(),
+			"fox",
+			"txt",
+			content.len() as u64,
+			&mut content.as_slice(),
+		))
+		.unwrap();
+		let mut exported = Vec::new();
+
+		block_on(protocol.export(docs.id(), "hold", &mut exported)).unwrap();
+
+		let mut other = god_of(&dst);
+		let target = Uid::new(ROOT_ID);
+
+		assert!(matches!(
+			block_on(other.import(&mut Cursor::new(&exported), "wrong", target)),
+			Err(Error::Locked {
+				stage: Stage::Decrypt,
+				..
+			})
+		));
+
+		let imported = block_on(other.import(&mut Cursor::new(&exported), "hold", target)).unwrap();
+
+		assert_eq!(imported.name(), "docs");
+		assert_ne!(imported.id(), docs.id());
+
+		let items = block_on(other.cd_to_dir(&imported.id())).unwrap().items();
+
+		assert_eq!(items.len(), 1);
+		assert_eq!(items[0].name(), "drafts");
+
+		let files = block_on(other.cd_to_dir(&items[0].id())).unwrap().items();
+
+		assert_eq!(files.len(), 1);
+		assert_ne!(files[0].id(), fox.id());
+		assert_eq!(files[0].ext(), Some("txt".to_string()));
+		assert_eq!(
+			block_on(other.download_chunk(files[0].id(), 0)).unwrap(),
+			content
+		);
+		assert!(other.integrity_report().is_clean());
+
+		// no keys, just the names and contents
+		let mut src = exported.as_slice();
+		let mut archive = open(&mut src, "hold", target).unwrap();
+		let mut records = Vec::new();
+
+		loop {
+			match archive.next().unwrap() {
+				Record::End => break,
+				record => records.push(record),
+			}
+		}
+
+		assert_eq!(records.len(), 4);
+		assert!(records.iter().any(|record| matches!(
+			record,
+			Record::Item(item) if item.kind == Kind::File {
+				ext: "txt".to_string(),
+				size: content.len() as u64,
+			}
+		)));
+		assert!(records.contains(&Record::Chunk(Chunk(content.clone()))));
+
+		// a tampered archive is caught before anything is imported
+		let mut tampered = exported.clone();
+		let last = tampered.len() - 2;
+
+		tampered[last] ^= 1;
+
+		assert!(matches!(
+			block_on(other.import(&mut Cursor::new(&tampered), "hold", target)),
+			Err(Error::Locked {
+				stage: Stage::Decrypt,
+				..
+			})
+		));
+		assert_eq!(block_on(other.cd_to_dir(&target)).unwrap().items().len(), 1);
+	}
+
+	#[test]
+	fn test_truncated() {
+		let backend = Backend::new();
+		let mut protocol = god_of(&backend);
+		let docs = block_on(protocol.mkdir(Uid::new(ROOT_ID), "docs")).unwrap();
+		// a few records' worth
+		let content = vec![7u8; MAX_CHUNK * 2 + 1];
+
+		block_on(protocol.upload_file(
+			docs.id(),
+			"big",
+			"bin",
+			content.len() as u64,
+			&mut content.as_slice(),
+		))
+		.unwrap();
+
+		let mut exported = Vec::new();
+
+		block_on(protocol.export(docs.id(), "hold", &mut exported)).unwrap();
+
+		let target = Uid::new(ROOT_ID);
+		let mut archive = exported.as_slice();
+		let mut archive = open(&mut archive, "hold", target).unwrap();
+
+		assert!(matches!(archive.next_item(), Ok(Some(_))));
+		assert!(matches!(archive.next_item(), Ok(Some(_))));
+
+		for chunk in content.chunks(MAX_CHUNK) {
+			assert_eq!(
+				archive.next().unwrap(),
+				Record::Chunk(Chunk(chunk.to_vec()))
+			);
+		}
+
+		assert_eq!(archive.next().unwrap(), Record::End);
+
+		// whether cut within a record or right before the end, nothing is imported
+		let end = 4 + wire::encode(&Record::End, Format::Cbor).len() + 16;
+
+		for len in [exported.len() - 1, exported.len() - end, exported.len() / 2] {
+			assert!(matches!(
+				block_on(protocol.import(&mut Cursor::new(&exported[..len]), "hold", target)),
+				Err(Error::Locked {
+					stage: Stage::Decode,
+					..
+				})
+			));
+		}
+
+		assert_eq!(
+			block_on(protocol.cd_to_dir(&target)).unwrap().items().len(),
+			1
+		);
+	}
+}
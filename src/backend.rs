@@ -0,0 +1,811 @@
+// a reference, in-process implementation of the server side: stores locked users, nodes, shares and
+// file chunks as is (it can't decrypt any of them) and serves them back through Network; useful for
+// end-to-end tests and local demos
+//
+// everything is kept in memory and, if opened with a path, written through to a single file after
+// each change
+
+use std::{
+	cell::{Cell, RefCell},
+	collections::{BTreeMap, HashMap, HashSet},
+	path::{Path, PathBuf},
+	rc::Rc,
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	base64_blobs::{deserialize_vec_base64, serialize_vec_base64},
+	hmac,
+	id::Uid,
+	identity,
+	protocol::{Error, Network},
+	register::{LockedUser, Migrated},
+	seeds::{FinishInviteIntent, InviteIntent, LockedShare, ROOT_ID},
+	sync::{ChangeLog, Changes, Cursor},
+	user::GOD_ID,
+	vault::{self, LockedNode},
+	wire::{self, Format},
+};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+struct Chunk(
+	#[serde(
+		serialize_with = "serialize_vec_base64",
+		deserialize_with = "deserialize_vec_base64"
+	)]
+	Vec<u8>,
+);
+
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+struct Store {
+	// as registered, but with no roots, shares and intents, which are kept below
+	users: HashMap<Uid, LockedUser>,
+	// the latest version of each node
+	nodes: HashMap<Uid, LockedNode>,
+	shares: Vec<LockedShare>,
+	chunks: HashMap<Uid, BTreeMap<u64, Chunk>>,
+	digests: HashMap<Uid, Vec<hmac::Digest>>,
+	intents: Vec<InviteIntent>,
+	changes: ChangeLog,
+}
+
+// cheap to clone: clones share the same store, eg a few clients and a test inspecting it
+#[derive(Clone, Default)]
+pub struct Backend {
+	store: Rc<RefCell<Store>>,
+	path: Option<Rc<PathBuf>>,
+	// simulates a lost connection, eg to demo offline mode
+	offline: Rc<Cell<bool>>,
+	// the number of nodes to save before going offline, see set_offline_after
+	saves_left: Rc<Cell<Option<usize>>>,
+}
+
+impl Backend {
+	// in memory only
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	// loads path, if it exists, and keeps it up to date
+	pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+		let path = path.as_ref().to_path_buf();
+		let store = match std::fs::read(&path) {
+			Ok(bytes) => wire::decode(&bytes).map_err(Error::BadEncoding)?,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => Store::default(),
+			Err(e) => return Err(Error::Io(e.to_string())),
+		};
+
+		Ok(Self {
+			store: Rc::new(RefCell::new(store)),
+			path: Some(Rc::new(path)),
+			offline: Rc::new(Cell::new(false)),
+			saves_left: Rc::new(Cell::new(None)),
+		})
+	}
+
+	// while set, all Network calls fail with NoNetwork
+	pub fn set_offline(&self, offline: bool) {
+		self.offline.set(offline);
+	}
+
+	// goes offline once n more nodes are saved, eg to cut a batch off between its nodes and their
+	// listing
+	pub fn set_offline_after(&self, n: usize) {
+		self.saves_left.set(Some(n));
+	}
+
+	fn check_online(&self) -> Result<(), Error> {
+		if self.offline.get() {
+			Err(Error::NoNetwork("offline".to_string()))
+		} else {
+			Ok(())
+		}
+	}
+
+	// through a temp file renamed over path, so that a crash half way leaves the previous store
+	fn persist(&self) -> Result<(), Error> {
+		let Some(ref path) = self.path else {
+			return Ok(());
+		};
+		let mut tmp = path.as_os_str().to_owned();
+		tmp.push(".tmp");
+
+		std::fs::write(&tmp, wire::encode(&*self.store.borrow(), Format::Cbor))
+			.and_then(|_| std::fs::rename(&tmp, path.as_path()))
+			.map_err(|e| Error::Io(e.to_string()))
+	}
+
+	fn update<T>(&self, f: impl FnOnce(&mut Store) -> Result<T, Error>) -> Result<T, Error> {
+		let res = f(&mut self.store.borrow_mut())?;
+
+		self.persist()?;
+
+		Ok(res)
+	}
+
+	pub fn register(&self, user: LockedUser) -> Result<(), Error> {
+		let id = user._pub.id();
+
+		self.update(|store| {
+			if store.users.contains_key(&id) {
+				return Err(Error::BadOperation {
+					id,
+					reason: "already registered",
+				});
+			}
+
+			let LockedUser {
+				encrypted_priv,
+				_pub,
+				shares,
+				pending_invite_intents,
+				roots,
+				cursor: _,
+			} = user;
+
+			for node in roots {
+				store.changes.log(node.id);
+				store.nodes.insert(node.id, node);
+			}
+			store.shares.extend(shares);
+			store.intents.extend(pending_invite_intents);
+			store.users.insert(
+				id,
+				LockedUser {
+					encrypted_priv,
+					_pub,
+					shares: Vec::new(),
+					pending_invite_intents: Vec::new(),
+					roots: Vec::new(),
+					cursor: Cursor::default(),
+				},
+			);
+
+			Ok(())
+		})
+	}
+
+	// an invitee signing up with ref_src acknowledges the intent, so that its sender can finish it
+	pub fn ack_invite_intent(
+		&self,
+		ref_src: &str,
+		receiver: identity::Public,
+	) -> Result<(), Error> {
+		self.update(|store| {
+			let intent = store
+				.intents
+				.iter_mut()
+				.find(|i| i.ref_src == ref_src)
+				.ok_or(Error::NotFound(receiver.id()))?;
+
+			if intent.user_id != receiver.id() {
+				return Err(Error::NoAccess(receiver.id()));
+			}
+
+			intent.receiver = Some(receiver);
+
+			Ok(())
+		})
+	}
+
+	// what a user needs to unlock: its shares, both sent and received, and the subtrees shared with
+	// it; god gets the whole tree
+	pub fn locked_user(&self, id: Uid) -> Option<LockedUser> {
+		let store = self.store.borrow();
+		let user = store.users.get(&id)?;
+		let shares: Vec<LockedShare> = store
+			.shares
+			.iter()
+			.filter(|s| s.export.receiver == id || s.sender.id() == id)
+			.cloned()
+			.collect();
+		let root_ids: Vec<Uid> = if id == GOD_ID {
+			vec![Uid::new(ROOT_ID)]
+		} else {
+			shares
+				.iter()
+				.filter(|s| s.export.receiver == id)
+				.flat_map(|s| s.export.fs.iter().cloned())
+				.collect()
+		};
+		let mut seen = HashSet::new();
+		let roots = root_ids
+			.into_iter()
+			.flat_map(|id| vault::locked_subtree(&store.nodes, id))
+			.filter(|node| seen.insert(node.id))
+			.collect();
+
+		let pending_invite_intents = store
+			.intents
+			.iter()
+			.filter(|i| i.sender.id() == id)
+			.cloned()
+			.collect();
+
+		Some(LockedUser {
+			shares,
+			pending_invite_intents,
+			roots,
+			cursor: store.changes.cursor(),
+			..user.clone()
+		})
+	}
+
+	pub fn node(&self, id: Uid) -> Option<LockedNode> {
+		self.store.borrow().nodes.get(&id).cloned()
+	}
+}
+
+#[async_trait(?Send)]
+impl Network for Backend {
+	async fn fetch_subtree(&self, id: Uid) -> Result<Vec<LockedNode>, Error> {
+		self.check_online()?;
+
+		let mut subtree = vault::locked_subtree(&self.store.borrow().nodes, id);
+		// children only
+		subtree.retain(|node| node.id != id);
+
+		Ok(subtree)
+	}
+
+	async fn upload_chunk(&self, file_id: Uid, chunk_idx: u64, ct: &[u8]) -> Result<(), Error> {
+		self.check_online()?;
+
+		self.update(|store| {
+			store
+				.chunks
+				.entry(file_id)
+				.or_default()
+				.insert(chunk_idx, Chunk(ct.to_vec()));
+
+			Ok(())
+		})
+	}
+
+	async fn upload_digests(&self, file_id: Uid, digests: &[hmac::Digest]) -> Result<(), Error> {
+		self.check_online()?;
+
+		self.update(|store| {
+			store.digests.insert(file_id, digests.to_vec());
+
+			Ok(())
+		})
+	}
+
+	async fn fetch_digests(&self, file_id: Uid) -> Result<Vec<hmac::Digest>, Error> {
+		self.check_online()?;
+
+		self.store
+			.borrow()
+			.digests
+			.get(&file_id)
+			.cloned()
+			.ok_or(Error::NotFound(file_id))
+	}
+
+	async fn fetch_chunk(&self, file_id: Uid, chunk_idx: u64) -> Result<Vec<u8>, Error> {
+		self.check_online()?;
+
+		self.store
+			.borrow()
+			.chunks
+			.get(&file_id)
+			.and_then(|chunks| chunks.get(&chunk_idx))
+			.map(|c| c.0.clone())
+			.ok_or(Error::NotFound(file_id))
+	}
+
+	async fn save_node(&self, node: &LockedNode, base: Option<&hmac::Digest>) -> Result<(), Error> {
+		self.check_online()?;
+
+		self.update(|store| {
+			match (store.nodes.get(&node.id), base) {
+				(Some(stored), base) if base != Some(&stored.hash()) => {
+					return Err(Error::Conflict {
+						id: node.id,
+						theirs: Box::new(stored.clone()),
+					})
+				}
+				(None, Some(_)) => return Err(Error::NotFound(node.id)),
+				_ => {}
+			}
+
+			store.changes.log(node.id);
+			store.nodes.insert(node.id, node.clone());
+
+			Ok(())
+		})?;
+
+		match self.saves_left.get() {
+			Some(0 | 1) => {
+				self.saves_left.set(None);
+				self.offline.set(true);
+			}
+			Some(n) => self.saves_left.set(Some(n - 1)),
+			None => {}
+		}
+
+		Ok(())
+	}
+
+	async fn copy_file(&self, from: Uid, to: Uid) -> Result<(), Error> {
+		self.check_online()?;
+
+		self.update(|store| {
+			let chunks = store
+				.chunks
+				.get(&from)
+				.cloned()
+				.ok_or(Error::NotFound(from))?;
+			let digests = store.digests.get(&from).cloned();
+
+			store.chunks.insert(to, chunks);
+
+			if let Some(digests) = digests {
+				store.digests.insert(to, digests);
+			}
+
+			Ok(())
+		})
+	}
+
+	async fn delete_node(&self, id: Uid) -> Result<(), Error> {
+		self.check_online()?;
+
+		self.update(|store| {
+			let subtree = vault::locked_subtree(&store.nodes, id);
+
+			if subtree.is_empty() {
+				return Err(Error::NotFound(id));
+			}
+
+			for node in subtree {
+				store.changes.log(node.id);
+				store.nodes.remove(&node.id);
+				store.chunks.remove(&node.id);
+				store.digests.remove(&node.id);
+			}
+
+			Ok(())
+		})
+	}
+
+	async fn fetch_changes(&self, since: Cursor) -> Result<Changes, Error> {
+		self.check_online()?;
+
+		let store = self.store.borrow();
+
+		store
+			.changes
+			.page(since, |id| Ok(store.nodes.get(&id).cloned()))
+	}
+
+	async fn upload_share(&self, share: &LockedShare) -> Result<(), Error> {
+		self.check_online()?;
+
+		self.update(|store| {
+			store.shares.push(share.clone());
+
+			Ok(())
+		})
+	}
+
+	async fn fetch_shares(&self, user_id: Uid) -> Result<Vec<LockedShare>, Error> {
+		self.check_online()?;
+
+		Ok(self
+			.store
+			.borrow()
+			.shares
+			.iter()
+			.filter(|s| s.export.receiver == user_id || s.sender.id() == user_id)
+			.cloned()
+			.collect())
+	}
+
+	async fn post_invite_intent(&self, intent: &InviteIntent) -> Result<(), Error> {
+		self.check_online()?;
+
+		self.update(|store| {
+			store.intents.retain(|i| i.ref_src != intent.ref_src);
+			store.intents.push(intent.clone());
+
+			Ok(())
+		})
+	}
+
+	async fn fetch_invite_intents(&self, sender_id: Uid) -> Result<Vec<InviteIntent>, Error> {
+		self.check_online()?;
+
+		Ok(self
+			.store
+			.borrow()
+			.intents
+			.iter()
+			.filter(|i| i.sender.id() == sender_id)
+			.cloned()
+			.collect())
+	}
+
+	async fn finish_invite_intent(&self, finish: &FinishInviteIntent) -> Result<(), Error> {
+		self.check_online()?;
+
+		self.update(|store| {
+			let idx = store
+				.intents
+				.iter()
+				.position(|i| i.ref_src == finish.ref_src && i.receiver.is_some())
+				.ok_or(Error::NotFound(finish.share.export.receiver))?;
+
+			store.intents.remove(idx);
+			store.shares.push(finish.share.clone());
+
+			Ok(())
+		})
+	}
+
+	async fn save_migrated(&self, migrated: &Migrated) -> Result<(), Error> {
+		self.check_online()?;
+
+		self.update(|store| {
+			let user = store
+				.users
+				.get_mut(&migrated.user_id)
+				.ok_or(Error::NotFound(migrated.user_id))?;
+
+			if let Some(ref lock) = migrated.encrypted_priv {
+				user.encrypted_priv = Some(lock.clone());
+			}
+
+			for share in store.shares.iter_mut() {
+				if let Some(new) = migrated.share_for(share) {
+					*share = new.clone();
+				}
+			}
+
+			Ok(())
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use futures::executor::block_on;
+
+	use super::Backend;
+	use crate::{
+		fixtures::{locked_user, login, register},
+		id::Uid,
+		identity::Identity,
+		protocol::{Error, Network, Protocol},
+		seeds::{Bundle, LockedShare, ROOT_ID},
+		user::{User, GOD_ID},
+		vault::{FileSystem, Resolution},
+	};
+
+	#[test]
+	fn test_upload_and_download() {
+		let backend = Backend::new();
+		let god = Identity::generate(Uid::new(GOD_ID));
+		let (_, root) = FileSystem::new(&User::fs_seed(god.private()), &god);
+
+		register(&backend, &god, vec![root]);
+
+		let content = b"the quick brown fox".to_vec();
+		let view = block_on(login(&backend, &god).upload_file(
+			Uid::new(ROOT_ID),
+			"fox",
+			"txt",
+			content.len() as u64,
+			&mut content.as_slice(),
+		))
+		.unwrap();
+
+		// another device of the same user
+		let mut protocol = login(&backend, &god);
+		let items = block_on(protocol.ls_cur_mut()).unwrap().items();
+		let ct = block_on(backend.fetch_chunk(view.id(), 0)).unwrap();
+
+		assert!(protocol.integrity_report().is_clean());
+		assert_eq!(items.len(), 1);
+		assert_eq!(items[0].name(), "fox");
+		assert_eq!(
+			block_on(protocol.chunk_decrypt_for_file(&ct, &view.id(), 0)).unwrap(),
+			content
+		);
+		assert!(protocol.is_fully_verified(&view.id()));
+		assert!(backend.register(locked_user(&god, Vec::new())).is_err());
+	}
+
+	#[test]
+	fn test_share_subtree() {
+		let backend = Backend::new();
+		let god = Identity::generate(Uid::new(GOD_ID));
+		let admin = Identity::generate(Uid::new(1));
+		let (mut fs, root) = FileSystem::new(&User::fs_seed(god.private()), &god);
+		let (docs, _) = fs.mkdir_mut(Uid::new(ROOT_ID), "docs", &god).unwrap();
+		let (_, locked_file) = fs.touch_mut(docs, 0, "a", "txt", &god).unwrap();
+		let (_, locked_private) = fs.mkdir_mut(Uid::new(ROOT_ID), "private", &god).unwrap();
+		// with its file listed
+		let locked_docs = fs.list(docs, &god).unwrap();
+
+		register(&backend, &god, vec![root]);
+		register(&backend, &admin, Vec::new());
+
+		for node in [locked_docs, locked_file, locked_private] {
+			block_on(backend.save_node(&node, None)).unwrap();
+		}
+
+		let mut bundle = Bundle::new();
+		bundle.set_fs(docs, fs.share_node(docs).unwrap());
+		block_on(backend.upload_share(&LockedShare::new(&god, admin.public(), &bundle))).unwrap();
+
+		let locked = backend.locked_user(admin.id()).unwrap();
+		let mut protocol = login(&backend, &admin);
+		let items = block_on(protocol.ls_cur_mut()).unwrap().items();
+
+		assert_eq!(locked.shares.len(), 1);
+		assert_eq!(locked.roots.len(), 2);
+		assert_eq!(items.len(), 1);
+		assert_eq!(items[0].name(), "docs");
+		assert_eq!(
+			block_on(protocol.cd_to_dir(&docs)).unwrap().items().len(),
+			1
+		);
+		assert_eq!(backend.locked_user(god.id()).unwrap().roots.len(), 4);
+		assert!(backend.locked_user(Uid::new(2)).is_none());
+	}
+
+	#[test]
+	fn test_invite() {
+		let backend = Backend::new();
+		let god = Identity::generate(Uid::new(GOD_ID));
+		let admin = Identity::generate(Uid::new(1));
+		let (_, root) = FileSystem::new(&User::fs_seed(god.private()), &god);
+
+		register(&backend, &god, vec![root]);
+
+		let mut protocol = login(&backend, &god);
+		let docs = block_on(protocol.mkdir(Uid::new(ROOT_ID), "docs")).unwrap();
+
+		block_on(protocol.mkdir(docs.id(), "drafts")).unwrap();
+		block_on(protocol.invite("admin@vault", admin.id(), Some(vec![docs.id()]))).unwrap();
+
+		// not acknowledged yet
+		assert_eq!(block_on(protocol.finish_invites()), Ok(0));
+		assert_eq!(
+			backend
+				.locked_user(god.id())
+				.unwrap()
+				.pending_invite_intents
+				.len(),
+			1
+		);
+
+		register(&backend, &admin, Vec::new());
+		backend
+			.ack_invite_intent("admin@vault", admin.public().clone())
+			.unwrap();
+
+		assert_eq!(block_on(protocol.finish_invites()), Ok(1));
+		assert!(backend
+			.locked_user(god.id())
+			.unwrap()
+			.pending_invite_intents
+			.is_empty());
+
+		let mut protocol = login(&backend, &admin);
+		let items = block_on(protocol.ls_cur_mut()).unwrap().items();
+
+		assert!(protocol.integrity_report().is_clean());
+		assert_eq!(items.len(), 1);
+		assert_eq!(items[0].name(), "docs");
+		assert_eq!(
+			block_on(protocol.cd_to_dir(&docs.id()))
+				.unwrap()
+				.items()
+				.len(),
+			1
+		);
+	}
+
+	#[test]
+	fn test_sync() {
+		let backend = Backend::new();
+		let god = Identity::generate(Uid::new(GOD_ID));
+		let (_, root) = FileSystem::new(&User::fs_seed(god.private()), &god);
+
+		register(&backend, &god, vec![root]);
+
+		let mut laptop = login(&backend, &god);
+		let mut phone = login(&backend, &god);
+		let docs = block_on(laptop.mkdir(Uid::new(ROOT_ID), "docs")).unwrap();
+
+		block_on(laptop.mkdir(docs.id(), "drafts")).unwrap();
+
+		// the dirs, listed ones included
+		assert_eq!(block_on(phone.sync()), Ok(3));
+		assert_eq!(
+			phone.cursor(),
+			backend.locked_user(god.id()).unwrap().cursor
+		);
+		assert_eq!(block_on(phone.sync()), Ok(0));
+
+		let items = block_on(phone.cd_to_dir(&docs.id())).unwrap().items();
+
+		assert!(phone.integrity_report().is_clean());
+		assert_eq!(items.len(), 1);
+		assert_eq!(items[0].name(), "drafts");
+
+		block_on(laptop.delete(docs.id())).unwrap();
+
+		// docs, drafts and root, listed again
+		assert_eq!(block_on(phone.sync()), Ok(3));
+		assert!(block_on(phone.ls_cur_mut()).unwrap().items().is_empty());
+		assert!(phone.integrity_report().is_clean());
+	}
+
+	#[test]
+	fn test_conflicts() {
+		let backend = Backend::new();
+		let god = Identity::generate(Uid::new(GOD_ID));
+		let (_, root) = FileSystem::new(&User::fs_seed(god.private()), &god);
+
+		register(&backend, &god, vec![root]);
+
+		let mut laptop = login(&backend, &god);
+		let docs = block_on(laptop.mkdir(Uid::new(ROOT_ID), "docs")).unwrap();
+		let content = b"minutes".to_vec();
+		let file = block_on(laptop.upload_file(
+			docs.id(),
+			"minutes",
+			"txt",
+			content.len() as u64,
+			&mut content.as_slice(),
+		))
+		.unwrap();
+		let mut phone = login(&backend, &god);
+		let names = |protocol: &mut Protocol, id: Uid| {
+			let mut names: Vec<String> = block_on(protocol.cd_to_dir(&id))
+				.unwrap()
+				.items()
+				.iter()
+				.map(|n| n.name())
+				.collect();
+			names.sort();
+
+			names
+		};
+
+		block_on(laptop.rename(docs.id(), "papers")).unwrap();
+
+		assert!(matches!(
+			block_on(phone.rename(docs.id(), "notes")),
+			Err(Error::Conflict { id, .. }) if id == docs.id()
+		));
+		assert_eq!(phone.conflicts(), [docs.id()]);
+		// the edit is undone until resolved
+		assert_eq!(names(&mut phone, Uid::new(ROOT_ID)), ["docs"]);
+		assert!(matches!(
+			block_on(phone.resolve(docs.id(), Resolution::Both)),
+			Err(Error::BadOperation { .. })
+		));
+
+		block_on(phone.resolve(docs.id(), Resolution::Mine)).unwrap();
+		block_on(laptop.sync()).unwrap();
+
+		assert!(phone.conflicts().is_empty());
+		assert_eq!(names(&mut phone, Uid::new(ROOT_ID)), ["notes"]);
+		assert_eq!(names(&mut laptop, Uid::new(ROOT_ID)), ["notes"]);
+
+		block_on(laptop.rename(file.id(), "agenda")).unwrap();
+
+		assert!(block_on(phone.rename(file.id(), "summary")).is_err());
+
+		block_on(phone.resolve(file.id(), Resolution::Theirs)).unwrap();
+
+		assert_eq!(names(&mut phone, docs.id()), ["agenda"]);
+
+		block_on(laptop.rename(file.id(), "report")).unwrap();
+
+		assert!(block_on(phone.rename(file.id(), "summary")).is_err());
+
+		block_on(phone.resolve(file.id(), Resolution::Both)).unwrap();
+		block_on(laptop.sync()).unwrap();
+
+		let copy = block_on(laptop.cd_to_dir(&docs.id()))
+			.unwrap()
+			.items()
+			.into_iter()
+			.find(|n| n.id() != file.id())
+			.unwrap();
+
+		assert_eq!(
+			names(&mut laptop, docs.id()),
+			["report", "summary (conflict)"]
+		);
+		assert_eq!(block_on(laptop.download_chunk(copy.id(), 0)), Ok(content));
+		assert!(laptop.integrity_report().is_clean());
+		assert!(phone.integrity_report().is_clean());
+	}
+
+	#[test]
+	fn test_resolve_mine_keeps_children_added_since() {
+		let backend = Backend::new();
+		let god = Identity::generate(Uid::new(GOD_ID));
+		let (_, root) = FileSystem::new(&User::fs_seed(god.private()), &god);
+
+		register(&backend, &god, vec![root]);
+
+		let mut laptop = login(&backend, &god);
+		let docs = block_on(laptop.mkdir(Uid::new(ROOT_ID), "docs")).unwrap();
+		let mut phone = login(&backend, &god);
+
+		// loaded while still empty
+		assert!(block_on(phone.cd_to_dir(&docs.id()))
+			.unwrap()
+			.items()
+			.is_empty());
+
+		block_on(laptop.mkdir(docs.id(), "drafts")).unwrap();
+
+		assert!(block_on(phone.rename(docs.id(), "notes")).is_err());
+
+		block_on(phone.resolve(docs.id(), Resolution::Mine)).unwrap();
+
+		let mut tablet = login(&backend, &god);
+		let items = block_on(tablet.cd_to_dir(&docs.id())).unwrap().items();
+
+		assert_eq!(items.len(), 1);
+		assert_eq!(items[0].name(), "drafts");
+		assert!(tablet.integrity_report().is_clean());
+		assert_eq!(
+			block_on(tablet.cd_to_dir(&Uid::new(ROOT_ID)))
+				.unwrap()
+				.items()[0]
+				.name(),
+			"notes"
+		);
+		assert_eq!(
+			block_on(phone.cd_to_dir(&docs.id())).unwrap().items().len(),
+			1
+		);
+	}
+
+	#[test]
+	fn test_persistence() {
+		let path = std::env::temp_dir().join(format!("qvault-{}.cbor", Uid::generate()));
+		let god = Identity::generate(Uid::new(GOD_ID));
+		let (_, root) = FileSystem::new(&User::fs_seed(god.private()), &god);
+		let backend = Backend::open(&path).unwrap();
+
+		register(&backend, &god, vec![root]);
+		block_on(backend.upload_chunk(Uid::new(7), 0, b"chunk")).unwrap();
+
+		let reopened = Backend::open(&path).unwrap();
+
+		assert_eq!(
+			reopened.locked_user(god.id()),
+			backend.locked_user(god.id())
+		);
+		assert_eq!(
+			block_on(reopened.fetch_chunk(Uid::new(7), 0)),
+			Ok(b"chunk".to_vec())
+		);
+		assert_eq!(
+			block_on(reopened.fetch_digests(Uid::new(7))),
+			Err(Error::NotFound(Uid::new(7)))
+		);
+		// renamed over path once written
+		assert!(!path.with_extension("cbor.tmp").exists());
+
+		std::fs::remove_file(&path).unwrap();
+		std::fs::write(&path, b"not a store").unwrap();
+
+		assert!(matches!(Backend::open(&path), Err(Error::BadEncoding(_))));
+
+		std::fs::remove_file(&path).unwrap();
+	}
+}
@@ -3,6 +3,9 @@ use serde::de::Visitor;
 use serde::de::{Error, Unexpected};
 use serde::{Deserializer, Serializer};
 
+// blobs are base64 encoded strings in human readable formats (json) and raw bytes in binary ones
+// (cbor); see wire
+
 // A wrapper for blob serialization as a base64 encoded string.
 pub struct Base64BlobRef<'a>(&'a [u8]);
 impl<'a> From<&'a [u8]> for Base64BlobRef<'a> {
@@ -37,6 +40,20 @@ impl<'de> Visitor<'de> for Base64Visitor {
 			},
 		}
 	}
+
+	fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+	where
+		E: serde::de::Error,
+	{
+		Ok(v.to_vec())
+	}
+
+	fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+	where
+		E: serde::de::Error,
+	{
+		Ok(v)
+	}
 }
 
 impl<'de> Visitor<'de> for OptionalBase64Visitor {
@@ -50,7 +67,7 @@ impl<'de> Visitor<'de> for OptionalBase64Visitor {
 	where
 		D: Deserializer<'de>,
 	{
-		let result = deserializer.deserialize_str(Base64Visitor {});
+		let result = deserialize_vec_base64(deserializer);
 		result.map(|value| Some(value))
 	}
 
@@ -64,7 +81,15 @@ impl<'de> Visitor<'de> for OptionalBase64Visitor {
 
 impl serde::Serialize for Base64BlobRef<'_> {
 	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-		serializer.serialize_str(&base64::encode(self.0))
+		serialize_slice_base64(self.0, serializer)
+	}
+}
+
+fn serialize_slice_base64<S: Serializer>(blob: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+	if serializer.is_human_readable() {
+		serializer.serialize_str(&base64::encode(blob))
+	} else {
+		serializer.serialize_bytes(blob)
 	}
 }
 
@@ -89,14 +114,15 @@ pub fn deserialize_vec_optional_base64<'de, D: Deserializer<'de>>(
 pub fn deserialize_vec_base64<'de, D: Deserializer<'de>>(
 	deserializer: D,
 ) -> Result<Vec<u8>, D::Error> {
-	deserializer.deserialize_str(Base64Visitor {})
+	if deserializer.is_human_readable() {
+		deserializer.deserialize_str(Base64Visitor {})
+	} else {
+		deserializer.deserialize_byte_buf(Base64Visitor {})
+	}
 }
 
-pub fn serialize_vec_base64<S: Serializer>(
-	blob: &Vec<u8>,
-	serializer: S,
-) -> Result<S::Ok, S::Error> {
-	serializer.serialize_str(base64::encode(blob.as_slice()).as_str())
+pub fn serialize_vec_base64<S: Serializer>(blob: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+	serialize_slice_base64(blob, serializer)
 }
 
 pub fn deserialize_array_base64<'de, D, const N: usize>(
@@ -119,19 +145,31 @@ where
 			E: serde::de::Error,
 		{
 			let decoded = base64::decode(v).map_err(E::custom)?;
+
+			self.visit_bytes(&decoded)
+		}
+
+		fn visit_bytes<E>(self, v: &[u8]) -> Result<[u8; N], E>
+		where
+			E: serde::de::Error,
+		{
 			let mut array = [0u8; N];
 
-			if decoded.len() != N {
+			if v.len() != N {
 				return Err(E::custom(format!("expected a byte array of length {}", N)));
 			}
 
-			array.copy_from_slice(&decoded);
+			array.copy_from_slice(v);
 
 			Ok(array)
 		}
 	}
 
-	deserializer.deserialize_str(Base64Visitor::<N>)
+	if deserializer.is_human_readable() {
+		deserializer.deserialize_str(Base64Visitor::<N>)
+	} else {
+		deserializer.deserialize_bytes(Base64Visitor::<N>)
+	}
 }
 
 pub fn serialize_array_base64<S, const N: usize>(
@@ -141,7 +179,7 @@ pub fn serialize_array_base64<S, const N: usize>(
 where
 	S: Serializer,
 {
-	serializer.serialize_str(&base64::encode(blob))
+	serialize_slice_base64(blob, serializer)
 }
 
 #[cfg(test)]
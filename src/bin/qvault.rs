@@ -0,0 +1,10 @@
+use std::io;
+
+fn main() {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+
+	if let Err(e) = qvault::cli::run(&args, &mut io::stdin().lock(), &mut io::stdout().lock()) {
+		eprintln!("qvault: {}", e);
+		std::process::exit(1);
+	}
+}
@@ -0,0 +1,459 @@
+// bulk transfers between local dir trees and the vault
+//
+// onboarding: files are encrypted and uploaded chunk by chunk, while their locked nodes are
+// batched, which Protocol::import_dir commits a dir (or BATCH_SIZE bytes) at a time, relisting it; a
+// Journal of what's been committed lets an interrupted import resume where it stopped
+//
+// downloads: Protocol::download_dir writes a whole subtree, decrypted and verified, and reports the
+// files it failed to write instead of giving up on the first one
+
+use std::{
+	collections::BTreeMap,
+	fs::{self, File},
+	path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	id::Uid,
+	identity, merkle,
+	protocol::{read_chunk, Error, Network},
+	vault::{Entry, FileSystem, LockedNode, NewNodeReq, Node},
+};
+
+// a batch is committed once this much content is uploaded, or its dir is done
+pub(crate) const BATCH_SIZE: u64 = 16 << 20;
+
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Progress {
+	// the latest file or dir handled
+	pub path: PathBuf,
+	pub files: u64,
+	pub total_files: u64,
+	pub bytes: u64,
+	pub total_bytes: u64,
+}
+
+// local paths, relative to the imported dir, which are committed already, and their node ids; keep
+// it (eg on each progress report) to resume an interrupted import
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+pub struct Journal {
+	pub done: BTreeMap<String, Uid>,
+	// (dir id, node id) by local path for nodes which may be saved, but not listed yet; a resumed
+	// import moves them to done, if their dir lists them after all, and deletes them otherwise
+	#[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+	pub pending: BTreeMap<String, (Uid, Uid)>,
+}
+
+// what Protocol::download_dir has written, and what it failed to, by local path
+#[derive(Default, Debug)]
+pub struct Download {
+	pub progress: Progress,
+	pub failed: Vec<(PathBuf, Error)>,
+}
+
+// nodes whose contents, if any, are uploaded already
+#[derive(Default)]
+pub(crate) struct Batch {
+	// in the order of upload, with their relative paths
+	pub nodes: Vec<(String, Node, LockedNode)>,
+	pub bytes: u64,
+}
+
+impl Batch {
+	// returns the file's size
+	pub fn push(&mut self, rel: String, (node, locked_node): (Node, LockedNode)) -> u64 {
+		let size = match node.entry {
+			Entry::File { ref info } => info.size,
+			Entry::Dir { .. } => 0,
+		};
+
+		self.bytes += size;
+		self.nodes.push((rel, node, locked_node));
+
+		size
+	}
+}
+
+pub(crate) fn split_ext(file_name: &str) -> (&str, &str) {
+	match file_name.rsplit_once('.') {
+		Some((name, ext)) if !name.is_empty() => (name, ext),
+		_ => (file_name, ""),
+	}
+}
+
+// name.ext, unless it can't be a single path component, eg "..", or a/b
+pub(crate) fn local_name(node: &Node) -> Option<String> {
+	let name = match node.entry {
+		Entry::File { ref info } if !info.ext.is_empty() => format!("{}.{}", node.name, info.ext),
+		_ => node.name.clone(),
+	};
+
+	if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+		None
+	} else {
+		Some(name)
+	}
+}
+
+// files and dirs only, by name; anything else, eg symlinks, is skipped
+pub(crate) fn entries(dir: &Path) -> Result<Vec<(String, PathBuf, bool)>, Error> {
+	let io = |e: std::io::Error| Error::Io(format!("{}: {}", dir.display(), e));
+	let mut entries = Vec::new();
+
+	for entry in fs::read_dir(dir).map_err(io)? {
+		let entry = entry.map_err(io)?;
+		let file_type = entry.file_type().map_err(io)?;
+
+		if file_type.is_file() || file_type.is_dir() {
+			entries.push((
+				entry.file_name().to_string_lossy().into_owned(),
+				entry.path(),
+				file_type.is_dir(),
+			));
+		}
+	}
+	entries.sort();
+
+	Ok(entries)
+}
+
+// the number of files and their total size
+pub(crate) fn scan(dir: &Path) -> Result<(u64, u64), Error> {
+	let (mut files, mut bytes) = (0, 0);
+
+	for (_, path, is_dir) in entries(dir)? {
+		if is_dir {
+			let (f, b) = scan(&path)?;
+
+			files += f;
+			bytes += b;
+		} else {
+			files += 1;
+			bytes += fs::metadata(&path)
+				.map_err(|e| Error::Io(format!("{}: {}", path.display(), e)))?
+				.len();
+		}
+	}
+
+	Ok((files, bytes))
+}
+
+// touches a file in parent_id, and encrypts and uploads path a chunk at a time, like
+// Protocol::upload_file; the node is neither inserted nor saved yet
+pub(crate) async fn upload_file(
+	net: &dyn Network,
+	fs: &FileSystem,
+	parent_id: Uid,
+	path: &Path,
+	owner: &identity::Identity,
+) -> Result<(Node, LockedNode), Error> {
+	let io = |e: std::io::Error| Error::Io(format!("{}: {}", path.display(), e));
+	let mut src = File::open(path).map_err(io)?;
+	let size = src.metadata().map_err(io)?.len();
+	let file_name = path
+		.file_name()
+		.map(|n| n.to_string_lossy().into_owned())
+		.unwrap_or_default();
+	let (name, ext) = split_ext(&file_name);
+	let NewNodeReq { mut node, .. } = fs.touch(parent_id, size, name, ext, owner)?;
+	let Entry::File { ref mut info } = node.entry else {
+		return Err(Error::BadOperation {
+			id: node.id,
+			reason: "not a file",
+		});
+	};
+	let digest_key = info.digest_key();
+	let mut buf = vec![0u8; info.chunk_size() as usize];
+	let mut leaves = Vec::new();
+	let mut read_total = 0;
+
+	loop {
+		let read = read_chunk(&mut src, &mut buf)?;
+
+		// an empty file still gets one (empty) chunk to carry the auth tag
+		if read == 0 && !leaves.is_empty() {
+			break;
+		}
+
+		let idx = leaves.len() as u64;
+		let ct = info.key_iv.chunk_encrypt_async(idx, &buf[..read]).await;
+
+		net.upload_chunk(node.id, idx, &ct).await?;
+		leaves.push(merkle::leaf(&digest_key, idx, &buf[..read]));
+		read_total += read as u64;
+
+		if read < buf.len() {
+			break;
+		}
+	}
+
+	// changed while being read
+	if read_total != size {
+		return Err(Error::Io(format!("{}: size changed", path.display())));
+	}
+
+	info.digest = Some(merkle::root(&leaves));
+	net.upload_digests(node.id, &leaves).await?;
+
+	let locked_node = fs.lock(&node, owner)?;
+
+	Ok((node, locked_node))
+}
+
+#[cfg(test)]
+mod tests {
+	use std::cell::RefCell;
+
+	use futures::executor::block_on;
+
+	use super::{Journal, Progress};
+	use crate::{
+		backend::Backend,
+		fixtures::{login, register_god},
+		id::Uid,
+		protocol::Network,
+		seeds::ROOT_ID,
+	};
+
+	#[test]
+	fn test_import_dir_and_resume() {
+		let dir = std::env::temp_dir().join(format!("qvault-bulk-{}", Uid::generate()));
+
+		std::fs::create_dir_all(dir.join("docs/empty")).unwrap();
+		std::fs::write(dir.join("a.txt"), b"alpha").unwrap();
+		std::fs::write(dir.join("docs/b.tar.gz"), b"bravo!").unwrap();
+
+		let backend = Backend::new();
+		let god = register_god(&backend);
+
+		// cut off between saving a.txt and listing it
+		let mut journal = Journal::default();
+		let mut protocol = login(&backend, &god);
+
+		backend.set_offline_after(1);
+
+		assert!(block_on(protocol.import_dir(
+			&dir,
+			Uid::new(ROOT_ID),
+			&mut journal,
+			&mut |_, _| {},
+		))
+		.is_err());
+		assert!(journal.done.is_empty());
+
+		let (_, orphan) = journal.pending["a.txt"];
+		let saved = |id| {
+			block_on(backend.fetch_subtree(Uid::new(ROOT_ID)))
+				.unwrap()
+				.iter()
+				.any(|n| n.id == id)
+		};
+
+		backend.set_offline(false);
+
+		assert!(saved(orphan));
+
+		// interrupted once docs/ is created
+		let res = block_on(protocol.import_dir(
+			&dir,
+			Uid::new(ROOT_ID),
+			&mut journal,
+			&mut |p: &Progress, _: &Journal| {
+				if p.path.ends_with("docs") {
+					backend.set_offline(true);
+				}
+			},
+		));
+
+		assert!(res.is_err());
+		assert_eq!(journal.done.len(), 2);
+		assert!(journal.done.contains_key("a.txt"));
+
+		// resumed on another device
+		backend.set_offline(false);
+
+		// the orphan is gone, while b.tar.gz failed to upload before it was journaled
+		assert!(!saved(orphan));
+		assert!(!journal.pending.contains_key("a.txt"));
+		assert!(!journal.pending.contains_key("docs/b.tar.gz"));
+
+		let reports = RefCell::new(Vec::new());
+		let mut protocol = login(&backend, &god);
+		let progress = block_on(protocol.import_dir(
+			&dir,
+			Uid::new(ROOT_ID),
+			&mut journal,
+			&mut |p: &Progress, _: &Journal| reports.borrow_mut().push(p.clone()),
+		))
+		.unwrap();
+
+		assert_eq!((progress.files, progress.total_files), (2, 2));
+		assert_eq!((progress.bytes, progress.total_bytes), (11, 11));
+		assert_eq!(journal.done.len(), 4);
+		assert!(journal.pending.is_empty());
+		assert!(reports.borrow().iter().all(|p| !p.path.ends_with("a.txt")));
+
+		let items = block_on(protocol.ls_cur_mut()).unwrap().items();
+
+		assert_eq!(items.len(), 2);
+		assert!(protocol.integrity_report().is_clean());
+
+		let docs = journal.done["docs"];
+		let items = block_on(protocol.cd_to_dir(&docs)).unwrap().items();
+		let b = items.iter().find(|i| !i.is_dir()).unwrap();
+
+		assert_eq!(items.len(), 2);
+		assert_eq!(
+			(b.name(), b.ext()),
+			("b.tar".to_string(), Some("gz".to_string()))
+		);
+		assert_eq!(block_on(backend.fetch_digests(b.id())).unwrap().len(), 1);
+		assert_eq!(
+			block_on(protocol.download_chunk(b.id(), 0)).unwrap(),
+			b"bravo!"
+		);
+
+		// pending, but listed after all
+		let a = journal.done.remove("a.txt").unwrap();
+
+		journal
+			.pending
+			.insert("a.txt".to_string(), (Uid::new(ROOT_ID), a));
+		block_on(protocol.import_dir(&dir, Uid::new(ROOT_ID), &mut journal, &mut |_, _| {}))
+			.unwrap();
+
+		assert_eq!(journal.done["a.txt"], a);
+		assert!(saved(a));
+		assert_eq!(
+			block_on(protocol.cd_to_dir(&Uid::new(ROOT_ID)))
+				.unwrap()
+				.items()
+				.len(),
+			2
+		);
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_import_dir_resumes_dirs() {
+		let dir = std::env::temp_dir().join(format!("qvault-bulk-{}", Uid::generate()));
+
+		std::fs::create_dir_all(dir.join("docs")).unwrap();
+		std::fs::write(dir.join("docs/b"), b"bravo!").unwrap();
+
+		let backend = Backend::new();
+		let god = register_god(&backend);
+
+		// cut off between saving docs/ and listing it
+		let mut journal = Journal::default();
+		let mut protocol = login(&backend, &god);
+
+		backend.set_offline_after(1);
+
+		assert!(block_on(protocol.import_dir(
+			&dir,
+			Uid::new(ROOT_ID),
+			&mut journal,
+			&mut |_, _| {},
+		))
+		.is_err());
+		assert!(journal.done.is_empty());
+
+		let (_, orphan) = journal.pending["docs"];
+
+		backend.set_offline(false);
+
+		let mut protocol = login(&backend, &god);
+
+		block_on(protocol.import_dir(&dir, Uid::new(ROOT_ID), &mut journal, &mut |_, _| {}))
+			.unwrap();
+
+		// made once, rather than next to an orphan
+		let items = block_on(protocol.cd_to_dir(&Uid::new(ROOT_ID)))
+			.unwrap()
+			.items();
+		let nodes = block_on(backend.fetch_subtree(Uid::new(ROOT_ID))).unwrap();
+
+		assert_eq!(items.len(), 1);
+		assert_eq!(items[0].id(), journal.done["docs"]);
+		assert!(nodes.iter().all(|n| n.id != orphan));
+		assert_eq!(journal.done.len(), 2);
+		// once the orphan, rejected as unlisted on login, is gone
+		assert!(login(&backend, &god).integrity_report().is_clean());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_download_dir() {
+		let dir = std::env::temp_dir().join(format!("qvault-bulk-{}", Uid::generate()));
+		let src = dir.join("src");
+		let dst = dir.join("dst");
+
+		std::fs::create_dir_all(src.join("docs/empty")).unwrap();
+		std::fs::write(src.join("a.txt"), b"alpha").unwrap();
+		std::fs::write(src.join("docs/b"), b"bravo!").unwrap();
+		std::fs::write(src.join("docs/c.md"), b"").unwrap();
+
+		let backend = Backend::new();
+		let god = register_god(&backend);
+
+		let mut journal = Journal::default();
+
+		block_on(login(&backend, &god).import_dir(
+			&src,
+			Uid::new(ROOT_ID),
+			&mut journal,
+			&mut |_, _| {},
+		))
+		.unwrap();
+
+		// a.txt is tampered with on the server
+		block_on(backend.upload_chunk(journal.done["a.txt"], 0, b"garbage")).unwrap();
+
+		// a file which fails doesn't replace what's there already
+		std::fs::create_dir_all(&dst).unwrap();
+		std::fs::write(dst.join("a.txt"), b"old").unwrap();
+
+		let mut protocol = login(&backend, &god);
+		let mut reports = Vec::new();
+		let download = block_on(
+			protocol.download_dir(Uid::new(ROOT_ID), &dst, &mut |p| reports.push(p.clone())),
+		)
+		.unwrap();
+
+		// a chunk of b and one of c.md, then each file
+		assert_eq!(reports.len(), 5);
+		assert!(reports.windows(2).all(|r| r[0].bytes <= r[1].bytes));
+		assert_eq!(download.progress.files, 3);
+		assert_eq!(download.progress.total_bytes, 11);
+		assert_eq!(download.progress.bytes, 11);
+		assert_eq!(download.failed.len(), 1);
+		assert_eq!(download.failed[0].0, dst.join("a.txt"));
+		assert_eq!(std::fs::read(dst.join("a.txt")).unwrap(), b"old");
+		assert_eq!(std::fs::read(dst.join("docs/b")).unwrap(), b"bravo!");
+		assert!(std::fs::read(dst.join("docs/c.md")).unwrap().is_empty());
+		assert!(dst.join("docs/empty").is_dir());
+		// no temp files left behind
+		assert_eq!(std::fs::read_dir(dst.join("docs")).unwrap().count(), 3);
+		assert_eq!(std::fs::read_dir(&dst).unwrap().count(), 2);
+
+		// a single file replaces what's there once verified
+		let mut reports = 0;
+
+		block_on(
+			protocol.download_file(journal.done["docs/b"], &dst.join("a.txt"), &mut |_| {
+				reports += 1
+			}),
+		)
+		.unwrap();
+
+		assert_eq!(std::fs::read(dst.join("a.txt")).unwrap(), b"bravo!");
+		assert_eq!(reports, 2);
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
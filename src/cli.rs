@@ -0,0 +1,566 @@
+// the qvault command: scriptable vault maintenance on top of Protocol; see USAGE and src/bin
+
+use std::{
+	fmt,
+	fs::File,
+	io::{self, BufRead, IsTerminal, Write},
+	path::Path,
+	str::FromStr,
+};
+
+use futures::executor::block_on;
+
+use crate::{
+	backend::Backend,
+	bulk::split_ext,
+	dir_store::DirStore,
+	id::Uid,
+	identity::{self, Identity},
+	password_lock,
+	protocol::{self, Network, NodeView, Protocol},
+	register::LockedUser,
+	seeds::ROOT_ID,
+	sync::Cursor,
+	user::{User, GOD_ID},
+	vault::FileSystem,
+	wire::{self, Format},
+};
+
+pub const USAGE: &str = "usage:
+  qvault inspect <locked user file>
+  qvault --store <path> register [--god]
+  qvault --store <path> --user <id> <command>
+
+commands:
+  ls [path]
+  cd <path>                  within a shell only
+  mkdir <path>
+  put <local file> [dir]
+  get <path> <local path>    a dir is downloaded as a whole
+  share <path> <user id>
+  shell                      runs commands from stdin, one per line
+
+a store is a dir (see dir_store) if it exists as one or ends with /, and a single file otherwise;
+the password is taken from QVAULT_PASS or else the first line of stdin, which is then not to be a
+terminal, so that it's kept out of the arguments and off the screen; paths are /-separated, either absolute or relative to the current dir, which is /
+initially";
+
+#[derive(Debug)]
+pub enum Error {
+	Usage(String),
+	// no such node
+	Path(String),
+	Io(String),
+	Protocol(protocol::Error),
+	Wire(wire::Error),
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::Usage(msg) => write!(f, "{}\n\n{}", msg, USAGE),
+			Error::Path(path) => write!(f, "{}: no such file or dir", path),
+			Error::Io(msg) => write!(f, "io: {}", msg),
+			Error::Protocol(e) => write!(f, "{}", e),
+			Error::Wire(e) => write!(f, "{}", e),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+impl From<protocol::Error> for Error {
+	fn from(e: protocol::Error) -> Self {
+		Self::Protocol(e)
+	}
+}
+
+impl From<wire::Error> for Error {
+	fn from(e: wire::Error) -> Self {
+		Self::Wire(e)
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(e: std::io::Error) -> Self {
+		Self::Io(e.to_string())
+	}
+}
+
+fn usage(msg: &str) -> Error {
+	Error::Usage(msg.to_string())
+}
+
+// what the cli needs from a backend on top of Network: accounts
+pub(crate) trait Server {
+	fn register(&self, user: LockedUser) -> Result<(), protocol::Error>;
+	fn locked_user(&self, id: Uid) -> Result<Option<LockedUser>, protocol::Error>;
+	// a handle for a Protocol to talk through
+	fn net(&self) -> Box<dyn Network>;
+}
+
+impl Server for Backend {
+	fn register(&self, user: LockedUser) -> Result<(), protocol::Error> {
+		Backend::register(self, user)
+	}
+
+	fn locked_user(&self, id: Uid) -> Result<Option<LockedUser>, protocol::Error> {
+		Ok(Backend::locked_user(self, id))
+	}
+
+	fn net(&self) -> Box<dyn Network> {
+		Box::new(self.clone())
+	}
+}
+
+impl Server for DirStore {
+	fn register(&self, user: LockedUser) -> Result<(), protocol::Error> {
+		DirStore::register(self, user)
+	}
+
+	fn locked_user(&self, id: Uid) -> Result<Option<LockedUser>, protocol::Error> {
+		DirStore::locked_user(self, id)
+	}
+
+	fn net(&self) -> Box<dyn Network> {
+		Box::new(self.clone())
+	}
+}
+
+// a dir, existing or ending with /, is a DirStore; anything else is a single Backend file
+fn open_store(path: &str) -> Result<Box<dyn Server>, Error> {
+	if path.ends_with('/') || Path::new(path).is_dir() {
+		Ok(Box::new(DirStore::open(path)?))
+	} else {
+		Ok(Box::new(Backend::open(path)?))
+	}
+}
+
+#[derive(Default)]
+struct Options {
+	store: Option<String>,
+	user: Option<Uid>,
+}
+
+// args exclude the program name; stdin is read by shell only
+pub fn run(args: &[String], stdin: &mut dyn BufRead, out: &mut dyn Write) -> Result<(), Error> {
+	let mut opts = Options::default();
+	let mut args = args.iter().map(String::as_str);
+	let mut cmd = Vec::new();
+
+	while let Some(arg) = args.next() {
+		let mut value = || args.next().map(str::to_string).ok_or(usage(arg));
+
+		match arg {
+			"--store" => opts.store = Some(value()?),
+			"--user" => {
+				opts.user = Some(Uid::from_str(&value()?).map_err(|e| usage(&e))?);
+			}
+			_ => {
+				cmd.push(arg);
+				cmd.extend(args.by_ref());
+			}
+		}
+	}
+
+	match cmd.as_slice() {
+		[] => Err(usage("no command")),
+		["inspect", path] => inspect(&std::fs::read(path)?, out),
+		["register", flags @ ..] => {
+			let god = match flags {
+				[] => false,
+				["--god"] => true,
+				_ => return Err(usage("register takes --god only")),
+			};
+			let server = open_store(opts.store.as_deref().ok_or(usage("no --store"))?)?;
+			let id = register(&*server, &read_pass(stdin)?, god)?;
+
+			writeln!(out, "{}", id)?;
+
+			Ok(())
+		}
+		cmd => {
+			let server = open_store(opts.store.as_deref().ok_or(usage("no --store"))?)?;
+			let user = opts.user.ok_or(usage("no --user"))?;
+			let mut session = Session::unlock(server, user, &read_pass(stdin)?)?;
+
+			match cmd {
+				["shell"] => session.shell(stdin, out),
+				["cd", ..] => Err(usage("cd makes sense within a shell only")),
+				cmd => session.exec(cmd, out),
+			}
+		}
+	}
+}
+
+// QVAULT_PASS or else the first line of stdin, which is not to be a terminal: it would echo the
+// password back, since there's no way to turn that off here
+fn read_pass(stdin: &mut dyn BufRead) -> Result<String, Error> {
+	if let Ok(pass) = std::env::var("QVAULT_PASS") {
+		return Ok(pass);
+	}
+
+	if io::stdin().is_terminal() {
+		return Err(usage("set QVAULT_PASS or pipe the password to stdin"));
+	}
+
+	let mut line = String::new();
+
+	stdin.read_line(&mut line)?;
+
+	match line.trim_end_matches(['\r', '\n']) {
+		"" => Err(usage("no password")),
+		pass => Ok(pass.to_string()),
+	}
+}
+
+// returns the new user's id
+fn register(server: &dyn Server, pass: &str, god: bool) -> Result<Uid, Error> {
+	let identity = Identity::generate(if god {
+		Uid::new(GOD_ID)
+	} else {
+		Uid::generate()
+	});
+	let roots = if god {
+		vec![FileSystem::new(&User::fs_seed(identity.private()), &identity).1]
+	} else {
+		Vec::new()
+	};
+	let encrypted_priv = password_lock::lock(identity.private(), pass)
+		.map_err(|_| Error::Io("failed to lock the private key".to_string()))?;
+
+	server.register(LockedUser {
+		encrypted_priv: Some(encrypted_priv),
+		_pub: identity.public().clone(),
+		shares: Vec::new(),
+		pending_invite_intents: Vec::new(),
+		roots,
+		cursor: Cursor::default(),
+	})?;
+
+	Ok(identity.id())
+}
+
+// a summary of a locked user; nothing is decrypted
+fn inspect(bytes: &[u8], out: &mut dyn Write) -> Result<(), Error> {
+	let format = wire::format_of(bytes)?;
+	let user: LockedUser = wire::decode(bytes)?;
+
+	writeln!(out, "id: {}", user._pub.id())?;
+	writeln!(out, "god: {}", user._pub.is_god())?;
+	writeln!(out, "format: {}", format.content_type())?;
+	writeln!(out, "password-locked: {}", user.encrypted_priv.is_some())?;
+	writeln!(out, "shares: {}", user.shares.len())?;
+	writeln!(
+		out,
+		"pending invite intents: {}",
+		user.pending_invite_intents.len()
+	)?;
+	writeln!(out, "locked nodes: {}", user.roots.len())?;
+	writeln!(out, "cursor: {}", user.cursor.0)?;
+
+	Ok(())
+}
+
+// files are listed and matched as name.ext
+fn file_name(view: &NodeView) -> String {
+	match view.ext() {
+		Some(ext) if !ext.is_empty() => format!("{}.{}", view.name(), ext),
+		_ => view.name(),
+	}
+}
+
+fn split_path(path: &str) -> (&str, &str) {
+	let path = path.trim_end_matches('/');
+
+	match path.rsplit_once('/') {
+		Some(("", name)) => ("/", name),
+		Some((dir, name)) => (dir, name),
+		None => (".", path),
+	}
+}
+
+struct Session {
+	protocol: Protocol,
+	server: Box<dyn Server>,
+	cwd: Uid,
+}
+
+impl Session {
+	fn unlock(server: Box<dyn Server>, id: Uid, pass: &str) -> Result<Self, Error> {
+		let locked = server
+			.locked_user(id)?
+			.ok_or(protocol::Error::NotFound(id))?;
+		let mut protocol =
+			Protocol::unlock(&wire::encode(&locked, Format::Cbor), pass, server.net())?;
+
+		block_on(protocol.migrate())?;
+
+		Ok(Self {
+			protocol,
+			server,
+			cwd: Uid::new(ROOT_ID),
+		})
+	}
+
+	fn shell(&mut self, stdin: &mut dyn BufRead, out: &mut dyn Write) -> Result<(), Error> {
+		for line in stdin.lines() {
+			let line = line?;
+			let cmd: Vec<&str> = line.split_whitespace().collect();
+
+			// a failed command doesn't end the session, just like in any other shell
+			if let Err(e) = self.exec(&cmd, out) {
+				writeln!(out, "error: {}", e)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn exec(&mut self, cmd: &[&str], out: &mut dyn Write) -> Result<(), Error> {
+		match cmd {
+			[] => Ok(()),
+			["ls"] => self.ls(".", out),
+			["ls", path] => self.ls(path, out),
+			["cd", path] => self.cd(path),
+			["mkdir", path] => self.mkdir(path),
+			["put", local] => self.put(local, "."),
+			["put", local, dir] => self.put(local, dir),
+			["get", path, local] => self.get(path, local, out),
+			["share", path, user_id] => self.share(path, user_id),
+			[cmd, ..] => Err(usage(&format!("bad arguments for {}", cmd))),
+		}
+	}
+
+	// the root has no view
+	fn resolve(&mut self, path: &str) -> Result<(Uid, Option<NodeView>), Error> {
+		let mut cur = if path.starts_with('/') {
+			Uid::new(ROOT_ID)
+		} else {
+			self.cwd
+		};
+		let mut view = None;
+
+		for name in path.split('/') {
+			match name {
+				"" | "." => {}
+				".." => {
+					let parent = block_on(self.protocol.cd_to_dir(&cur))?
+						.breadcrumbs()
+						.pop()
+						.filter(|p| p.id() != ROOT_ID);
+
+					cur = parent.as_ref().map_or(Uid::new(ROOT_ID), |p| p.id());
+					view = parent;
+				}
+				name => {
+					let item = block_on(self.protocol.cd_to_dir(&cur))?
+						.items()
+						.into_iter()
+						.find(|item| file_name(item) == name)
+						.ok_or(Error::Path(path.to_string()))?;
+
+					cur = item.id();
+					view = Some(item);
+				}
+			}
+		}
+
+		Ok((cur, view))
+	}
+
+	fn resolve_dir(&mut self, path: &str) -> Result<Uid, Error> {
+		match self.resolve(path)? {
+			(_, Some(view)) if !view.is_dir() => Err(Error::Path(format!("{}/", path))),
+			(id, _) => Ok(id),
+		}
+	}
+
+	fn ls(&mut self, path: &str, out: &mut dyn Write) -> Result<(), Error> {
+		let id = self.resolve_dir(path)?;
+
+		for item in block_on(self.protocol.cd_to_dir(&id))?.items() {
+			if item.is_dir() {
+				writeln!(out, "{}\t-\t{}/", item.id(), file_name(&item))?;
+			} else {
+				writeln!(out, "{}\t{}\t{}", item.id(), item.size(), file_name(&item))?;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn cd(&mut self, path: &str) -> Result<(), Error> {
+		self.cwd = self.resolve_dir(path)?;
+
+		Ok(())
+	}
+
+	fn mkdir(&mut self, path: &str) -> Result<(), Error> {
+		let (dir, name) = split_path(path);
+		let parent_id = self.resolve_dir(dir)?;
+
+		block_on(self.protocol.mkdir(parent_id, name))?;
+
+		Ok(())
+	}
+
+	fn put(&mut self, local: &str, dir: &str) -> Result<(), Error> {
+		let parent_id = self.resolve_dir(dir)?;
+		let file_name = Path::new(local)
+			.file_name()
+			.and_then(|n| n.to_str())
+			.ok_or(Error::Path(local.to_string()))?;
+		let (name, ext) = split_ext(file_name);
+		let mut src = File::open(local)?;
+		let size = src.metadata()?.len();
+
+		block_on(
+			self.protocol
+				.upload_file(parent_id, name, ext, size, &mut src),
+		)?;
+
+		Ok(())
+	}
+
+	// a dir is written as a whole; files which fail are listed, but don't stop the rest
+	fn get(&mut self, path: &str, local: &str, out: &mut dyn Write) -> Result<(), Error> {
+		match self.resolve(path)? {
+			(id, Some(view)) if !view.is_dir() => {
+				block_on(
+					self.protocol
+						.download_file(id, Path::new(local), &mut |_| {}),
+				)?;
+			}
+			(id, _) => {
+				let download = block_on(self.protocol.download_dir(
+					id,
+					Path::new(local),
+					&mut |_| {},
+				))?;
+
+				for (path, e) in download.failed {
+					writeln!(out, "error: {}: {}", path.display(), e)?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	fn share(&mut self, path: &str, user_id: &str) -> Result<(), Error> {
+		let (id, _) = self.resolve(path)?;
+		let user_id = Uid::from_str(user_id).map_err(|e| usage(&e))?;
+		let receiver: identity::Public = self
+			.server
+			.locked_user(user_id)?
+			.ok_or(protocol::Error::NotFound(user_id))?
+			._pub;
+
+		block_on(self.protocol.share(id, &receiver))?;
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::io::Cursor;
+
+	use super::{run, Error};
+	use crate::{
+		backend::Backend,
+		id::Uid,
+		user::GOD_ID,
+		wire::{self, Format},
+	};
+
+	fn qvault(args: &str, stdin: &str) -> Result<String, Error> {
+		let args: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+		let mut out = Vec::new();
+
+		run(&args, &mut Cursor::new(stdin), &mut out)?;
+
+		Ok(String::from_utf8(out).unwrap())
+	}
+
+	#[test]
+	fn test_scripted_session() {
+		let dir = std::env::temp_dir().join(format!("qvault-cli-{}", Uid::generate()));
+
+		std::fs::create_dir_all(&dir).unwrap();
+
+		let store = dir.join("store").display().to_string();
+		let local = dir.join("notes.txt").display().to_string();
+		let copy = dir.join("copy.txt").display().to_string();
+		let god = qvault(&format!("--store {} register --god", store), "god\n").unwrap();
+
+		assert_eq!(god.trim(), Uid::new(GOD_ID).to_string());
+
+		let alice = qvault(&format!("--store {} register", store), "alice\n").unwrap();
+		let alice = alice.trim();
+		let as_god = format!("--store {} --user {}", store, god.trim());
+
+		std::fs::write(&local, b"hello").unwrap();
+
+		qvault(&format!("{} mkdir /docs", as_god), "god\n").unwrap();
+
+		let out = qvault(
+			&format!("{} shell", as_god),
+			&format!(
+				"god\ncd docs\nput {}\nmkdir drafts\nls\ncd nowhere\nget notes.txt {}\nshare /docs {}\n",
+				local, copy, alice
+			),
+		)
+		.unwrap();
+		let lines: Vec<&str> = out.lines().collect();
+
+		assert_eq!(lines.len(), 3);
+		assert!(lines[0].ends_with("\t5\tnotes.txt"));
+		assert!(lines[1].ends_with("\t-\tdrafts/"));
+		assert_eq!(lines[2], "error: nowhere: no such file or dir");
+		assert_eq!(std::fs::read(&copy).unwrap(), b"hello");
+
+		// alice sees what's shared with her only
+		let as_alice = format!("--store {} --user {}", store, alice);
+		let out = qvault(&format!("{} ls", as_alice), "alice\n").unwrap();
+
+		assert!(out.trim().ends_with("\t-\tdocs/"));
+		assert!(matches!(
+			qvault(&format!("{} ls /docs/notes.txt", as_alice), "alice\n"),
+			Err(Error::Path(_))
+		));
+		assert!(qvault(&format!("{} ls", as_alice), "wrong\n").is_err());
+		assert!(matches!(
+			qvault(&format!("{} ls", as_alice), ""),
+			Err(Error::Usage(_))
+		));
+		assert!(matches!(
+			qvault(&format!("{} cd docs", as_alice), "alice\n"),
+			Err(Error::Usage(_))
+		));
+
+		let locked = dir.join("alice.json");
+		let alice_id = alice.parse().unwrap();
+
+		std::fs::write(
+			&locked,
+			wire::encode(
+				&Backend::open(&store)
+					.unwrap()
+					.locked_user(alice_id)
+					.unwrap(),
+				Format::Json,
+			),
+		)
+		.unwrap();
+
+		let out = qvault(&format!("inspect {}", locked.display()), "").unwrap();
+
+		assert!(out.contains(&format!("id: {}\n", alice)));
+		assert!(out.contains("god: false\n"));
+		assert!(out.contains("shares: 1\n"));
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
 
 use crate::{
 	hkdf,
@@ -49,23 +50,26 @@ impl SeedById for Vec<Seeds> {
 }
 
 pub fn derive_table_seed_from_root(root: &Seed, table_name: &str) -> Seed {
+	let ikm = Zeroizing::new([root.bytes.as_slice(), table_name.as_bytes()].concat());
+
 	Seed {
-		bytes: hkdf::Hkdf::from_ikm(&[root.bytes.as_slice(), table_name.as_bytes()].concat())
-			.expand_no_info::<{ seeds::SEED_SIZE }>(),
+		bytes: hkdf::Hkdf::from_ikm(&ikm).expand_no_info::<{ seeds::SEED_SIZE }>(),
 	}
 }
 
 pub fn derive_column_seed_from_table(table: &Seed, column_name: &str) -> Seed {
+	let ikm = Zeroizing::new([table.bytes.as_slice(), column_name.as_bytes()].concat());
+
 	Seed {
-		bytes: hkdf::Hkdf::from_ikm(&[table.bytes.as_slice(), column_name.as_bytes()].concat())
-			.expand_no_info::<{ seeds::SEED_SIZE }>(),
+		bytes: hkdf::Hkdf::from_ikm(&ikm).expand_no_info::<{ seeds::SEED_SIZE }>(),
 	}
 }
 
 pub fn derive_entry_seed_from_column(col: &Seed, salt: &Salt) -> Seed {
+	let ikm = Zeroizing::new([col.bytes.as_slice(), salt.bytes.as_slice()].concat());
+
 	Seed {
-		bytes: hkdf::Hkdf::from_ikm(&[col.bytes, salt.bytes].concat())
-			.expand_no_info::<{ seeds::SEED_SIZE }>(),
+		bytes: hkdf::Hkdf::from_ikm(&ikm).expand_no_info::<{ seeds::SEED_SIZE }>(),
 	}
 }
 
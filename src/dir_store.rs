@@ -0,0 +1,727 @@
+// a server side kept in a local directory, one file per item, for the cli, tests and air-gapped
+// deployments; serves the same Network as backend::Backend, but keeps nothing in memory, so that any
+// number of handles (and processes) opened on the same directory see each other's changes
+//
+// layout:
+//   index                 parents of all nodes, the change log and a share counter
+//   index.lock            held by whoever is updating index
+//   users/<id>            locked users, as registered, but with no roots, shares and intents
+//   nodes/<id>            the latest version of each node
+//   shares/<n>            locked shares in the order of upload
+//   intents/<hash>        invite intents by ref_src
+//   chunks/<id>/<idx>     encrypted chunks as is
+//   digests/<id>          chunk digests
+//
+// each file is written to a temp file first and then renamed, so readers never see a partial write;
+// writers take turns through index.lock, held while the index is read, modified and written
+
+use std::{
+	collections::{HashMap, HashSet},
+	fs,
+	io::ErrorKind,
+	path::{Path, PathBuf},
+	rc::Rc,
+	sync::atomic::{AtomicU64, Ordering},
+	thread,
+	time::Duration,
+};
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+	hmac,
+	id::Uid,
+	identity,
+	protocol::{Error, Network},
+	register::{LockedUser, Migrated},
+	seeds::{FinishInviteIntent, InviteIntent, LockedShare, ROOT_ID},
+	sync::{ChangeLog, Changes, Cursor},
+	user::GOD_ID,
+	vault::LockedNode,
+	wire::{self, Format},
+};
+
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+struct Index {
+	// node id -> parent id, enough to walk subtrees without reading every node
+	parents: HashMap<Uid, Uid>,
+	changes: ChangeLog,
+	// the number of shares uploaded so far; names the next one
+	shares: u64,
+}
+
+impl Index {
+	// id itself, if known, followed by its descendants
+	fn subtree(&self, id: Uid) -> Vec<Uid> {
+		let mut children: HashMap<Uid, Vec<Uid>> = HashMap::new();
+
+		for (&child, &parent) in &self.parents {
+			children.entry(parent).or_default().push(child);
+		}
+
+		let mut subtree: Vec<Uid> = self
+			.parents
+			.contains_key(&id)
+			.then_some(id)
+			.into_iter()
+			.collect();
+		let mut to_visit = vec![id];
+
+		while let Some(id) = to_visit.pop() {
+			for &child in children.get(&id).into_iter().flatten() {
+				to_visit.push(child);
+				subtree.push(child);
+			}
+		}
+
+		subtree
+	}
+}
+
+// see DirStore::lock_index
+const LOCK_ATTEMPTS: u32 = 500;
+const LOCK_RETRY: Duration = Duration::from_millis(10);
+
+// removes the lock file once dropped
+struct IndexLock(PathBuf);
+
+impl Drop for IndexLock {
+	fn drop(&mut self) {
+		_ = fs::remove_file(&self.0);
+	}
+}
+
+// fixed width hex rather than base64 to be safe on case-insensitive file systems
+fn file_name(id: Uid) -> String {
+	format!("{:016x}", u64::from_be_bytes(id.as_bytes()))
+}
+
+fn io_err(e: std::io::Error) -> Error {
+	Error::Io(e.to_string())
+}
+
+// cheap to clone: clones share the same directory
+#[derive(Clone)]
+pub struct DirStore {
+	root: Rc<PathBuf>,
+}
+
+impl DirStore {
+	// creates root, if needed; an existing store is picked up as is
+	pub fn open(root: impl AsRef<Path>) -> Result<Self, Error> {
+		let root = root.as_ref().to_path_buf();
+
+		for dir in ["users", "nodes", "shares", "intents", "chunks", "digests"] {
+			fs::create_dir_all(root.join(dir)).map_err(io_err)?;
+		}
+
+		Ok(Self {
+			root: Rc::new(root),
+		})
+	}
+
+	fn path(&self, dir: &str, id: Uid) -> PathBuf {
+		self.root.join(dir).join(file_name(id))
+	}
+
+	fn chunk_path(&self, file_id: Uid, chunk_idx: u64) -> PathBuf {
+		self.path("chunks", file_id).join(chunk_idx.to_string())
+	}
+
+	fn intent_path(&self, ref_src: &str) -> PathBuf {
+		self.path("intents", Uid::from_bytes(ref_src.as_bytes()))
+	}
+
+	fn read_bytes(&self, path: &Path) -> Result<Option<Vec<u8>>, Error> {
+		match fs::read(path) {
+			Ok(bytes) => Ok(Some(bytes)),
+			Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(io_err(e)),
+		}
+	}
+
+	fn read<T: DeserializeOwned>(&self, path: &Path) -> Result<Option<T>, Error> {
+		self.read_bytes(path)?
+			.map(|bytes| wire::decode(&bytes).map_err(Error::BadEncoding))
+			.transpose()
+	}
+
+	// every file in dir, in no particular order
+	fn read_all<T: DeserializeOwned>(&self, dir: &str) -> Result<Vec<T>, Error> {
+		let mut items = Vec::new();
+
+		for entry in fs::read_dir(self.root.join(dir)).map_err(io_err)? {
+			let path = entry.map_err(io_err)?.path();
+
+			if path.extension().is_none() {
+				items.extend(self.read(&path)?);
+			}
+		}
+
+		Ok(items)
+	}
+
+	// a temp file next to path, renamed over it once fully written; temp files have an extension,
+	// which real items never do, unique to each write, so that neither other processes nor other
+	// handles in this one write to it at the same time
+	fn write_bytes(&self, path: &Path, bytes: &[u8]) -> Result<(), Error> {
+		static WRITES: AtomicU64 = AtomicU64::new(0);
+
+		let tmp = path.with_extension(format!(
+			"{}-{}.tmp",
+			std::process::id(),
+			WRITES.fetch_add(1, Ordering::Relaxed)
+		));
+
+		fs::write(&tmp, bytes).map_err(io_err)?;
+		fs::rename(&tmp, path).map_err(io_err)
+	}
+
+	fn write<T: Serialize>(&self, path: &Path, val: &T) -> Result<(), Error> {
+		self.write_bytes(path, &wire::encode(val, Format::Cbor))
+	}
+
+	fn remove(&self, path: &Path) -> Result<(), Error> {
+		let res = if path.is_dir() {
+			fs::remove_dir_all(path)
+		} else {
+			fs::remove_file(path)
+		};
+
+		match res {
+			Err(e) if e.kind() != ErrorKind::NotFound => Err(io_err(e)),
+			_ => Ok(()),
+		}
+	}
+
+	fn index(&self) -> Result<Index, Error> {
+		Ok(self.read(&self.root.join("index"))?.unwrap_or_default())
+	}
+
+	// waits for whoever holds the lock, for LOCK_ATTEMPTS at most: a lock left behind by a crashed
+	// writer is to be removed by hand
+	fn lock_index(&self) -> Result<IndexLock, Error> {
+		let path = self.root.join("index.lock");
+
+		for _ in 0..LOCK_ATTEMPTS {
+			match fs::OpenOptions::new()
+				.write(true)
+				.create_new(true)
+				.open(&path)
+			{
+				Ok(_) => return Ok(IndexLock(path)),
+				Err(e) if e.kind() == ErrorKind::AlreadyExists => thread::sleep(LOCK_RETRY),
+				Err(e) => return Err(io_err(e)),
+			}
+		}
+
+		Err(Error::Io(format!("{}: held for too long", path.display())))
+	}
+
+	fn update_index<T>(&self, f: impl FnOnce(&mut Index) -> Result<T, Error>) -> Result<T, Error> {
+		let _lock = self.lock_index()?;
+		let mut index = self.index()?;
+		let res = f(&mut index)?;
+
+		self.write(&self.root.join("index"), &index)?;
+
+		Ok(res)
+	}
+
+	fn nodes(&self, ids: impl IntoIterator<Item = Uid>) -> Result<Vec<LockedNode>, Error> {
+		let mut nodes = Vec::new();
+
+		for id in ids {
+			nodes.extend(self.node(id)?);
+		}
+
+		Ok(nodes)
+	}
+
+	pub fn node(&self, id: Uid) -> Result<Option<LockedNode>, Error> {
+		self.read(&self.path("nodes", id))
+	}
+
+	fn put_node(&self, index: &mut Index, node: &LockedNode) -> Result<(), Error> {
+		self.write(&self.path("nodes", node.id), node)?;
+		index.parents.insert(node.id, node.parent_id);
+		index.changes.log(node.id);
+
+		Ok(())
+	}
+
+	fn put_share(&self, index: &mut Index, share: &LockedShare) -> Result<(), Error> {
+		self.write(&self.path("shares", Uid::new(index.shares)), share)?;
+		index.shares += 1;
+
+		Ok(())
+	}
+
+	// in the order of upload
+	fn shares_of(&self, user_id: Uid) -> Result<Vec<LockedShare>, Error> {
+		let mut shares = Vec::new();
+
+		for n in 0..self.index()?.shares {
+			shares.extend(
+				self.read::<LockedShare>(&self.path("shares", Uid::new(n)))?
+					.filter(|s| s.export.receiver == user_id || s.sender.id() == user_id),
+			);
+		}
+
+		Ok(shares)
+	}
+
+	fn intents_of(&self, sender_id: Uid) -> Result<Vec<InviteIntent>, Error> {
+		Ok(self
+			.read_all::<InviteIntent>("intents")?
+			.into_iter()
+			.filter(|i| i.sender.id() == sender_id)
+			.collect())
+	}
+
+	// under the index lock, so that two processes can't register the same id at once
+	pub fn register(&self, user: LockedUser) -> Result<(), Error> {
+		let id = user._pub.id();
+		let path = self.path("users", id);
+		let LockedUser {
+			encrypted_priv,
+			_pub,
+			shares,
+			pending_invite_intents,
+			roots,
+			cursor: _,
+		} = user;
+
+		self.update_index(|index| {
+			if path.exists() {
+				return Err(Error::BadOperation {
+					id,
+					reason: "already registered",
+				});
+			}
+
+			for node in &roots {
+				self.put_node(index, node)?;
+			}
+
+			for share in &shares {
+				self.put_share(index, share)?;
+			}
+
+			for intent in &pending_invite_intents {
+				self.write(&self.intent_path(&intent.ref_src), intent)?;
+			}
+
+			self.write(
+				&path,
+				&LockedUser {
+					encrypted_priv,
+					_pub,
+					shares: Vec::new(),
+					pending_invite_intents: Vec::new(),
+					roots: Vec::new(),
+					cursor: Cursor::default(),
+				},
+			)
+		})
+	}
+
+	// see Backend::ack_invite_intent
+	pub fn ack_invite_intent(
+		&self,
+		ref_src: &str,
+		receiver: identity::Public,
+	) -> Result<(), Error> {
+		let path = self.intent_path(ref_src);
+		let mut intent: InviteIntent = self.read(&path)?.ok_or(Error::NotFound(receiver.id()))?;
+
+		if intent.user_id != receiver.id() {
+			return Err(Error::NoAccess(receiver.id()));
+		}
+
+		intent.receiver = Some(receiver);
+
+		self.write(&path, &intent)
+	}
+
+	// see Backend::locked_user
+	pub fn locked_user(&self, id: Uid) -> Result<Option<LockedUser>, Error> {
+		let Some(user) = self.read::<LockedUser>(&self.path("users", id))? else {
+			return Ok(None);
+		};
+		let index = self.index()?;
+		let shares = self.shares_of(id)?;
+		let root_ids: Vec<Uid> = if id == GOD_ID {
+			vec![Uid::new(ROOT_ID)]
+		} else {
+			shares
+				.iter()
+				.filter(|s| s.export.receiver == id)
+				.flat_map(|s| s.export.fs.iter().cloned())
+				.collect()
+		};
+		let mut seen = HashSet::new();
+		let ids: Vec<Uid> = root_ids
+			.into_iter()
+			.flat_map(|id| index.subtree(id))
+			.filter(|id| seen.insert(*id))
+			.collect();
+
+		Ok(Some(LockedUser {
+			shares,
+			pending_invite_intents: self.intents_of(id)?,
+			roots: self.nodes(ids)?,
+			cursor: index.changes.cursor(),
+			..user
+		}))
+	}
+}
+
+#[async_trait(?Send)]
+impl Network for DirStore {
+	async fn fetch_subtree(&self, id: Uid) -> Result<Vec<LockedNode>, Error> {
+		// children only
+		self.nodes(self.index()?.subtree(id).into_iter().filter(|&n| n != id))
+	}
+
+	async fn upload_chunk(&self, file_id: Uid, chunk_idx: u64, ct: &[u8]) -> Result<(), Error> {
+		fs::create_dir_all(self.path("chunks", file_id)).map_err(io_err)?;
+
+		self.write_bytes(&self.chunk_path(file_id, chunk_idx), ct)
+	}
+
+	async fn upload_digests(&self, file_id: Uid, digests: &[hmac::Digest]) -> Result<(), Error> {
+		self.write(&self.path("digests", file_id), &digests)
+	}
+
+	async fn fetch_digests(&self, file_id: Uid) -> Result<Vec<hmac::Digest>, Error> {
+		self.read(&self.path("digests", file_id))?
+			.ok_or(Error::NotFound(file_id))
+	}
+
+	async fn fetch_chunk(&self, file_id: Uid, chunk_idx: u64) -> Result<Vec<u8>, Error> {
+		self.read_bytes(&self.chunk_path(file_id, chunk_idx))?
+			.ok_or(Error::NotFound(file_id))
+	}
+
+	async fn save_node(&self, node: &LockedNode, base: Option<&hmac::Digest>) -> Result<(), Error> {
+		self.update_index(|index| {
+			match (self.node(node.id)?, base) {
+				(Some(stored), base) if base != Some(&stored.hash()) => {
+					return Err(Error::Conflict {
+						id: node.id,
+						theirs: Box::new(stored),
+					})
+				}
+				(None, Some(_)) => return Err(Error::NotFound(node.id)),
+				_ => {}
+			}
+
+			self.put_node(index, node)
+		})
+	}
+
+	async fn copy_file(&self, from: Uid, to: Uid) -> Result<(), Error> {
+		let src = self.path("chunks", from);
+
+		if !src.is_dir() {
+			return Err(Error::NotFound(from));
+		}
+
+		fs::create_dir_all(self.path("chunks", to)).map_err(io_err)?;
+
+		for entry in fs::read_dir(&src).map_err(io_err)? {
+			let path = entry.map_err(io_err)?.path();
+
+			if let (Some(name), None) = (path.file_name(), path.extension()) {
+				let ct = fs::read(&path).map_err(io_err)?;
+
+				self.write_bytes(&self.path("chunks", to).join(name), &ct)?;
+			}
+		}
+
+		if let Some(digests) = self.read_bytes(&self.path("digests", from))? {
+			self.write_bytes(&self.path("digests", to), &digests)?;
+		}
+
+		Ok(())
+	}
+
+	async fn delete_node(&self, id: Uid) -> Result<(), Error> {
+		self.update_index(|index| {
+			let subtree = index.subtree(id);
+
+			if subtree.is_empty() {
+				return Err(Error::NotFound(id));
+			}
+
+			for id in subtree {
+				self.remove(&self.path("nodes", id))?;
+				self.remove(&self.path("chunks", id))?;
+				self.remove(&self.path("digests", id))?;
+				index.parents.remove(&id);
+				index.changes.log(id);
+			}
+
+			Ok(())
+		})
+	}
+
+	async fn fetch_changes(&self, since: Cursor) -> Result<Changes, Error> {
+		self.index()?.changes.page(since, |id| self.node(id))
+	}
+
+	async fn upload_share(&self, share: &LockedShare) -> Result<(), Error> {
+		self.update_index(|index| self.put_share(index, share))
+	}
+
+	async fn fetch_shares(&self, user_id: Uid) -> Result<Vec<LockedShare>, Error> {
+		self.shares_of(user_id)
+	}
+
+	async fn post_invite_intent(&self, intent: &InviteIntent) -> Result<(), Error> {
+		self.write(&self.intent_path(&intent.ref_src), intent)
+	}
+
+	async fn fetch_invite_intents(&self, sender_id: Uid) -> Result<Vec<InviteIntent>, Error> {
+		self.intents_of(sender_id)
+	}
+
+	// under the index lock, so that an intent is finished once at most
+	async fn finish_invite_intent(&self, finish: &FinishInviteIntent) -> Result<(), Error> {
+		let path = self.intent_path(&finish.ref_src);
+
+		self.update_index(|index| {
+			match self.read::<InviteIntent>(&path)? {
+				Some(intent) if intent.receiver.is_some() => {}
+				_ => return Err(Error::NotFound(finish.share.export.receiver)),
+			}
+
+			self.put_share(index, &finish.share)?;
+			self.remove(&path)
+		})
+	}
+
+	async fn save_migrated(&self, migrated: &Migrated) -> Result<(), Error> {
+		let path = self.path("users", migrated.user_id);
+
+		self.update_index(|index| {
+			let mut user: LockedUser =
+				self.read(&path)?.ok_or(Error::NotFound(migrated.user_id))?;
+
+			for n in 0..index.shares {
+				let share_path = self.path("shares", Uid::new(n));
+
+				if let Some(new) = self
+					.read::<LockedShare>(&share_path)?
+					.as_ref()
+					.and_then(|share| migrated.share_for(share))
+				{
+					self.write(&share_path, new)?;
+				}
+			}
+
+			if let Some(ref lock) = migrated.encrypted_priv {
+				user.encrypted_priv = Some(lock.clone());
+				self.write(&path, &user)?;
+			}
+
+			Ok(())
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use futures::executor::block_on;
+
+	use super::DirStore;
+	use crate::{
+		cli::Server,
+		fixtures::{locked_user, login, register},
+		id::Uid,
+		identity::Identity,
+		password_lock,
+		protocol::{Network, Protocol},
+		register::LockedUser,
+		seeds::{Bundle, LockedShare, ROOT_ID},
+		user::{User, GOD_ID},
+		vault::FileSystem,
+		version::Versioned,
+		wire::{self, Format},
+	};
+
+	#[test]
+	fn test_share_and_sync_across_handles() {
+		let dir = std::env::temp_dir().join(format!("qvault-dir-{}", Uid::generate()));
+		let store = DirStore::open(&dir).unwrap();
+		let god = Identity::generate(Uid::new(GOD_ID));
+		let admin = Identity::generate(Uid::new(1));
+		let (_, root) = FileSystem::new(&User::fs_seed(god.private()), &god);
+
+		register(&store, &god, vec![root]);
+		register(&store, &admin, Vec::new());
+
+		let mut protocol = login(&store, &god);
+		let docs = block_on(protocol.mkdir(Uid::new(ROOT_ID), "docs")).unwrap();
+		let content = b"the quick brown fox".to_vec();
+		let file = block_on(protocol.upload_file(
+			docs.id(),
+			"fox",
+			"txt",
+			content.len() as u64,
+			&mut content.as_slice(),
+		))
+		.unwrap();
+
+		block_on(protocol.share(docs.id(), admin.public())).unwrap();
+
+		// a fresh handle, as if another process opened the same dir
+		let store = DirStore::open(&dir).unwrap();
+		let mut admin_protocol = login(&store, &admin);
+		let items = block_on(admin_protocol.ls_cur_mut()).unwrap().items();
+
+		assert_eq!(items.len(), 1);
+		assert_eq!(items[0].name(), "docs");
+		assert_eq!(
+			block_on(admin_protocol.cd_to_dir(&docs.id()))
+				.unwrap()
+				.items()
+				.len(),
+			1
+		);
+		assert_eq!(
+			block_on(admin_protocol.download_chunk(file.id(), 0)).unwrap(),
+			content
+		);
+		assert!(admin_protocol.integrity_report().is_clean());
+		assert!(store.locked_user(Uid::new(2)).unwrap().is_none());
+
+		let mut phone = login(&store, &god);
+
+		block_on(protocol.delete(file.id())).unwrap();
+
+		// the file and docs, listed again
+		assert_eq!(block_on(phone.sync()), Ok(2));
+		assert!(block_on(store.fetch_chunk(file.id(), 0)).is_err());
+		assert!(block_on(phone.cd_to_dir(&docs.id()))
+			.unwrap()
+			.items()
+			.is_empty());
+		// no temp files left behind
+		assert!(std::fs::read_dir(dir.join("nodes")).unwrap().all(|e| e
+			.unwrap()
+			.path()
+			.extension()
+			.is_none()));
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_writers_take_turns() {
+		let dir = std::env::temp_dir().join(format!("qvault-dir-{}", Uid::generate()));
+
+		DirStore::open(&dir).unwrap();
+
+		// as if from a few processes: each thread has a handle of its own
+		let writers: Vec<_> = (0..4)
+			.map(|_| {
+				let dir = dir.clone();
+
+				std::thread::spawn(move || {
+					let store = DirStore::open(&dir).unwrap();
+
+					for _ in 0..25 {
+						store
+							.update_index(|index| {
+								index.shares += 1;
+
+								Ok(())
+							})
+							.unwrap();
+					}
+				})
+			})
+			.collect();
+
+		for writer in writers {
+			writer.join().unwrap();
+		}
+
+		assert_eq!(DirStore::open(&dir).unwrap().index().unwrap().shares, 100);
+		assert!(!dir.join("index.lock").exists());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_register_once() {
+		let dir = std::env::temp_dir().join(format!("qvault-dir-{}", Uid::generate()));
+		let admin = Identity::generate(Uid::new(1));
+
+		DirStore::open(&dir).unwrap();
+
+		// the same id from a few handles at once
+		let registrations: Vec<_> = (0..4)
+			.map(|_| {
+				let (dir, admin) = (dir.clone(), admin.clone());
+
+				std::thread::spawn(move || {
+					DirStore::open(&dir)
+						.unwrap()
+						.register(locked_user(&admin, Vec::new()))
+						.is_ok()
+				})
+			})
+			.collect();
+		let registered = registrations
+			.into_iter()
+			.filter_map(|r| r.join().unwrap().then_some(()))
+			.count();
+
+		assert_eq!(registered, 1);
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn test_save_migrated() {
+		let dir = std::env::temp_dir().join(format!("qvault-dir-{}", Uid::generate()));
+		let store = DirStore::open(&dir).unwrap();
+		let god = Identity::generate(Uid::new(GOD_ID));
+		let admin = Identity::generate(Uid::new(1));
+		let mut share = LockedShare::new(&god, admin.public(), &Bundle::new());
+		let mut lock = password_lock::lock(admin.private(), "pass").unwrap();
+
+		share.payload.version = 0;
+		lock.version = 0;
+		store
+			.register(LockedUser {
+				encrypted_priv: Some(lock),
+				shares: vec![share],
+				..locked_user(&admin, Vec::new())
+			})
+			.unwrap();
+
+		let locked = store.locked_user(admin.id()).unwrap().unwrap();
+		let mut protocol = Protocol::unlock(
+			&wire::encode(&locked, Format::Cbor),
+			"pass",
+			Server::net(&store),
+		)
+		.unwrap();
+
+		assert_eq!(block_on(protocol.migrate()), Ok(2));
+
+		// as seen by the next session
+		let locked = store.locked_user(admin.id()).unwrap().unwrap();
+
+		assert!(!locked.encrypted_priv.unwrap().is_outdated());
+		assert_eq!(locked.shares.len(), 1);
+		assert!(!locked.shares[0].payload.is_outdated());
+
+		std::fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -1,9 +1,9 @@
 use rand::rngs::OsRng;
-use rand::RngCore;
 use serde::{self, Deserialize, Serialize};
 
 use crate::base64_blobs::{deserialize_array_base64, serialize_array_base64};
 use crate::{
+	env::Rng,
 	key_pair::{KeyPair, KeyPairSize},
 	private_key::PrivateKey,
 	public_key::PublicKey,
@@ -55,7 +55,11 @@ pub type KeyPairEd25519 =
 
 impl KeyPairEd25519 {
 	pub fn generate() -> Self {
-		let private = PrivateKeyEd25519::generate();
+		Self::generate_with(&mut OsRng)
+	}
+
+	pub fn generate_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
+		let private = PrivateKeyEd25519::generate_with(rng);
 		let public = PublicKeyEd25519::from_private(&private);
 
 		Self::new(private, public)
@@ -64,8 +68,12 @@ impl KeyPairEd25519 {
 
 impl PrivateKeyEd25519 {
 	pub fn generate() -> Self {
+		Self::generate_with(&mut OsRng)
+	}
+
+	pub fn generate_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
 		let mut key = [0u8; KeyTypeEd25519::PRIV];
-		OsRng.fill_bytes(&mut key);
+		rng.fill_bytes(&mut key);
 
 		Self::from(&key)
 	}
@@ -104,18 +112,21 @@ impl PublicKeyEd25519 {
 	}
 
 	pub fn verify(&self, msg: &[u8], signature: &Signature) -> bool {
+		self.try_verify(msg, signature).is_ok()
+	}
+
+	// same as verify, but tells why it failed: a malformed key or a mismatching signature
+	pub fn try_verify(
+		&self,
+		msg: &[u8],
+		signature: &Signature,
+	) -> Result<(), ed25519_dalek::SignatureError> {
 		use ed25519_dalek::Verifier;
 
-		if let Ok(public) = ed25519_dalek::VerifyingKey::from_bytes(self.as_bytes()) {
-			public
-				.verify(
-					msg,
-					&ed25519_dalek::Signature::from_bytes(signature.as_bytes()),
-				)
-				.is_ok()
-		} else {
-			false
-		}
+		ed25519_dalek::VerifyingKey::from_bytes(self.as_bytes())?.verify(
+			msg,
+			&ed25519_dalek::Signature::from_bytes(signature.as_bytes()),
+		)
 	}
 }
 
@@ -0,0 +1,87 @@
+// randomness and time used by the vault; both are pluggable, so that tests and test vectors can be
+// reproduced across implementations
+
+use std::cell::{RefCell, RefMut};
+
+use rand::{rngs::OsRng, CryptoRng, RngCore};
+
+pub trait Rng: RngCore + CryptoRng {}
+
+impl<T: RngCore + CryptoRng> Rng for T {}
+
+pub trait Clock {
+	// millis since unix epoch
+	fn now(&self) -> u64;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> u64 {
+		use std::time::{SystemTime, UNIX_EPOCH};
+		let duration = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+
+		duration.as_secs() * 1000 + duration.subsec_millis() as u64
+	}
+}
+
+// always returns the same time; useful for tests
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+	fn now(&self) -> u64 {
+		self.0
+	}
+}
+
+pub struct Env {
+	rng: RefCell<Box<dyn Rng>>,
+	clock: Box<dyn Clock>,
+}
+
+impl Env {
+	pub fn new(rng: impl Rng + 'static, clock: impl Clock + 'static) -> Self {
+		Self {
+			rng: RefCell::new(Box::new(rng)),
+			clock: Box::new(clock),
+		}
+	}
+
+	// OsRng and the system time
+	pub fn system() -> Self {
+		Self::new(OsRng, SystemClock)
+	}
+
+	// don't keep it borrowed across calls that might need randomness as well
+	pub fn rng(&self) -> RefMut<'_, Box<dyn Rng>> {
+		self.rng.borrow_mut()
+	}
+
+	pub fn now(&self) -> u64 {
+		self.clock.now()
+	}
+}
+
+impl std::fmt::Debug for Env {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Env").finish_non_exhaustive()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use rand::RngCore;
+	use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
+
+	use super::{Env, FixedClock};
+
+	#[test]
+	fn test_seeded_env_is_deterministic() {
+		let e0 = Env::new(ChaCha20Rng::from_seed([1u8; 32]), FixedClock(42));
+		let e1 = Env::new(ChaCha20Rng::from_seed([1u8; 32]), FixedClock(42));
+
+		assert_eq!(e0.rng().next_u64(), e1.rng().next_u64());
+		assert_eq!(e0.now(), 42);
+		assert_ne!(e0.rng().next_u64(), Env::system().rng().next_u64());
+	}
+}
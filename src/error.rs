@@ -0,0 +1,156 @@
+// a crate-level error to propagate any module error with ?; module errors carry the context (the
+// node or share id and the failing stage) and are kept as sources
+
+use std::{fmt, sync::Arc};
+
+use crate::{
+	aes_gcm, identity, kyber, password_lock, protocol, register, user, vault, version, wire, x448,
+};
+
+// where opening a locked node or share failed, or locking an archive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+	// written by a newer client
+	Version,
+	// wrong seed or key, or a corrupted ciphertext
+	Decrypt,
+	// decrypted, but not of the expected layout
+	Decode,
+	// decoded, but the signature doesn't match
+	Verify,
+	// verified, but its dir's listing doesn't include it, eg not listed yet
+	Listing,
+	// an archive couldn't be locked, eg its password's key derivation failed; see archive::seal
+	Encrypt,
+}
+
+impl fmt::Display for Stage {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(match self {
+			Stage::Version => "version",
+			Stage::Decrypt => "decrypt",
+			Stage::Decode => "decode",
+			Stage::Verify => "signature",
+			Stage::Listing => "listing",
+			Stage::Encrypt => "encrypt",
+		})
+	}
+}
+
+// the error a stage or a decoding failed with, kept as a source for reporting; errors compare by
+// their context only, so any two causes are equal
+#[derive(Debug, Clone, Default)]
+pub struct Cause(Option<Arc<dyn std::error::Error + Send + Sync>>);
+
+impl Cause {
+	pub fn new<E: std::error::Error + Send + Sync + 'static>(e: E) -> Self {
+		Self(Some(Arc::new(e)))
+	}
+
+	pub fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		self.0
+			.as_deref()
+			.map(|e| e as &(dyn std::error::Error + 'static))
+	}
+}
+
+impl PartialEq for Cause {
+	fn eq(&self, _: &Self) -> bool {
+		true
+	}
+}
+
+#[derive(Debug)]
+pub enum Error {
+	Aes(aes_gcm::Error),
+	Identity(identity::Error),
+	Kyber(kyber::Error),
+	PasswordLock(password_lock::Error),
+	Protocol(protocol::Error),
+	Register(register::Error),
+	User(user::Error),
+	Vault(vault::Error),
+	Version(version::Error),
+	Wire(wire::Error),
+	X448(x448::Error),
+}
+
+macro_rules! from_module_errors {
+	($($variant:ident => $module:ident),* $(,)?) => {
+		$(
+			impl From<$module::Error> for Error {
+				fn from(e: $module::Error) -> Self {
+					Error::$variant(e)
+				}
+			}
+		)*
+
+		impl fmt::Display for Error {
+			fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+				match self {
+					$(Error::$variant(e) => write!(f, "{}: {}", stringify!($module), e),)*
+				}
+			}
+		}
+
+		impl std::error::Error for Error {
+			fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+				match self {
+					$(Error::$variant(e) => Some(e),)*
+				}
+			}
+		}
+	};
+}
+
+from_module_errors! {
+	Aes => aes_gcm,
+	Identity => identity,
+	Kyber => kyber,
+	PasswordLock => password_lock,
+	Protocol => protocol,
+	Register => register,
+	User => user,
+	Vault => vault,
+	Version => version,
+	Wire => wire,
+	X448 => x448,
+}
+
+#[cfg(test)]
+mod tests {
+	use std::error::Error as _;
+
+	use super::{Cause, Error, Stage};
+	use crate::{id::Uid, vault, wire};
+
+	fn decode_uid(bytes: &[u8]) -> Result<Uid, Error> {
+		Ok(wire::decode(bytes)?)
+	}
+
+	#[test]
+	fn test_from_module_errors() {
+		let err = decode_uid(b"{}").unwrap_err();
+
+		assert!(matches!(err, Error::Wire(wire::Error::BadEncoding(_))));
+		assert_eq!(err.to_string(), "wire: bad encoding");
+		// down to the serde error
+		assert!(err.source().and_then(|e| e.source()).is_some());
+
+		let err: Error = vault::Error::Locked {
+			id: Uid::new(1),
+			stage: Stage::Decode,
+			cause: Cause::new(wire::decode::<Uid>(b"{}").unwrap_err()),
+		}
+		.into();
+
+		assert_eq!(
+			err.to_string(),
+			format!("vault: node {} failed at decode", Uid::new(1))
+		);
+		assert_eq!(
+			err.source().and_then(|e| e.source()).map(|e| e.to_string()),
+			Some("bad encoding".to_string())
+		);
+	}
+}
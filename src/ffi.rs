@@ -0,0 +1,936 @@
+// a c abi for apps to link against instead of maintaining their own wrappers; see include/qvault.h,
+// which is generated with `cargo build --features headers`
+//
+// conventions:
+// - every call returns a Status and writes its result, if any, to out
+// - a protocol is not thread-safe: use it from one thread at a time, a background one preferably,
+//   since calls block until the network callback has returned
+// - whatever is returned to the app is owned by it and to be released with the matching _free
+// - no panic unwinds into the app: it's reported as Status::Panic instead
+// - byte pointers may be null, if their length is zero; strings are nul-terminated utf-8
+#![allow(clippy::missing_safety_doc)]
+
+use std::{
+	cell::Cell,
+	ffi::{c_char, c_void, CStr, CString},
+	panic::{self, AssertUnwindSafe},
+	ptr,
+	rc::Rc,
+	slice,
+};
+
+use async_trait::async_trait;
+use futures::executor::block_on;
+use serde::Serialize;
+
+use crate::{
+	cache::Mutation,
+	env::Env,
+	error::Cause,
+	hmac,
+	id::Uid,
+	identity,
+	protocol::{DirView, Error, Network, NodeView, Protocol},
+	register::Migrated,
+	seeds::{FinishInviteIntent, InviteIntent, LockedShare, ROOT_ID},
+	sync::{Changes, Cursor},
+	vault::LockedNode,
+	wire::{self, Format},
+};
+
+/// What every call returns; the network callback returns one as well, as a u32.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Status {
+	Ok = 0,
+	NotFound,
+	NoNetwork,
+	NoAccess,
+	BadOperation,
+	BadEncoding,
+	Locked,
+	Io,
+	BadDigest,
+	Unlisted,
+	RolledBack,
+	/// Returned by the network callback along with the stored node, see Network::save_node.
+	Conflict,
+	/// A null pointer or a string of bad utf-8.
+	BadArgument,
+	/// A bug in the library; the protocol it happened in is not to be used anymore, other than to
+	/// be freed.
+	Panic,
+}
+
+impl TryFrom<u32> for Status {
+	type Error = u32;
+
+	fn try_from(n: u32) -> Result<Self, u32> {
+		[
+			Self::Ok,
+			Self::NotFound,
+			Self::NoNetwork,
+			Self::NoAccess,
+			Self::BadOperation,
+			Self::BadEncoding,
+			Self::Locked,
+			Self::Io,
+			Self::BadDigest,
+			Self::Unlisted,
+			Self::RolledBack,
+			Self::Conflict,
+			Self::BadArgument,
+			Self::Panic,
+		]
+		.into_iter()
+		.find(|s| *s as u32 == n)
+		.ok_or(n)
+	}
+}
+
+impl From<&Error> for Status {
+	fn from(e: &Error) -> Self {
+		match e {
+			Error::NotFound(_) => Self::NotFound,
+			Error::NoNetwork(_) => Self::NoNetwork,
+			Error::NoAccess(_) => Self::NoAccess,
+			Error::BadOperation { .. } => Self::BadOperation,
+			Error::BadEncoding(_) => Self::BadEncoding,
+			Error::Locked { .. } => Self::Locked,
+			Error::Io(_) => Self::Io,
+			Error::BadDigest(_) => Self::BadDigest,
+			Error::Unlisted(_) => Self::Unlisted,
+			Error::RolledBack { .. } => Self::RolledBack,
+			Error::Conflict { .. } => Self::Conflict,
+		}
+	}
+}
+
+/// Bytes owned by the app once returned; release them with qvault_buf_free.
+#[repr(C)]
+pub struct Buf {
+	pub ptr: *mut u8,
+	pub len: usize,
+}
+
+impl From<Vec<u8>> for Buf {
+	fn from(bytes: Vec<u8>) -> Self {
+		let len = bytes.len();
+
+		Self {
+			ptr: Box::into_raw(bytes.into_boxed_slice()) as *mut u8,
+			len,
+		}
+	}
+}
+
+/// A node of a DirInfo, released along with it.
+#[repr(C)]
+pub struct NodeInfo {
+	pub id: u64,
+	pub created_at: u64,
+	pub size: u64,
+	pub is_dir: bool,
+	pub name: *mut c_char,
+	/// Null for dirs and files with no extension.
+	pub ext: *mut c_char,
+}
+
+impl From<NodeView> for NodeInfo {
+	fn from(view: NodeView) -> Self {
+		Self {
+			id: u64::from_be_bytes(view.id().as_bytes()),
+			created_at: view.created_at(),
+			size: view.size(),
+			is_dir: view.is_dir(),
+			name: c_string(view.name()),
+			ext: view.ext().map_or(ptr::null_mut(), c_string),
+		}
+	}
+}
+
+/// A listed dir, owned by the app once returned; release it with qvault_dir_free.
+#[repr(C)]
+pub struct DirInfo {
+	pub name: *mut c_char,
+	pub items: *mut NodeInfo,
+	pub items_len: usize,
+	/// Grand-grandparent, grandparent, parent.
+	pub breadcrumbs: *mut NodeInfo,
+	pub breadcrumbs_len: usize,
+}
+
+impl From<DirView> for DirInfo {
+	fn from(view: DirView) -> Self {
+		let (items, items_len) = node_infos(view.items());
+		let (breadcrumbs, breadcrumbs_len) = node_infos(view.breadcrumbs());
+
+		Self {
+			name: c_string(view.name()),
+			items,
+			items_len,
+			breadcrumbs,
+			breadcrumbs_len,
+		}
+	}
+}
+
+// what the network callback is asked to do, json-encoded
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Request {
+	Fetch(Query),
+	Mutate(Box<Mutation>),
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Query {
+	// -> [LockedNode]
+	Subtree(Uid),
+	// -> [Digest]
+	Digests(Uid),
+	// -> the chunk as is, not encoded
+	Chunk { file_id: Uid, chunk_idx: u64 },
+	// -> Changes
+	Changes(Cursor),
+	// -> [LockedShare]
+	Shares(Uid),
+	// -> [InviteIntent]
+	InviteIntents(Uid),
+}
+
+impl Request {
+	// what an error the callback returns is about, eg the node that wasn't found
+	fn id(&self) -> Uid {
+		match self {
+			Request::Fetch(query) => match *query {
+				Query::Subtree(id)
+				| Query::Digests(id)
+				| Query::Chunk { file_id: id, .. }
+				| Query::Shares(id)
+				| Query::InviteIntents(id) => id,
+				Query::Changes(_) => Uid::new(ROOT_ID),
+			},
+			Request::Mutate(mutation) => match **mutation {
+				Mutation::UploadChunk { file_id, .. } | Mutation::UploadDigests { file_id, .. } => {
+					file_id
+				}
+				Mutation::SaveNode { ref node, .. } => node.id,
+				Mutation::CopyFile { from, .. } => from,
+				Mutation::DeleteNode(id) => id,
+				Mutation::UploadShare(ref share) => share.export.receiver,
+				Mutation::PostInviteIntent(ref intent) => intent.user_id,
+				Mutation::FinishInviteIntent(ref finish) => finish.share.export.receiver,
+				Mutation::SaveMigrated(ref migrated) => migrated.user_id,
+			},
+		}
+	}
+}
+
+/// Filled by the network callback with qvault_response_set; owned by the library.
+pub struct Response(Vec<u8>);
+
+/// The app's side of Network.
+///
+/// call is to perform the json-encoded request and, if it succeeds, set the response; it's
+/// expected to return, as a u32, Ok, Conflict (with the stored node as the response), NotFound,
+/// NoAccess, BadOperation or BadEncoding, if the server rejects the request, Io, if it fails to
+/// store it, or NoNetwork; any other status is taken as NoNetwork. req and resp are only valid for
+/// the duration of the call.
+///
+/// Once qvault_protocol_new or qvault_protocol_unlock succeeds, the protocol owns ctx and calls
+/// release, if set, with it when freed; should they fail, ctx remains the app's to release.
+#[repr(C)]
+pub struct Net {
+	pub ctx: *mut c_void,
+	pub call: unsafe extern "C" fn(
+		ctx: *mut c_void,
+		req: *const u8,
+		req_len: usize,
+		resp: *mut Response,
+	) -> u32,
+	pub release: Option<unsafe extern "C" fn(ctx: *mut c_void)>,
+}
+
+impl Net {
+	fn call(&self, req: Request) -> Result<Vec<u8>, Error> {
+		let id = req.id();
+		let req = wire::encode(&req, Format::Json);
+		let mut resp = Response(Vec::new());
+		let status = unsafe { (self.call)(self.ctx, req.as_ptr(), req.len(), &mut resp) };
+
+		match Status::try_from(status) {
+			Ok(Status::Ok) => Ok(resp.0),
+			Ok(Status::Conflict) => {
+				let theirs: LockedNode = wire::decode(&resp.0).map_err(Error::BadEncoding)?;
+
+				Err(Error::Conflict {
+					id: theirs.id,
+					theirs: Box::new(theirs),
+				})
+			}
+			Ok(Status::NotFound) => Err(Error::NotFound(id)),
+			Ok(Status::NoAccess) => Err(Error::NoAccess(id)),
+			Ok(Status::BadOperation) => Err(Error::BadOperation {
+				id,
+				reason: "rejected by the server",
+			}),
+			Ok(Status::BadEncoding) => Err(Error::BadEncoding(wire::Error::BadEncoding(
+				Cause::default(),
+			))),
+			Ok(Status::Io) => Err(Error::Io("failed on the server".to_string())),
+			Ok(status) => Err(Error::NoNetwork(format!("{:?}", status))),
+			Err(n) => Err(Error::NoNetwork(format!("unknown status {}", n))),
+		}
+	}
+
+	fn fetch<T: serde::de::DeserializeOwned>(&self, query: Query) -> Result<T, Error> {
+		wire::decode(&self.call(Request::Fetch(query))?).map_err(Error::BadEncoding)
+	}
+
+	fn mutate(&self, mutation: Mutation) -> Result<(), Error> {
+		self.call(Request::Mutate(Box::new(mutation))).map(|_| ())
+	}
+}
+
+// a Net owned by a protocol; started is set only once the protocol is, so that a failed start
+// leaves ctx to the app
+struct Owned {
+	net: Net,
+	started: Rc<Cell<bool>>,
+}
+
+impl Drop for Owned {
+	fn drop(&mut self) {
+		if let (true, Some(release)) = (self.started.get(), self.net.release) {
+			unsafe { release(self.net.ctx) };
+		}
+	}
+}
+
+fn start(
+	net: Net,
+	f: impl FnOnce(Box<dyn Network>) -> Result<Protocol, Error>,
+) -> Result<Protocol, Error> {
+	let started = Rc::new(Cell::new(false));
+	let res = f(Box::new(Owned {
+		net,
+		started: started.clone(),
+	}));
+
+	started.set(res.is_ok());
+
+	res
+}
+
+#[async_trait(?Send)]
+impl Network for Owned {
+	async fn fetch_subtree(&self, id: Uid) -> Result<Vec<LockedNode>, Error> {
+		self.net.fetch(Query::Subtree(id))
+	}
+
+	async fn upload_chunk(&self, file_id: Uid, chunk_idx: u64, ct: &[u8]) -> Result<(), Error> {
+		self.net.mutate(Mutation::UploadChunk {
+			file_id,
+			chunk_idx,
+			ct: ct.to_vec(),
+		})
+	}
+
+	async fn upload_digests(&self, file_id: Uid, digests: &[hmac::Digest]) -> Result<(), Error> {
+		self.net.mutate(Mutation::UploadDigests {
+			file_id,
+			digests: digests.to_vec(),
+		})
+	}
+
+	async fn fetch_digests(&self, file_id: Uid) -> Result<Vec<hmac::Digest>, Error> {
+		self.net.fetch(Query::Digests(file_id))
+	}
+
+	async fn fetch_chunk(&self, file_id: Uid, chunk_idx: u64) -> Result<Vec<u8>, Error> {
+		self.net
+			.call(Request::Fetch(Query::Chunk { file_id, chunk_idx }))
+	}
+
+	async fn save_node(&self, node: &LockedNode, base: Option<&hmac::Digest>) -> Result<(), Error> {
+		self.net.mutate(Mutation::SaveNode {
+			node: node.clone(),
+			base: base.cloned(),
+		})
+	}
+
+	async fn copy_file(&self, from: Uid, to: Uid) -> Result<(), Error> {
+		self.net.mutate(Mutation::CopyFile { from, to })
+	}
+
+	async fn delete_node(&self, id: Uid) -> Result<(), Error> {
+		self.net.mutate(Mutation::DeleteNode(id))
+	}
+
+	async fn fetch_changes(&self, since: Cursor) -> Result<Changes, Error> {
+		self.net.fetch(Query::Changes(since))
+	}
+
+	async fn upload_share(&self, share: &LockedShare) -> Result<(), Error> {
+		self.net.mutate(Mutation::UploadShare(share.clone()))
+	}
+
+	async fn fetch_shares(&self, user_id: Uid) -> Result<Vec<LockedShare>, Error> {
+		self.net.fetch(Query::Shares(user_id))
+	}
+
+	async fn post_invite_intent(&self, intent: &InviteIntent) -> Result<(), Error> {
+		self.net.mutate(Mutation::PostInviteIntent(intent.clone()))
+	}
+
+	async fn fetch_invite_intents(&self, sender_id: Uid) -> Result<Vec<InviteIntent>, Error> {
+		self.net.fetch(Query::InviteIntents(sender_id))
+	}
+
+	async fn finish_invite_intent(&self, finish: &FinishInviteIntent) -> Result<(), Error> {
+		self.net
+			.mutate(Mutation::FinishInviteIntent(finish.clone()))
+	}
+
+	async fn save_migrated(&self, migrated: &Migrated) -> Result<(), Error> {
+		self.net.mutate(Mutation::SaveMigrated(migrated.clone()))
+	}
+}
+
+fn c_string(s: String) -> *mut c_char {
+	// names are user input, so a nul would otherwise truncate them silently
+	CString::new(s.replace('\0', "\u{fffd}"))
+		.unwrap()
+		.into_raw()
+}
+
+fn node_infos(views: Vec<NodeView>) -> (*mut NodeInfo, usize) {
+	let infos: Vec<NodeInfo> = views.into_iter().map(NodeInfo::from).collect();
+	let len = infos.len();
+
+	(
+		Box::into_raw(infos.into_boxed_slice()) as *mut NodeInfo,
+		len,
+	)
+}
+
+unsafe fn free_node_infos(ptr: *mut NodeInfo, len: usize) {
+	for info in Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len)).iter() {
+		drop(CString::from_raw(info.name));
+
+		if !info.ext.is_null() {
+			drop(CString::from_raw(info.ext));
+		}
+	}
+}
+
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+	if len == 0 {
+		&[]
+	} else {
+		slice::from_raw_parts(ptr, len)
+	}
+}
+
+unsafe fn put<T>(out: *mut T, res: Result<T, Error>) -> Status {
+	match res {
+		Ok(val) => {
+			*out = val;
+
+			Status::Ok
+		}
+		Err(e) => Status::from(&e),
+	}
+}
+
+// a panic must not unwind into the app
+fn catch(f: impl FnOnce() -> Status) -> Status {
+	panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or(Status::Panic)
+}
+
+unsafe fn put_dir(out: *mut *mut DirInfo, res: Result<DirView, Error>) -> Status {
+	put(
+		out,
+		res.map(|view| Box::into_raw(Box::new(DirInfo::from(view)))),
+	)
+}
+
+/// Starts a protocol for a regular user; ident_priv is a wire-encoded identity::Private, locked a
+/// wire-encoded LockedUser. On success, out is to be passed to qvault_protocol_migrate and
+/// released with qvault_protocol_free; see Net for who releases net.
+#[no_mangle]
+pub unsafe extern "C" fn qvault_protocol_new(
+	ident_priv: *const u8,
+	ident_priv_len: usize,
+	locked: *const u8,
+	locked_len: usize,
+	net: Net,
+	out: *mut *mut Protocol,
+) -> Status {
+	catch(|| {
+		if out.is_null() {
+			return Status::BadArgument;
+		}
+
+		let res = wire::decode::<identity::Private>(bytes(ident_priv, ident_priv_len))
+			.map_err(Error::BadEncoding)
+			.and_then(|private| {
+				start(net, |net| {
+					Protocol::new_with_env(
+						private,
+						bytes(locked, locked_len),
+						net,
+						Rc::new(Env::system()),
+					)
+				})
+			});
+
+		put(out, res.map(|p| Box::into_raw(Box::new(p))))
+	})
+}
+
+/// Starts a protocol for an admin, whose private key is locked with a password; see
+/// Protocol::unlock. On success, out is to be passed to qvault_protocol_migrate and released with
+/// qvault_protocol_free; see Net for who releases net.
+#[no_mangle]
+pub unsafe extern "C" fn qvault_protocol_unlock(
+	locked: *const u8,
+	locked_len: usize,
+	pass: *const c_char,
+	net: Net,
+	out: *mut *mut Protocol,
+) -> Status {
+	catch(|| {
+		if out.is_null() || pass.is_null() {
+			return Status::BadArgument;
+		}
+
+		let Ok(pass) = CStr::from_ptr(pass).to_str() else {
+			return Status::BadArgument;
+		};
+		let res = start(net, |net| {
+			Protocol::unlock(bytes(locked, locked_len), pass, net)
+		});
+
+		put(out, res.map(|p| Box::into_raw(Box::new(p))))
+	})
+}
+
+/// Saves what qvault_protocol_new or qvault_protocol_unlock found in outdated layouts: own nodes,
+/// received shares and the password lock; see Protocol::migrate. To be called once started, and
+/// again later, should it fail; on success, out is set to how many were saved.
+#[no_mangle]
+pub unsafe extern "C" fn qvault_protocol_migrate(
+	protocol: *mut Protocol,
+	out: *mut usize,
+) -> Status {
+	catch(|| match protocol.as_mut() {
+		Some(protocol) if !out.is_null() => put(out, block_on(protocol.migrate())),
+		_ => Status::BadArgument,
+	})
+}
+
+/// Releases a protocol along with its Net; protocol may be null.
+#[no_mangle]
+pub unsafe extern "C" fn qvault_protocol_free(protocol: *mut Protocol) {
+	catch(|| {
+		if !protocol.is_null() {
+			drop(Box::from_raw(protocol));
+		}
+
+		Status::Ok
+	});
+}
+
+/// Lists the current dir; on success, out is to be released with qvault_dir_free.
+#[no_mangle]
+pub unsafe extern "C" fn qvault_ls_cur(protocol: *mut Protocol, out: *mut *mut DirInfo) -> Status {
+	catch(|| match protocol.as_mut() {
+		Some(protocol) if !out.is_null() => put_dir(out, block_on(protocol.ls_cur_mut())),
+		_ => Status::BadArgument,
+	})
+}
+
+/// Enters a dir and lists it; on success, out is to be released with qvault_dir_free.
+#[no_mangle]
+pub unsafe extern "C" fn qvault_cd_to_dir(
+	protocol: *mut Protocol,
+	id: u64,
+	out: *mut *mut DirInfo,
+) -> Status {
+	catch(|| match protocol.as_mut() {
+		Some(protocol) if !out.is_null() => {
+			put_dir(out, block_on(protocol.cd_to_dir(&Uid::new(id))))
+		}
+		_ => Status::BadArgument,
+	})
+}
+
+/// Leaves the current dir and lists its parent; on success, out is to be released with
+/// qvault_dir_free.
+#[no_mangle]
+pub unsafe extern "C" fn qvault_go_back(protocol: *mut Protocol, out: *mut *mut DirInfo) -> Status {
+	catch(|| match protocol.as_mut() {
+		Some(protocol) if !out.is_null() => put_dir(out, block_on(protocol.go_back())),
+		_ => Status::BadArgument,
+	})
+}
+
+/// Fetches a chunk through the network callback and decrypts it; on success, out is to be released
+/// with qvault_buf_free.
+#[no_mangle]
+pub unsafe extern "C" fn qvault_download_chunk(
+	protocol: *mut Protocol,
+	file_id: u64,
+	chunk_idx: u64,
+	out: *mut Buf,
+) -> Status {
+	catch(|| match protocol.as_mut() {
+		Some(protocol) if !out.is_null() => put(
+			out,
+			block_on(protocol.download_chunk(Uid::new(file_id), chunk_idx)).map(Buf::from),
+		),
+		_ => Status::BadArgument,
+	})
+}
+
+/// Decrypts a chunk fetched by the app itself, eg streamed or cached; ct remains the app's. On
+/// success, out is to be released with qvault_buf_free.
+#[no_mangle]
+pub unsafe extern "C" fn qvault_decrypt_chunk(
+	protocol: *mut Protocol,
+	file_id: u64,
+	chunk_idx: u64,
+	ct: *const u8,
+	ct_len: usize,
+	out: *mut Buf,
+) -> Status {
+	catch(|| match protocol.as_mut() {
+		Some(protocol) if !out.is_null() => put(
+			out,
+			block_on(protocol.chunk_decrypt_for_file(
+				bytes(ct, ct_len),
+				&Uid::new(file_id),
+				chunk_idx,
+			))
+			.map(Buf::from),
+		),
+		_ => Status::BadArgument,
+	})
+}
+
+/// Copies bytes into a response, so ptr remains the app's; to be called by the network callback.
+#[no_mangle]
+pub unsafe extern "C" fn qvault_response_set(resp: *mut Response, ptr: *const u8, len: usize) {
+	catch(|| {
+		if let Some(resp) = resp.as_mut() {
+			resp.0 = bytes(ptr, len).to_vec();
+		}
+
+		Status::Ok
+	});
+}
+
+/// Releases a dir returned by the library, its nodes included; dir may be null.
+#[no_mangle]
+pub unsafe extern "C" fn qvault_dir_free(dir: *mut DirInfo) {
+	catch(|| {
+		if !dir.is_null() {
+			let dir = Box::from_raw(dir);
+
+			drop(CString::from_raw(dir.name));
+			free_node_infos(dir.items, dir.items_len);
+			free_node_infos(dir.breadcrumbs, dir.breadcrumbs_len);
+		}
+
+		Status::Ok
+	});
+}
+
+/// Releases bytes returned by the library.
+#[no_mangle]
+pub unsafe extern "C" fn qvault_buf_free(buf: Buf) {
+	catch(|| {
+		if !buf.ptr.is_null() {
+			drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+				buf.ptr, buf.len,
+			)));
+		}
+
+		Status::Ok
+	});
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{
+		ffi::{c_void, CString},
+		ptr, slice,
+	};
+
+	use futures::{executor::block_on, FutureExt};
+	use serde::Deserialize;
+
+	use super::{
+		qvault_buf_free, qvault_cd_to_dir, qvault_dir_free, qvault_download_chunk, qvault_ls_cur,
+		qvault_protocol_free, qvault_protocol_migrate, qvault_protocol_unlock, qvault_response_set,
+		Buf, DirInfo, Net, Response, Status,
+	};
+	use crate::{
+		backend::Backend,
+		cache::Mutation,
+		fixtures, hmac,
+		id::Uid,
+		password_lock,
+		protocol::{Error, Network, Protocol},
+		seeds::ROOT_ID,
+		sync::Cursor,
+		wire::{self, Format},
+	};
+
+	#[derive(Deserialize)]
+	#[serde(rename_all = "snake_case")]
+	enum Request {
+		Fetch(Query),
+		Mutate(Box<Mutation>),
+	}
+
+	#[derive(Deserialize)]
+	#[serde(rename_all = "snake_case")]
+	enum Query {
+		Subtree(Uid),
+		Digests(Uid),
+		Chunk { file_id: Uid, chunk_idx: u64 },
+		Changes(Cursor),
+		Shares(Uid),
+		InviteIntents(Uid),
+	}
+
+	// what an app would do: pass requests on to its backend; Backend's futures are ready right away,
+	// and block_on can't be nested anyway
+	unsafe extern "C" fn call(
+		ctx: *mut c_void,
+		req: *const u8,
+		req_len: usize,
+		resp: *mut Response,
+	) -> u32 {
+		let backend = &*(ctx as *const Backend);
+		let req: Request = wire::decode(slice::from_raw_parts(req, req_len)).unwrap();
+		let res = match req {
+			Request::Fetch(query) => async {
+				match query {
+					Query::Subtree(id) => backend
+						.fetch_subtree(id)
+						.await
+						.map(|r| wire::encode(&r, Format::Json)),
+					Query::Digests(id) => backend
+						.fetch_digests(id)
+						.await
+						.map(|r| wire::encode(&r, Format::Json)),
+					Query::Chunk { file_id, chunk_idx } => {
+						backend.fetch_chunk(file_id, chunk_idx).await
+					}
+					Query::Changes(since) => backend
+						.fetch_changes(since)
+						.await
+						.map(|r| wire::encode(&r, Format::Json)),
+					Query::Shares(id) => backend
+						.fetch_shares(id)
+						.await
+						.map(|r| wire::encode(&r, Format::Json)),
+					Query::InviteIntents(id) => backend
+						.fetch_invite_intents(id)
+						.await
+						.map(|r| wire::encode(&r, Format::Json)),
+				}
+			}
+			.now_or_never()
+			.unwrap(),
+			Request::Mutate(mutation) => mutation
+				.apply(backend)
+				.now_or_never()
+				.unwrap()
+				.map(|_| Vec::new()),
+		};
+
+		match res {
+			Ok(bytes) => {
+				qvault_response_set(resp, bytes.as_ptr(), bytes.len());
+
+				Status::Ok as u32
+			}
+			Err(Error::Conflict { theirs, .. }) => {
+				let bytes = wire::encode(&*theirs, Format::Json);
+
+				qvault_response_set(resp, bytes.as_ptr(), bytes.len());
+
+				Status::Conflict as u32
+			}
+			Err(e) => Status::from(&e) as u32,
+		}
+	}
+
+	unsafe extern "C" fn release(ctx: *mut c_void) {
+		drop(Box::from_raw(ctx as *mut Backend));
+	}
+
+	fn net(backend: &Backend) -> Net {
+		Net {
+			ctx: Box::into_raw(Box::new(backend.clone())) as *mut c_void,
+			call,
+			release: Some(release),
+		}
+	}
+
+	#[test]
+	fn test_unlock_ls_and_download() {
+		let backend = Backend::new();
+		let (god, mut locked) = fixtures::god();
+
+		locked.encrypted_priv = Some(password_lock::lock(god.private(), "pass").unwrap());
+		backend.register(locked).unwrap();
+
+		let locked = wire::encode(&backend.locked_user(god.id()).unwrap(), Format::Json);
+		let mut protocol = Protocol::unlock(&locked, "pass", Box::new(backend.clone())).unwrap();
+		let docs = block_on(protocol.mkdir(Uid::new(ROOT_ID), "docs")).unwrap();
+		let file =
+			block_on(protocol.upload_file(docs.id(), "a", "txt", 3, &mut &b"abc"[..])).unwrap();
+		let docs_id = u64::from_be_bytes(docs.id().as_bytes());
+		let file_id = u64::from_be_bytes(file.id().as_bytes());
+
+		unsafe {
+			let locked = wire::encode(&backend.locked_user(god.id()).unwrap(), Format::Json);
+			let mut protocol = ptr::null_mut();
+			let wrong = CString::new("wrong").unwrap();
+			let pass = CString::new("pass").unwrap();
+			let failed = net(&backend);
+			let ctx = failed.ctx;
+
+			assert_eq!(
+				qvault_protocol_unlock(
+					locked.as_ptr(),
+					locked.len(),
+					wrong.as_ptr(),
+					failed,
+					&mut protocol
+				),
+				Status::NoAccess
+			);
+			// still the app's
+			release(ctx);
+			assert_eq!(
+				qvault_protocol_unlock(
+					locked.as_ptr(),
+					locked.len(),
+					pass.as_ptr(),
+					net(&backend),
+					&mut protocol
+				),
+				Status::Ok
+			);
+
+			let mut migrated = usize::MAX;
+
+			// nothing's outdated
+			assert_eq!(qvault_protocol_migrate(protocol, &mut migrated), Status::Ok);
+			assert_eq!(migrated, 0);
+			assert_eq!(
+				qvault_protocol_migrate(protocol, ptr::null_mut()),
+				Status::BadArgument
+			);
+
+			let mut dir: *mut DirInfo = ptr::null_mut();
+
+			assert_eq!(qvault_ls_cur(protocol, &mut dir), Status::Ok);
+			assert_eq!((*dir).items_len, 1);
+			assert_eq!((*(*dir).items).id, docs_id);
+			assert!((*(*dir).items).is_dir);
+			qvault_dir_free(dir);
+
+			assert_eq!(qvault_cd_to_dir(protocol, docs_id, &mut dir), Status::Ok);
+			assert_eq!((*dir).items_len, 1);
+			assert_eq!((*dir).breadcrumbs_len, 1);
+			assert_eq!(
+				std::ffi::CStr::from_ptr((*(*dir).items).ext).to_str(),
+				Ok("txt")
+			);
+			qvault_dir_free(dir);
+
+			let mut buf = Buf {
+				ptr: ptr::null_mut(),
+				len: 0,
+			};
+
+			assert_eq!(
+				qvault_download_chunk(protocol, file_id, 0, &mut buf),
+				Status::Ok
+			);
+			assert_eq!(slice::from_raw_parts(buf.ptr, buf.len), b"abc");
+			qvault_buf_free(buf);
+
+			// out of range, as the server tells
+			assert_eq!(
+				qvault_download_chunk(protocol, file_id, 1, &mut Buf::from(Vec::new())),
+				Status::NotFound
+			);
+			qvault_protocol_free(protocol);
+		}
+
+		// the digests went through as well
+		assert_eq!(
+			block_on(backend.fetch_digests(file.id())).map(|d: Vec<hmac::Digest>| d.len()),
+			Ok(1)
+		);
+	}
+
+	#[test]
+	fn test_callback_status() {
+		// returns whatever ctx points to
+		unsafe extern "C" fn call(
+			ctx: *mut c_void,
+			_: *const u8,
+			_: usize,
+			_: *mut Response,
+		) -> u32 {
+			*(ctx as *const u32)
+		}
+
+		let mut status = 0u32;
+		let net = Net {
+			ctx: &mut status as *mut u32 as *mut c_void,
+			call,
+			release: None,
+		};
+		let mut call_with = |n: u32| {
+			status = n;
+
+			net.call(super::Request::Fetch(super::Query::Shares(Uid::new(1))))
+		};
+
+		assert_eq!(
+			call_with(Status::NotFound as u32),
+			Err(Error::NotFound(Uid::new(1)))
+		);
+		assert_eq!(
+			call_with(Status::NoAccess as u32),
+			Err(Error::NoAccess(Uid::new(1)))
+		);
+		assert!(matches!(
+			call_with(Status::BadOperation as u32),
+			Err(Error::BadOperation { id, .. }) if id == Uid::new(1)
+		));
+		assert!(matches!(call_with(Status::Io as u32), Err(Error::Io(_))));
+		// not the server's to return
+		assert_eq!(
+			call_with(Status::Panic as u32),
+			Err(Error::NoNetwork("Panic".to_string()))
+		);
+		assert_eq!(
+			call_with(99),
+			Err(Error::NoNetwork("unknown status 99".to_string()))
+		);
+		assert_eq!(Status::try_from(Status::Panic as u32), Ok(Status::Panic));
+	}
+}
@@ -0,0 +1,67 @@
+// accounts for tests: registered the way the cli does, with no password, and protocols logged in as
+// them, either through a server (see cli::Server) or any other network
+
+use std::rc::Rc;
+
+use crate::{
+	cli::Server,
+	env::Env,
+	id::Uid,
+	identity::Identity,
+	protocol::{Network, Protocol},
+	register::LockedUser,
+	sync::Cursor,
+	user::{User, GOD_ID},
+	vault::{FileSystem, LockedNode},
+	wire::{self, Format},
+};
+
+// no password, shares or intents
+pub(crate) fn locked_user(ident: &Identity, roots: Vec<LockedNode>) -> LockedUser {
+	LockedUser {
+		encrypted_priv: None,
+		_pub: ident.public().clone(),
+		shares: Vec::new(),
+		pending_invite_intents: Vec::new(),
+		roots,
+		cursor: Cursor::default(),
+	}
+}
+
+// a god with a root of its own, yet to be registered
+pub(crate) fn god() -> (Identity, LockedUser) {
+	let god = Identity::generate(Uid::new(GOD_ID));
+	let (_, root) = FileSystem::new(&User::fs_seed(god.private()), &god);
+	let locked = locked_user(&god, vec![root]);
+
+	(god, locked)
+}
+
+pub(crate) fn register(server: &dyn Server, ident: &Identity, roots: Vec<LockedNode>) {
+	server.register(locked_user(ident, roots)).unwrap();
+}
+
+pub(crate) fn register_god(server: &dyn Server) -> Identity {
+	let (god, locked) = god();
+
+	server.register(locked).unwrap();
+
+	god
+}
+
+pub(crate) fn login_with(ident: &Identity, locked: &LockedUser, net: Box<dyn Network>) -> Protocol {
+	Protocol::new_with_env(
+		ident.private().clone(),
+		&wire::encode(locked, Format::Json),
+		net,
+		Rc::new(Env::system()),
+	)
+	.unwrap()
+}
+
+// as served by server, eg on another device
+pub(crate) fn login(server: &dyn Server, ident: &Identity) -> Protocol {
+	let locked = server.locked_user(ident.id()).unwrap().unwrap();
+
+	login_with(ident, &locked, server.net())
+}
@@ -1,9 +1,17 @@
+use zeroize::{Zeroize, Zeroizing};
+
 use crate::hmac::{self, Digest};
 
 pub struct Hkdf {
 	prk: Digest,
 }
 
+impl Drop for Hkdf {
+	fn drop(&mut self) {
+		self.prk.0.zeroize();
+	}
+}
+
 impl Hkdf {
 	const EMPTY_KEY: [u8; hmac::Key::SIZE] = [0u8; hmac::Key::SIZE];
 
@@ -29,8 +37,8 @@ impl Hkdf {
 
 		let n = (LEN - 1) / Digest::SIZE + 1;
 
-		let mut res = Vec::<u8>::new();
-		let mut prev = Vec::<u8>::new();
+		let mut res = Zeroizing::new(Vec::<u8>::new());
+		let mut prev = Zeroizing::new(Vec::<u8>::new());
 
 		for i in 1..n + 1 {
 			let mut input = prev;
@@ -38,8 +46,11 @@ impl Hkdf {
 			input.extend(info);
 			input.push(i as u8);
 
-			prev = hmac::digest(&self.prk.into(), &input).as_bytes().to_vec();
-			res.extend(&prev);
+			let mut digest = hmac::digest(&self.prk.into(), &input);
+
+			prev = Zeroizing::new(digest.as_bytes().to_vec());
+			digest.0.zeroize();
+			res.extend(prev.iter());
 		}
 
 		res[..LEN].try_into().unwrap()
@@ -1,9 +1,18 @@
+use std::hash::{Hash, Hasher};
+
 use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
 use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
+
+use crate::base64_blobs::{deserialize_array_base64, serialize_array_base64};
+
+const DIGEST_SIZE: usize = 32;
 
 type HmacSha256 = Hmac<Sha256>;
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(Clone)]
 pub struct Key([u8; Self::SIZE]);
 
 impl Key {
@@ -18,17 +27,57 @@ impl Key {
 	}
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub struct Digest(pub [u8; Self::SIZE]);
+impl PartialEq for Key {
+	fn eq(&self, other: &Self) -> bool {
+		self.0.ct_eq(&other.0).into()
+	}
+}
+
+impl std::fmt::Debug for Key {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Key").finish_non_exhaustive()
+	}
+}
+
+impl Drop for Key {
+	fn drop(&mut self) {
+		self.0.zeroize();
+	}
+}
+
+// compared in constant time, since digests are mostly macs
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Digest(
+	#[serde(
+		serialize_with = "serialize_array_base64::<_, DIGEST_SIZE>",
+		deserialize_with = "deserialize_array_base64::<_, DIGEST_SIZE>"
+	)]
+	pub [u8; Self::SIZE],
+);
 
 impl Digest {
-	pub const SIZE: usize = 32;
+	pub const SIZE: usize = DIGEST_SIZE;
 
 	pub fn as_bytes(&self) -> &[u8; Self::SIZE] {
 		&self.0
 	}
 }
 
+impl PartialEq for Digest {
+	fn eq(&self, other: &Self) -> bool {
+		self.0.ct_eq(&other.0).into()
+	}
+}
+
+impl Eq for Digest {}
+
+// digests are hashed only to be looked up by, eg in Protocol::verifiers
+impl Hash for Digest {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.0.hash(state);
+	}
+}
+
 impl From<Digest> for Key {
 	fn from(digest: Digest) -> Self {
 		Self(digest.0)
@@ -1,12 +1,14 @@
 use std::{fmt, str::FromStr};
 
-use rand::{rngs::OsRng, Rng};
+use rand::rngs::OsRng;
 use serde::{
 	de::{self, Visitor},
 	Deserialize, Deserializer, Serialize, Serializer,
 };
 use sha2::{Digest, Sha256};
 
+use crate::env::Rng;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Uid(u64);
 
@@ -16,8 +18,11 @@ impl Uid {
 	}
 
 	pub fn generate() -> Self {
-		let mut rng = OsRng;
-		Self(rng.gen())
+		Self::generate_with(&mut OsRng)
+	}
+
+	pub fn generate_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
+		Self(rng.next_u64())
 	}
 
 	pub fn from_bytes(bytes: &[u8]) -> Self {
@@ -64,12 +69,22 @@ impl FromStr for Uid {
 	}
 }
 
+impl fmt::Display for Uid {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(&self.to_base64())
+	}
+}
+
 impl Serialize for Uid {
 	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
 	where
 		S: Serializer,
 	{
-		serializer.serialize_str(&self.to_base64())
+		if serializer.is_human_readable() {
+			serializer.serialize_str(&self.to_base64())
+		} else {
+			serializer.serialize_u64(self.0)
+		}
 	}
 }
 
@@ -93,9 +108,20 @@ impl<'de> Deserialize<'de> for Uid {
 			{
 				Ok(Uid::from_str(value).map_err(de::Error::custom)?)
 			}
+
+			fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+			where
+				E: de::Error,
+			{
+				Ok(Uid(value))
+			}
 		}
 
-		deserializer.deserialize_str(Base64Visitor)
+		if deserializer.is_human_readable() {
+			deserializer.deserialize_str(Base64Visitor)
+		} else {
+			deserializer.deserialize_u64(Base64Visitor)
+		}
 	}
 }
 
@@ -1,3 +1,4 @@
+use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 
@@ -5,10 +6,13 @@ use crate::{
 	aes_gcm,
 	base64_blobs::{deserialize_vec_base64, serialize_vec_base64},
 	ed25519::{KeyPairEd25519, PrivateKeyEd25519, PublicKeyEd25519, Signature},
+	env::Rng,
 	hmac,
 	id::Uid,
 	kyber::{self, KeyPairKyber, PrivateKeyKyber, PublicKeyKyber},
 	user::GOD_ID,
+	version::Versioned,
+	wire::{self, Format},
 	x448::{self, KeyPairX448, PrivateKeyX448, PublicKeyX448},
 };
 
@@ -18,24 +22,44 @@ pub struct Identity {
 	pub _pub: Public,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
 pub struct Private {
 	pub x448: PrivateKeyX448,
 	pub ed25519: PrivateKeyEd25519,
 	pub kyber: PrivateKeyKyber,
 }
 
+impl std::fmt::Debug for Private {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Private").finish_non_exhaustive()
+	}
+}
+
 #[derive(Debug)]
 pub enum Error {
 	BadKey,
+	UnsupportedVersion,
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Error::BadKey => "bad key",
+			Error::UnsupportedVersion => "unsupported version",
+		})
+	}
 }
 
+impl std::error::Error for Error {}
+
 impl Private {
 	pub fn decrypt(&self, ct: &Encrypted) -> Result<Vec<u8>, Error> {
+		ct.check_version().map_err(|_| Error::UnsupportedVersion)?;
+
 		let ecc = self.kyber.decrypt(&ct.ecc_ct).map_err(|_| Error::BadKey)?;
-		let ecc: x448::Encrypted = serde_json::from_slice(&ecc).map_err(|_| Error::BadKey)?;
+		let ecc: x448::Encrypted = wire::decode(&ecc).map_err(|_| Error::BadKey)?;
 		let aes = self.x448.decrypt(&ecc).map_err(|_| Error::BadKey)?;
-		let aes: aes_gcm::Aes = serde_json::from_slice(&aes).map_err(|_| Error::BadKey)?;
+		let aes: aes_gcm::Aes = wire::decode(&aes).map_err(|_| Error::BadKey)?;
 		let pt = aes.decrypt(&ct.ct).map_err(|_| Error::BadKey)?;
 
 		Ok(pt)
@@ -56,8 +80,11 @@ pub struct Public {
 	pub kyber: PublicKeyKyber,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Encrypted {
+	// see Versioned; 0 for payloads predating versioning
+	#[serde(default)]
+	pub version: u32,
 	// layer 0: aes-encrypted data
 	#[serde(
 		serialize_with = "serialize_vec_base64",
@@ -68,6 +95,36 @@ pub struct Encrypted {
 	ecc_ct: kyber::Encrypted,
 }
 
+impl Encrypted {
+	pub(crate) fn ct(&self) -> &[u8] {
+		&self.ct
+	}
+
+	// re-encrypts an outdated payload to its receiver in the current layout; None, if it is up to
+	// date
+	pub fn migrate<R: Rng + ?Sized>(
+		&self,
+		receiver: &Identity,
+		rng: &mut R,
+	) -> Result<Option<Self>, Error> {
+		if !self.is_outdated() {
+			return Ok(None);
+		}
+
+		let pt = receiver.private().decrypt(self)?;
+
+		Ok(Some(receiver.public().encrypt_serialized_with(&pt, rng)))
+	}
+}
+
+impl Versioned for Encrypted {
+	const VERSION: u32 = 1;
+
+	fn version(&self) -> u32 {
+		self.version
+	}
+}
+
 impl Public {
 	pub fn id(&self) -> Uid {
 		// id::from_bytes(&[self.x448.as_bytes(), self.ed25519.as_bytes().as_slice()].concat())
@@ -79,18 +136,48 @@ impl Public {
 	}
 
 	pub fn encrypt_serialized(&self, pt: &[u8]) -> Encrypted {
-		let aes = aes_gcm::Aes::new();
+		self.encrypt_serialized_with(pt, &mut OsRng)
+	}
+
+	pub fn encrypt_serialized_with<R: Rng + ?Sized>(&self, pt: &[u8], rng: &mut R) -> Encrypted {
+		self.encrypt_serialized_as(pt, Format::Json, rng)
+	}
+
+	// format applies to the inner layers only; pt is encrypted as is
+	pub fn encrypt_serialized_as<R: Rng + ?Sized>(
+		&self,
+		pt: &[u8],
+		format: Format,
+		rng: &mut R,
+	) -> Encrypted {
+		let aes = aes_gcm::Aes::generate_with(rng);
 		let ct = aes.encrypt(pt);
-		let aes_ct = self.x448.encrypt(&aes);
-		let ecc_ct = self.kyber.encrypt(aes_ct.clone());
+		let aes_ct = self
+			.x448
+			.encrypt_serialized_with(&wire::encode(&aes, format), rng);
+		let ecc_ct = self
+			.kyber
+			.encrypt_serialized_with(&wire::encode(&aes_ct, format), rng);
 
-		Encrypted { ct, ecc_ct }
+		Encrypted {
+			version: Encrypted::VERSION,
+			ct,
+			ecc_ct,
+		}
 	}
 
 	pub fn verify(&self, sig: &Signature, msg: &[u8]) -> bool {
 		self.ed25519.verify(msg, sig)
 	}
 
+	pub fn try_verify(
+		&self,
+		sig: &Signature,
+		msg: &[u8],
+	) -> Result<(), ed25519_dalek::SignatureError> {
+		self.ed25519.try_verify(msg, sig)
+	}
+
 	pub fn hash(&self) -> hmac::Digest {
 		let bytes = [
 			self.x448.as_bytes().as_slice(),
@@ -115,6 +202,24 @@ impl Public {
 
 		self.encrypt_serialized(&serialized)
 	}
+
+	pub fn encrypt_with<T, R>(&self, pt: T, rng: &mut R) -> Encrypted
+	where
+		T: Serialize,
+		R: Rng + ?Sized,
+	{
+		let serialized = serde_json::to_vec(&pt).unwrap();
+
+		self.encrypt_serialized_with(&serialized, rng)
+	}
+
+	pub fn encrypt_as<T, R>(&self, pt: T, format: Format, rng: &mut R) -> Encrypted
+	where
+		T: Serialize,
+		R: Rng + ?Sized,
+	{
+		self.encrypt_serialized_as(&wire::encode(&pt, format), format, rng)
+	}
 }
 
 impl Identity {
@@ -123,18 +228,22 @@ impl Identity {
 	}
 
 	pub fn generate(id: Uid) -> Self {
+		Self::generate_with(id, &mut OsRng)
+	}
+
+	pub fn generate_with<R: Rng + ?Sized>(id: Uid, rng: &mut R) -> Self {
 		let KeyPairX448 {
 			private: x448_priv,
 			public: x448_pub,
-		} = KeyPairX448::generate();
+		} = KeyPairX448::generate_with(rng);
 		let KeyPairEd25519 {
 			private: ed25519_priv,
 			public: ed25519_pub,
-		} = KeyPairEd25519::generate();
+		} = KeyPairEd25519::generate_with(rng);
 		let KeyPairKyber {
 			private: kyber_priv,
 			public: kyber_pub,
-		} = KeyPairKyber::generate();
+		} = KeyPairKyber::generate_with(rng);
 
 		Self {
 			_priv: Private {
@@ -162,8 +271,13 @@ impl Identity {
 
 #[cfg(test)]
 mod tests {
+	use rand::rngs::OsRng;
+
 	use super::Identity;
-	use crate::id::Uid;
+	use crate::{
+		id::Uid,
+		wire::{self, Format},
+	};
 
 	#[test]
 	fn test_encrypt_decrypt() {
@@ -175,6 +289,16 @@ mod tests {
 		assert_eq!(decrypted, msg);
 	}
 
+	#[test]
+	fn test_encrypt_decrypt_cbor() {
+		let ident = Identity::generate(Uid::new(0));
+		let msg = vec![1u8, 2, 3];
+		let encrypted = ident.public().encrypt_as(&msg, Format::Cbor, &mut OsRng);
+		let decrypted = ident.private().decrypt(&encrypted).unwrap();
+
+		assert_eq!(wire::decode::<Vec<u8>>(&decrypted).unwrap(), msg);
+	}
+
 	#[test]
 	fn test_sign_verify() {
 		let ident = Identity::generate(Uid::new(0));
@@ -192,4 +316,12 @@ mod tests {
 
 		assert_eq!(ident, deserialized);
 	}
+
+	#[test]
+	fn test_debug_does_not_leak_private() {
+		let ident = Identity::generate(Uid::new(0));
+
+		assert_eq!(format!("{:?}", ident.private()), "Private { .. }");
+		assert_eq!(ident.private(), &ident.private().clone());
+	}
 }
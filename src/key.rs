@@ -1,7 +1,49 @@
-// Accepts a type name, outputs a generic key type, eg PrivateKey<T, SIZE>, PublicKey<T, SIZE>, etc
+// Accepts a type name, outputs a generic key type, eg PrivateKey<T, SIZE>, PublicKey<T, SIZE>, etc;
+// `secret` keys are wiped on drop, compared in constant time and never printed
 macro_rules! key {
 	($type: ident) => {
-		#[derive(Debug, PartialEq)]
+		key!(@common $type);
+
+		impl<T, const SIZE: usize> std::fmt::Debug for $type<T, SIZE> {
+			fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+				f.debug_struct(stringify!($type))
+					.field("bytes", &self.bytes)
+					.finish()
+			}
+		}
+
+		impl<T, const SIZE: usize> PartialEq for $type<T, SIZE> {
+			fn eq(&self, other: &Self) -> bool {
+				self.bytes == other.bytes
+			}
+		}
+	};
+	($type: ident, secret) => {
+		key!(@common $type);
+
+		impl<T, const SIZE: usize> std::fmt::Debug for $type<T, SIZE> {
+			fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+				f.debug_struct(stringify!($type)).finish_non_exhaustive()
+			}
+		}
+
+		impl<T, const SIZE: usize> PartialEq for $type<T, SIZE> {
+			fn eq(&self, other: &Self) -> bool {
+				use subtle::ConstantTimeEq;
+
+				self.bytes.ct_eq(&other.bytes).into()
+			}
+		}
+
+		impl<T, const SIZE: usize> Drop for $type<T, SIZE> {
+			fn drop(&mut self) {
+				use zeroize::Zeroize;
+
+				self.bytes.zeroize();
+			}
+		}
+	};
+	(@common $type: ident) => {
 		pub struct $type<T, const SIZE: usize> {
 			bytes: [u8; SIZE],
 			_marker: std::marker::PhantomData<T>,
@@ -45,7 +87,11 @@ macro_rules! key {
 
 		impl<T, const SIZE: usize> serde::Serialize for $type<T, SIZE> {
 			fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-				serializer.serialize_str(&base64::encode(self.bytes))
+				if serializer.is_human_readable() {
+					serializer.serialize_str(&base64::encode(self.bytes))
+				} else {
+					serializer.serialize_bytes(&self.bytes)
+				}
 			}
 		}
 
@@ -68,13 +114,25 @@ macro_rules! key {
 						E: serde::de::Error,
 					{
 						let bytes = base64::decode(v).map_err(E::custom)?;
-						let bytes: [u8; SIZE] = bytes.as_slice().try_into().map_err(E::custom)?;
+
+						self.visit_bytes(&bytes)
+					}
+
+					fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+					where
+						E: serde::de::Error,
+					{
+						let bytes: [u8; SIZE] = v.try_into().map_err(E::custom)?;
 
 						Ok($type::new(bytes))
 					}
 				}
 
-				deserializer.deserialize_str(Visitor(std::marker::PhantomData))
+				if deserializer.is_human_readable() {
+					deserializer.deserialize_str(Visitor(std::marker::PhantomData))
+				} else {
+					deserializer.deserialize_bytes(Visitor(std::marker::PhantomData))
+				}
 			}
 		}
 	};
@@ -87,9 +145,11 @@ mod tests {
 	use super::key;
 
 	key!(Key);
+	key!(SecretKey, secret);
 	#[derive(Debug, PartialEq)]
 	struct KeyType;
 	type TestKey = Key<KeyType, 10>;
+	type TestSecretKey = SecretKey<KeyType, 10>;
 
 	#[test]
 	fn test_as_bytes() {
@@ -140,4 +200,24 @@ mod tests {
 
 		assert_eq!(key, deserialized);
 	}
+
+	#[test]
+	fn test_debug() {
+		let key = TestKey::new(b"0123456789".to_owned());
+		let secret = TestSecretKey::new(b"0123456789".to_owned());
+
+		assert!(format!("{:?}", key).contains("48, 49, 50"));
+		assert_eq!(format!("{:?}", secret), "SecretKey { .. }");
+	}
+
+	#[test]
+	fn test_secret_partial_eq() {
+		let k0 = TestSecretKey::new(b"0123456789".to_owned());
+		let k1 = k0.clone();
+		let k2 = TestSecretKey::new(b"0123456780".to_owned());
+
+		assert_eq!(k0, k1);
+		assert_ne!(k0, k2);
+		assert_eq!(k1.as_bytes(), b"0123456789");
+	}
 }
@@ -1,4 +1,4 @@
-use crate::{aes_gcm, hkdf};
+use crate::{aes_gcm, env::Rng, hkdf};
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 
@@ -14,6 +14,17 @@ pub enum Error {
 	WrongKey,
 }
 
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Error::BadCt => "bad ciphertext",
+			Error::WrongKey => "wrong key",
+		})
+	}
+}
+
+impl std::error::Error for Error {}
+
 #[derive(Debug, PartialEq)]
 pub struct KeyTypeKyber;
 
@@ -33,7 +44,7 @@ pub type KeyPairKyber = KP<KeyTypeKyber, { KeyTypeKyber::PRIV }, { KeyTypeKyber:
 pub type SharedKeyKyber = PrivateKey<KeyTypeKyber, { KeyTypeKyber::SHARED }>;
 pub type CiphertextKyber = PublicKey<KeyTypeKyber, { KeyTypeKyber::CT }>;
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Encrypted {
 	kyber_ct: CiphertextKyber,
 	ct: Vec<u8>,
@@ -47,10 +58,8 @@ fn aes_from_shared_key(ss: &SharedKeyKyber) -> aes_gcm::Aes {
 }
 
 impl PublicKeyKyber {
-	fn encapsulate(&self) -> (CiphertextKyber, SharedKeyKyber) {
-		let mut rng = OsRng;
-
-		let (ciphertext, shared) = pqc_kyber::encapsulate(self.as_bytes(), &mut rng).unwrap();
+	fn encapsulate<R: Rng + ?Sized>(&self, rng: &mut R) -> (CiphertextKyber, SharedKeyKyber) {
+		let (ciphertext, shared) = pqc_kyber::encapsulate(self.as_bytes(), &mut &mut *rng).unwrap();
 
 		(
 			CiphertextKyber::from(&ciphertext),
@@ -59,7 +68,11 @@ impl PublicKeyKyber {
 	}
 
 	pub fn encrypt_serialized(&self, pt: &[u8]) -> Encrypted {
-		let (kyber_ct, ss) = self.encapsulate();
+		self.encrypt_serialized_with(pt, &mut OsRng)
+	}
+
+	pub fn encrypt_serialized_with<R: Rng + ?Sized>(&self, pt: &[u8], rng: &mut R) -> Encrypted {
+		let (kyber_ct, ss) = self.encapsulate(rng);
 		let aes = aes_from_shared_key(&ss);
 		let ct = aes.encrypt(pt);
 
@@ -94,8 +107,11 @@ impl PrivateKeyKyber {
 
 impl KeyPairKyber {
 	pub fn generate() -> Self {
-		let mut rng = OsRng;
-		let pqc_kyber::Keypair { public, secret } = pqc_kyber::keypair(&mut rng).unwrap();
+		Self::generate_with(&mut OsRng)
+	}
+
+	pub fn generate_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
+		let pqc_kyber::Keypair { public, secret } = pqc_kyber::keypair(&mut &mut *rng).unwrap();
 
 		Self {
 			private: PrivateKeyKyber::from(&secret),
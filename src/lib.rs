@@ -1,8 +1,19 @@
 mod aes_gcm;
+mod archive;
+pub mod backend;
 mod base64_blobs;
+pub mod bulk;
+pub mod cache;
+pub mod cli;
 mod database;
+pub mod dir_store;
 pub mod ed25519;
 pub mod encrypted;
+pub mod env;
+pub mod error;
+pub mod ffi;
+#[cfg(test)]
+mod fixtures;
 mod hkdf;
 mod hmac;
 pub mod id;
@@ -10,13 +21,21 @@ pub mod identity;
 pub mod key;
 mod key_pair;
 pub mod kyber;
+mod merkle;
 mod password_lock;
 pub mod private_key;
 pub mod protocol;
 mod public_key;
 mod register;
+pub mod revision;
 mod salt;
+pub mod search;
 mod seeds;
+pub mod sync;
+#[cfg(test)]
+mod test_vectors;
 mod user;
 mod vault;
+pub mod version;
+pub mod wire;
 pub mod x448;
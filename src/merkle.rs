@@ -0,0 +1,145 @@
+use sha2::{Digest as _, Sha256};
+
+use crate::hmac::{self, Digest};
+
+// a leaf is keyed with a file-specific key, so that plaintext hashes leak nothing to the server;
+// idx binds a chunk to its position
+pub fn leaf(key: &hmac::Key, idx: u64, pt: &[u8]) -> Digest {
+	hmac::digest(key, &[idx.to_be_bytes().as_slice(), pt].concat())
+}
+
+fn node(left: &Digest, right: &Digest) -> Digest {
+	// the prefix separates inner nodes from leaves
+	let sha = Sha256::digest([[1u8].as_slice(), left.as_bytes(), right.as_bytes()].concat());
+
+	Digest(sha.into())
+}
+
+// an odd node is promoted to the next level as is
+pub fn root(leaves: &[Digest]) -> Digest {
+	let mut level = leaves.to_vec();
+
+	if level.is_empty() {
+		return Digest(Sha256::digest([]).into());
+	}
+
+	while level.len() > 1 {
+		level = level
+			.chunks(2)
+			.map(|pair| match pair {
+				[left, right] => node(left, right),
+				[single] => *single,
+				_ => unreachable!(),
+			})
+			.collect();
+	}
+
+	level[0]
+}
+
+// validates chunks one by one against a list of leaves committed to by a signed root; the list
+// itself comes from an untrusted source, so it is checked against the root first
+#[derive(Debug, Clone)]
+pub struct Verifier {
+	key: hmac::Key,
+	leaves: Vec<Digest>,
+	verified: Vec<bool>,
+}
+
+impl Verifier {
+	pub fn new(key: hmac::Key, root: &Digest, leaves: Vec<Digest>, count: u64) -> Option<Self> {
+		if leaves.len() as u64 == count && self::root(&leaves) == *root {
+			let verified = vec![false; leaves.len()];
+
+			Some(Self {
+				key,
+				leaves,
+				verified,
+			})
+		} else {
+			None
+		}
+	}
+
+	pub fn verify(&mut self, idx: u64, pt: &[u8]) -> bool {
+		if let Some(expected) = self.leaves.get(idx as usize) {
+			if leaf(&self.key, idx, pt) == *expected {
+				self.verified[idx as usize] = true;
+
+				return true;
+			}
+		}
+
+		false
+	}
+
+	// true once every chunk has been verified at least once
+	pub fn is_complete(&self) -> bool {
+		self.verified.iter().all(|&v| v)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{leaf, root, Verifier};
+	use crate::hmac::{self, Digest};
+
+	fn leaves(key: &hmac::Key, chunks: &[&[u8]]) -> Vec<Digest> {
+		chunks
+			.iter()
+			.enumerate()
+			.map(|(idx, c)| leaf(key, idx as u64, c))
+			.collect()
+	}
+
+	#[test]
+	fn test_root_depends_on_order_and_content() {
+		let key = hmac::Key::new([1u8; hmac::Key::SIZE]);
+		let r0 = root(&leaves(&key, &[b"a", b"b", b"c"]));
+
+		assert_eq!(r0, root(&leaves(&key, &[b"a", b"b", b"c"])));
+		assert_ne!(r0, root(&leaves(&key, &[b"b", b"a", b"c"])));
+		assert_ne!(r0, root(&leaves(&key, &[b"a", b"b"])));
+		assert_ne!(r0, root(&leaves(&key, &[b"a", b"b", b"d"])));
+		assert_ne!(
+			r0,
+			root(&leaves(
+				&hmac::Key::new([2u8; hmac::Key::SIZE]),
+				&[b"a", b"b", b"c"]
+			))
+		);
+	}
+
+	#[test]
+	fn test_single_leaf_is_root() {
+		let key = hmac::Key::new([1u8; hmac::Key::SIZE]);
+		let leaves = leaves(&key, &[b"a"]);
+
+		assert_eq!(root(&leaves), leaves[0]);
+	}
+
+	#[test]
+	fn test_verifier() {
+		let key = hmac::Key::new([1u8; hmac::Key::SIZE]);
+		let chunks: [&[u8]; 5] = [b"0", b"1", b"2", b"3", b"4"];
+		let leaves = leaves(&key, &chunks);
+		let root = root(&leaves);
+
+		assert!(Verifier::new(key.clone(), &root, leaves[..4].to_vec(), 4).is_none());
+		assert!(Verifier::new(key.clone(), &root, leaves.clone(), 4).is_none());
+
+		let mut verifier = Verifier::new(key, &root, leaves, 5).unwrap();
+
+		// out of order is fine
+		assert!(verifier.verify(3, b"3"));
+		assert!(!verifier.verify(3, b"2"));
+		assert!(!verifier.verify(5, b"5"));
+		assert!(!verifier.is_complete());
+
+		for (idx, chunk) in chunks.iter().enumerate() {
+			assert!(verifier.verify(idx as u64, chunk));
+		}
+
+		assert!(verifier.is_complete());
+	}
+}
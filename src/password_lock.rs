@@ -2,23 +2,48 @@
 // any data + eph_pass -> encrypted
 
 use argon2::{Config, ThreadMode, Variant, Version};
+use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 
 use crate::{
 	aes_gcm,
 	base64_blobs::{deserialize_vec_base64, serialize_vec_base64},
 	encrypted::Encrypted,
+	env::Rng,
 	hkdf, hmac,
 	salt::Salt,
+	version::Versioned,
 };
 
+impl Versioned for Lock {
+	const VERSION: u32 = 1;
+
+	fn version(&self) -> u32 {
+		self.version
+	}
+}
+
 #[derive(Debug)]
 pub enum Error {
 	Argon2Failed,
 	WrongKey,
 	BadJson,
+	UnsupportedVersion,
 }
 
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Error::Argon2Failed => "argon2 failed",
+			Error::WrongKey => "wrong password",
+			Error::BadJson => "bad json",
+			Error::UnsupportedVersion => "unsupported version",
+		})
+	}
+}
+
+impl std::error::Error for Error {}
+
 #[cfg(not(test))]
 const DEFAULT_CONFIG: Config = Config {
 	variant: Variant::Argon2id,
@@ -47,8 +72,11 @@ const DEFAULT_CONFIG: Config = Config {
 	thread_mode: ThreadMode::Parallel,
 };
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Lock {
+	// see Versioned; 0 for locks predating versioning
+	#[serde(default)]
+	pub version: u32,
 	// pt encrypted with master_key
 	#[serde(
 		serialize_with = "serialize_vec_base64",
@@ -63,9 +91,19 @@ pub fn lock<T>(pt: &T, pass: &str) -> Result<Lock, Error>
 where
 	T: Serialize,
 {
-	let master_key = aes_gcm::Aes::new();
+	lock_with(pt, pass, &mut OsRng)
+}
+
+pub fn lock_with<T, R>(pt: &T, pass: &str, rng: &mut R) -> Result<Lock, Error>
+where
+	T: Serialize,
+	R: Rng + ?Sized,
+{
+	let master_key = aes_gcm::Aes::generate_with(rng);
+	let salt = Salt::generate_with(rng);
+	let pt = serde_json::to_vec(pt).unwrap();
 
-	lock_with_master_key(master_key, pt, pass)
+	lock_with_params(&pt, pass, salt, master_key, &DEFAULT_CONFIG)
 }
 
 fn lock_with_params(
@@ -77,9 +115,10 @@ fn lock_with_params(
 ) -> Result<Lock, Error> {
 	let ct = master_key.encrypt(pt);
 	let pass_aes = aes_from_params(pass, &salt, config)?;
-	let master_key_ct = pass_aes.encrypt(&master_key.as_bytes());
+	let master_key_ct = pass_aes.encrypt(master_key.as_bytes().as_slice());
 
 	Ok(Lock {
+		version: Lock::VERSION,
 		ct,
 		master_key: Encrypted {
 			ct: master_key_ct,
@@ -108,6 +147,24 @@ pub fn unlock_with_master_key(master_key: &aes_gcm::Aes, ct: &[u8]) -> Result<Ve
 	Ok(pt)
 }
 
+// a master key locked with pass on its own, eg to encrypt a stream under it; see decrypt_master_key
+pub fn lock_master_key_with<R>(
+	master_key: &aes_gcm::Aes,
+	pass: &str,
+	rng: &mut R,
+) -> Result<Encrypted, Error>
+where
+	R: Rng + ?Sized,
+{
+	let salt = Salt::generate_with(rng);
+	let pass_aes = aes_from_params(pass, &salt, &DEFAULT_CONFIG)?;
+
+	Ok(Encrypted {
+		ct: pass_aes.encrypt(master_key.as_bytes().as_slice()),
+		salt,
+	})
+}
+
 pub fn decrypt_master_key(mk: &Encrypted, pass: &str) -> Result<aes_gcm::Aes, Error> {
 	decrypt_master_key_with_params(mk, pass, &DEFAULT_CONFIG)
 }
@@ -124,11 +181,63 @@ fn decrypt_master_key_with_params(
 }
 
 fn unlock_with_params(lock: &Lock, pass: &str, config: &Config) -> Result<Vec<u8>, Error> {
-	let master_key = decrypt_master_key_with_params(&lock.master_key, pass, config)?;
+	let master_key = unlock_master_key_with_params(lock, pass, config)?;
 
 	unlock_with_master_key(&master_key, &lock.ct)
 }
 
+// the master key alone, eg to unlock and then migrate a lock with no need to derive it twice
+pub fn unlock_master_key(lock: &Lock, pass: &str) -> Result<aes_gcm::Aes, Error> {
+	unlock_master_key_with_params(lock, pass, &DEFAULT_CONFIG)
+}
+
+fn unlock_master_key_with_params(
+	lock: &Lock,
+	pass: &str,
+	config: &Config,
+) -> Result<aes_gcm::Aes, Error> {
+	lock.check_version()
+		.map_err(|_| Error::UnsupportedVersion)?;
+
+	decrypt_master_key_with_params(&lock.master_key, pass, config)
+}
+
+// re-locks an outdated lock in the current layout under a fresh salt, given its master key (see
+// unlock_master_key); None, if it is up to date
+pub fn migrate_with<R>(
+	lock: &Lock,
+	master_key: &aes_gcm::Aes,
+	pass: &str,
+	rng: &mut R,
+) -> Result<Option<Lock>, Error>
+where
+	R: Rng + ?Sized,
+{
+	migrate_with_params(
+		lock,
+		master_key,
+		pass,
+		Salt::generate_with(rng),
+		&DEFAULT_CONFIG,
+	)
+}
+
+fn migrate_with_params(
+	lock: &Lock,
+	master_key: &aes_gcm::Aes,
+	pass: &str,
+	salt: Salt,
+	config: &Config,
+) -> Result<Option<Lock>, Error> {
+	if !lock.is_outdated() {
+		return Ok(None);
+	}
+
+	let pt = unlock_with_master_key(master_key, &lock.ct)?;
+
+	lock_with_params(&pt, pass, salt, master_key.clone(), config).map(Some)
+}
+
 fn aes_from_params(pass: &str, salt: &Salt, config: &Config) -> Result<aes_gcm::Aes, Error> {
 	let hash =
 		argon2::hash_raw(pass.as_bytes(), &salt.bytes, config).map_err(|_| Error::Argon2Failed)?;
@@ -140,11 +249,14 @@ fn aes_from_params(pass: &str, salt: &Salt, config: &Config) -> Result<aes_gcm::
 
 #[cfg(test)]
 mod tests {
-	use super::{unlock, DEFAULT_CONFIG};
+	use super::{
+		migrate_with_params, unlock, unlock_master_key_with_params, Error, Lock, DEFAULT_CONFIG,
+	};
 	use crate::{
 		aes_gcm,
 		password_lock::{lock_with_params, unlock_with_params},
 		salt::Salt,
+		version::Versioned,
 	};
 	use argon2::Config;
 
@@ -179,4 +291,70 @@ mod tests {
 
 		assert!(unlocked.is_err());
 	}
+
+	#[test]
+	fn test_migrate() {
+		let msg = b"1234567890";
+		let pass = "password123";
+		let master_key = aes_gcm::Aes::new();
+		let mut lock = lock_with_params(
+			msg,
+			pass,
+			Salt::generate(),
+			master_key.clone(),
+			&TEST_CONFIG,
+		)
+		.unwrap();
+
+		assert!(
+			migrate_with_params(&lock, &master_key, pass, Salt::generate(), &TEST_CONFIG)
+				.unwrap()
+				.is_none()
+		);
+
+		lock.version = 0;
+
+		let unlocked = unlock_master_key_with_params(&lock, pass, &TEST_CONFIG).unwrap();
+
+		assert!(migrate_with_params(
+			&lock,
+			&aes_gcm::Aes::new(),
+			pass,
+			Salt::generate(),
+			&TEST_CONFIG
+		)
+		.is_err());
+
+		let migrated = migrate_with_params(&lock, &unlocked, pass, Salt::generate(), &TEST_CONFIG)
+			.unwrap()
+			.unwrap();
+
+		assert!(!migrated.is_outdated());
+		assert_ne!(migrated.master_key.salt, lock.master_key.salt);
+		assert_eq!(
+			unlock_with_params(&migrated, pass, &TEST_CONFIG).unwrap(),
+			msg.to_vec()
+		);
+	}
+
+	#[test]
+	fn test_reject_newer() {
+		let msg = b"1234567890";
+		let pass = "password123";
+		let mut lock = lock_with_params(
+			msg,
+			pass,
+			Salt::generate(),
+			aes_gcm::Aes::new(),
+			&TEST_CONFIG,
+		)
+		.unwrap();
+
+		lock.version = Lock::VERSION + 1;
+
+		assert!(matches!(
+			unlock_with_params(&lock, pass, &TEST_CONFIG),
+			Err(Error::UnsupportedVersion)
+		));
+	}
 }
@@ -1,6 +1,6 @@
 use crate::key::key;
 
-key!(PrivateKey);
-key!(SharedKey);
+key!(PrivateKey, secret);
+key!(SharedKey, secret);
 
 // TODO: introduce Into<KeyPair<PrivateKey::Type, PrivateKey, PublicKey>>?
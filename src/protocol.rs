@@ -1,37 +1,99 @@
+use std::{
+	collections::{HashMap, HashSet},
+	io::{Read, Seek, Write},
+	path::{Path, PathBuf},
+	rc::Rc,
+};
+
 use async_recursion::async_recursion;
 use async_trait::async_trait;
 
 use crate::{
+	archive,
+	bulk::{self, Batch, Download, Journal, Progress},
+	env::Env,
+	error::{Cause, Stage},
+	hmac,
 	id::Uid,
 	identity::{self},
-	register::LockedUser,
-	seeds::ROOT_ID,
-	user::{self, User},
-	vault::{self, LockedNode, Node, NO_PARENT_ID},
+	merkle, password_lock,
+	register::{LockedUser, Migrated},
+	revision::Revisions,
+	seeds::{Bundle, FinishInviteIntent, InviteIntent, LockedShare, ROOT_ID},
+	sync::{Changes, Cursor},
+	user::{self, IntegrityReport, User},
+	vault::{self, Event, LockedNode, NewNodeReq, Node, Resolution, Snapshot, NO_PARENT_ID},
+	wire::{self, Format},
 };
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Error {
-	NotFound,
+	NotFound(Uid),
 	NoNetwork(String),
-	NoAccess,
-	BadOperation,
-	BadJson,
-	ForgedSig,
+	NoAccess(Uid),
+	BadOperation { id: Uid, reason: &'static str },
+	// a locked user or a response of neither json nor a known binary format
+	BadEncoding(wire::Error),
+	// see vault::Error::Locked
+	Locked { id: Uid, stage: Stage, cause: Cause },
+	Io(String),
+	// a chunk or its digests do not match the file's signed digest
+	BadDigest(Uid),
+	// a dir's children don't match its signed listing
+	Unlisted(Uid),
+	RolledBack { id: Uid, revision: u64, seen: u64 },
+	// an edit was based on an outdated node; theirs is what's stored now, see Protocol::resolve
+	Conflict { id: Uid, theirs: Box<LockedNode> },
 }
 
 impl From<vault::Error> for Error {
 	fn from(er: vault::Error) -> Self {
 		match er {
-			vault::Error::NotFound => Self::NotFound,
-			vault::Error::BadOperation => Self::BadOperation,
-			vault::Error::NoAccess => Self::NoAccess,
-			vault::Error::ForgedSig => Self::ForgedSig,
+			vault::Error::NotFound(id) => Self::NotFound(id),
+			vault::Error::BadOperation { id, reason } => Self::BadOperation { id, reason },
+			vault::Error::NoAccess(id) => Self::NoAccess(id),
+			vault::Error::Locked { id, stage, cause } => Self::Locked { id, stage, cause },
+			vault::Error::Unlisted(id) => Self::Unlisted(id),
+			vault::Error::RolledBack { id, revision, seen } => {
+				Self::RolledBack { id, revision, seen }
+			}
+		}
+	}
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::NotFound(id) => write!(f, "node {} not found", id),
+			Error::NoNetwork(msg) => write!(f, "network: {}", msg),
+			Error::NoAccess(id) => write!(f, "no access to node {}", id),
+			Error::BadOperation { id, reason } => write!(f, "node {}: {}", id, reason),
+			Error::BadEncoding(e) => write!(f, "{}", e),
+			Error::Locked { id, stage, .. } => write!(f, "node {} failed at {}", id, stage),
+			Error::Io(msg) => write!(f, "io: {}", msg),
+			Error::BadDigest(id) => write!(f, "digest mismatch for file {}", id),
+			Error::Unlisted(id) => write!(f, "children of node {} don't match its listing", id),
+			Error::RolledBack { id, revision, seen } => write!(
+				f,
+				"node {} rolled back to revision {}, while {} was seen",
+				id, revision, seen
+			),
+			Error::Conflict { id, .. } => write!(f, "node {} was edited concurrently", id),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::BadEncoding(e) => Some(e),
+			Error::Locked { cause, .. } => cause.source(),
+			_ => None,
 		}
 	}
 }
 
-// to be ffi-exposed; represents cur dir's (or any dir's, in fact) contents and meta
+// see ffi::DirInfo; represents cur dir's (or any dir's, in fact) contents and meta
 pub struct DirView {
 	items: Vec<NodeView>,
 	name: String,
@@ -53,12 +115,12 @@ impl DirView {
 	}
 }
 
-// to be ffi-exposed; a generic view to a node and its meta
+// see ffi::NodeInfo; a generic view to a node and its meta
 #[derive(Clone)]
 pub struct NodeView {
 	id: Uid,
 	created_at: u64,
-	size: u32,
+	size: u64,
 	name: String,
 	ext: Option<String>,
 }
@@ -72,7 +134,7 @@ impl NodeView {
 		self.id
 	}
 
-	pub fn size(&self) -> u32 {
+	pub fn size(&self) -> u64 {
 		self.size
 	}
 
@@ -93,21 +155,76 @@ impl NodeView {
 #[async_trait(?Send)]
 pub trait Network {
 	async fn fetch_subtree(&self, id: Uid) -> Result<Vec<LockedNode>, Error>;
+	// uploads an encrypted chunk of a file that is not yet committed
+	async fn upload_chunk(&self, file_id: Uid, chunk_idx: u64, ct: &[u8]) -> Result<(), Error>;
+	// stores the leaves of a file's merkle tree (one per chunk) next to its chunks
+	async fn upload_digests(&self, file_id: Uid, digests: &[hmac::Digest]) -> Result<(), Error>;
+	async fn fetch_digests(&self, file_id: Uid) -> Result<Vec<hmac::Digest>, Error>;
+	async fn fetch_chunk(&self, file_id: Uid, chunk_idx: u64) -> Result<Vec<u8>, Error>;
+	// creates or updates a node; for files, this is called only after all chunks are acknowledged
+	// base is the hash of the stored node an edit is based on: if it's no longer current, the edit
+	// fails with Conflict; no base means a new node, which fails the same way, if it exists
+	async fn save_node(&self, node: &LockedNode, base: Option<&hmac::Digest>) -> Result<(), Error>;
+	// copies the chunks and digests of a file to a new one with the same content key
+	async fn copy_file(&self, from: Uid, to: Uid) -> Result<(), Error>;
+	// removes a node along with its subtree and content
+	async fn delete_node(&self, id: Uid) -> Result<(), Error>;
+	// the next page of node changes after since; see sync
+	async fn fetch_changes(&self, since: Cursor) -> Result<Changes, Error>;
+	async fn upload_share(&self, share: &LockedShare) -> Result<(), Error>;
+	// shares sent or received by user_id
+	async fn fetch_shares(&self, user_id: Uid) -> Result<Vec<LockedShare>, Error>;
+	async fn post_invite_intent(&self, intent: &InviteIntent) -> Result<(), Error>;
+	// intents sent by sender_id; acknowledged ones have their receiver set
+	async fn fetch_invite_intents(&self, sender_id: Uid) -> Result<Vec<InviteIntent>, Error>;
+	// replaces an acknowledged intent with its share
+	async fn finish_invite_intent(&self, finish: &FinishInviteIntent) -> Result<(), Error>;
+	// stores a user's migrated password lock and shares in place of the outdated ones; shares no
+	// longer there, eg replaced by an earlier call, are skipped
+	async fn save_migrated(&self, migrated: &Migrated) -> Result<(), Error>;
 }
 
-// to be ffi-exposed; contains all the state required to use Vault
+// exposed as is to ffi; contains all the state required to use Vault
 pub struct Protocol {
 	// current directory
 	cd: Option<Uid>,
 	user: User,
 	// callbacks
 	net: Box<dyn Network>,
+	// chunk verifiers of files being downloaded, by file and signed digest, so that a file replaced
+	// half way is verified against its new digest; dropped once complete
+	verifiers: HashMap<(Uid, hmac::Digest), merkle::Verifier>,
+	// files whose every chunk has been verified, by file and digest
+	verified: HashSet<(Uid, hmac::Digest)>,
+	// how far the backend's change log has been applied
+	cursor: Cursor,
+	// edits rejected for being based on outdated nodes: (mine, theirs) by id
+	conflicts: HashMap<Uid, (Node, LockedNode)>,
+	// called with each change of the tree, once the call that made it has succeeded
+	observers: Vec<(Subscription, Observer)>,
+	// own nodes found in outdated layouts when unlocking, re-locked in the current ones; see migrate
+	outdated: Vec<LockedNode>,
+	// the password lock and received shares found in outdated layouts, migrated; see migrate
+	migrated: Migrated,
 }
 
+type Observer = Box<dyn Fn(&Event)>;
+
+// returned by Protocol::subscribe to unsubscribe later
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Subscription(u64);
+
 impl From<Node> for NodeView {
 	fn from(node: Node) -> Self {
+		(&node).into()
+	}
+}
+
+// no need to clone a dir's children
+impl From<&Node> for NodeView {
+	fn from(node: &Node) -> Self {
 		let (ext, size) = match node.entry {
-			vault::Entry::File { info } => (Some(info.ext), info.size),
+			vault::Entry::File { ref info } => (Some(info.ext.clone()), info.size),
 			vault::Entry::Dir {
 				seed: _,
 				children: _,
@@ -118,7 +235,7 @@ impl From<Node> for NodeView {
 			id: node.id,
 			size,
 			created_at: node.created_at,
-			name: node.name,
+			name: node.name.clone(),
 			ext,
 		}
 	}
@@ -141,20 +258,104 @@ impl TryFrom<Node> for DirView {
 				breadcrumbs: Vec::new(),
 			})
 		} else {
-			Err(Error::BadOperation)
+			Err(Error::BadOperation {
+				id: dir.id,
+				reason: "not a dir",
+			})
 		}
 	}
 }
 
+// for a Protocol which works with whatever's loaded locally; anything else fails
 struct NoNetwork;
 
+fn offline(call: &str) -> Error {
+	Error::NoNetwork(format!("{} is not available without a network", call))
+}
+
 #[async_trait(?Send)]
 impl Network for NoNetwork {
 	async fn fetch_subtree(&self, _id: Uid) -> Result<Vec<LockedNode>, Error> {
-		todo!("fetch_subtree is not implemented for Protocol<NoNetwork>");
+		Err(offline("fetch_subtree"))
+	}
+
+	async fn upload_chunk(&self, _file_id: Uid, _chunk_idx: u64, _ct: &[u8]) -> Result<(), Error> {
+		Err(offline("upload_chunk"))
+	}
+
+	async fn upload_digests(&self, _file_id: Uid, _digests: &[hmac::Digest]) -> Result<(), Error> {
+		Err(offline("upload_digests"))
+	}
+
+	async fn fetch_digests(&self, _file_id: Uid) -> Result<Vec<hmac::Digest>, Error> {
+		Err(offline("fetch_digests"))
+	}
+
+	async fn fetch_chunk(&self, _file_id: Uid, _chunk_idx: u64) -> Result<Vec<u8>, Error> {
+		Err(offline("fetch_chunk"))
+	}
+
+	async fn save_node(
+		&self,
+		_node: &LockedNode,
+		_base: Option<&hmac::Digest>,
+	) -> Result<(), Error> {
+		Err(offline("save_node"))
+	}
+
+	async fn copy_file(&self, _from: Uid, _to: Uid) -> Result<(), Error> {
+		Err(offline("copy_file"))
+	}
+
+	async fn delete_node(&self, _id: Uid) -> Result<(), Error> {
+		Err(offline("delete_node"))
+	}
+
+	async fn fetch_changes(&self, _since: Cursor) -> Result<Changes, Error> {
+		Err(offline("fetch_changes"))
+	}
+
+	async fn upload_share(&self, _share: &LockedShare) -> Result<(), Error> {
+		Err(offline("upload_share"))
+	}
+
+	async fn fetch_shares(&self, _user_id: Uid) -> Result<Vec<LockedShare>, Error> {
+		Err(offline("fetch_shares"))
+	}
+
+	async fn post_invite_intent(&self, _intent: &InviteIntent) -> Result<(), Error> {
+		Err(offline("post_invite_intent"))
+	}
+
+	async fn fetch_invite_intents(&self, _sender_id: Uid) -> Result<Vec<InviteIntent>, Error> {
+		Err(offline("fetch_invite_intents"))
+	}
+
+	async fn finish_invite_intent(&self, _finish: &FinishInviteIntent) -> Result<(), Error> {
+		Err(offline("finish_invite_intent"))
+	}
+
+	async fn save_migrated(&self, _migrated: &Migrated) -> Result<(), Error> {
+		Err(offline("save_migrated"))
 	}
 }
 
+// fills buf as much as possible; returns less than buf.len() at eof only
+pub(crate) fn read_chunk<R: Read>(src: &mut R, buf: &mut [u8]) -> Result<usize, Error> {
+	let mut filled = 0;
+
+	while filled < buf.len() {
+		match src.read(&mut buf[filled..]) {
+			Ok(0) => break,
+			Ok(n) => filled += n,
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+			Err(e) => return Err(Error::Io(e.to_string())),
+		}
+	}
+
+	Ok(filled)
+}
+
 impl Protocol {
 	pub fn new_no_network(ident_priv: identity::Private, locked_json: &str) -> Result<Self, Error> {
 		Self::new(ident_priv, locked_json, Box::new(NoNetwork))
@@ -165,29 +366,216 @@ impl Protocol {
 		lcoked_json: &str,
 		net: Box<dyn Network>,
 	) -> Result<Self, Error> {
-		let locked: LockedUser = serde_json::from_str(lcoked_json).map_err(|_| Error::BadJson)?;
+		Self::new_with_env(
+			ident_priv,
+			lcoked_json.as_bytes(),
+			net,
+			Rc::new(Env::system()),
+		)
+	}
+
+	// same as new, but with a custom rng and clock, eg for reproducible tests; locked is either
+	// json or any other wire format
+	pub fn new_with_env(
+		ident_priv: identity::Private,
+		locked: &[u8],
+		net: Box<dyn Network>,
+		env: Rc<Env>,
+	) -> Result<Self, Error> {
+		let locked: LockedUser = wire::decode(locked).map_err(Error::BadEncoding)?;
+
+		Self::new_with_params(ident_priv, locked, net, env)
+	}
+
+	// for admins, whose private keys are locked with a password
+	pub fn unlock(locked: &[u8], pass: &str, net: Box<dyn Network>) -> Result<Self, Error> {
+		let locked: LockedUser = wire::decode(locked).map_err(Error::BadEncoding)?;
+		let no_access = Error::NoAccess(locked._pub.id());
+		let lock = locked.encrypted_priv.as_ref().ok_or(no_access.clone())?;
+		let env = Rc::new(Env::system());
+		// argon2 is costly, so the master key is derived once for both
+		let master_key =
+			password_lock::unlock_master_key(lock, pass).map_err(|_| no_access.clone())?;
+		let private = password_lock::unlock_with_master_key(&master_key, &lock.ct)
+			.map_err(|_| no_access.clone())?;
+		let migrated_lock = password_lock::migrate_with(lock, &master_key, pass, &mut **env.rng())
+			.map_err(|_| no_access.clone())?;
+		let private = wire::decode(&private).map_err(|_| no_access)?;
+		let mut protocol = Self::new_with_params(private, locked, net, env)?;
 
-		Self::new_with_params(ident_priv, locked, net)
+		protocol.migrated.encrypted_priv = migrated_lock;
+
+		Ok(protocol)
 	}
 
 	fn new_with_params(
 		ident_priv: identity::Private,
 		locked: LockedUser,
 		net: Box<dyn Network>,
+		env: Rc<Env>,
 	) -> Result<Self, Error> {
+		let user = user::unlock_with_params(
+			&ident_priv,
+			&locked._pub,
+			&locked.shares,
+			&locked.roots,
+			env,
+		)
+		.map_err(|e| match e {
+			user::Error::Vault(e) => Error::from(e),
+			user::Error::Share { .. } => Error::NoAccess(locked._pub.id()),
+		})?;
+		let outdated = user.fs.migrate(&locked.roots, &user.identity)?;
+		let env = user.fs.env();
+		// those which fail to open are reported by user already
+		let shares = locked
+			.shares
+			.iter()
+			.filter(|s| s.export.receiver == locked._pub.id())
+			.filter_map(|s| Some((s.id(), s.migrate(&user.identity, &env).ok().flatten()?)))
+			.collect();
+
 		Ok(Self {
 			cd: None,
-			user: user::unlock_with_params(
-				&ident_priv,
-				&locked._pub,
-				&locked.shares,
-				&locked.roots,
-			)
-			.map_err(|_| Error::NoAccess)?,
-			net: net,
+			user,
+			net,
+			verifiers: HashMap::new(),
+			verified: HashSet::new(),
+			cursor: locked.cursor,
+			conflicts: HashMap::new(),
+			observers: Vec::new(),
+			outdated,
+			migrated: Migrated {
+				user_id: locked._pub.id(),
+				encrypted_priv: None,
+				shares,
+			},
 		})
 	}
 
+	// saves own nodes, received shares and the password lock found in outdated layouts when
+	// unlocking; returns how many were saved. What fails to save stays pending for the next call.
+	// To be called once unlocked, so that the outdated layouts can be dropped eventually
+	pub async fn migrate(&mut self) -> Result<usize, Error> {
+		let mut saved = 0;
+
+		while let Some(locked_node) = self.outdated.last() {
+			let base = self.user.fs.base(locked_node.id);
+
+			match self.net.save_node(locked_node, base.as_ref()).await {
+				Ok(()) => {
+					self.user.fs.set_base(locked_node);
+					saved += 1;
+				}
+				// edited elsewhere since, which supersedes it
+				Err(Error::Conflict { .. }) => {}
+				Err(e) => return Err(e),
+			}
+
+			self.outdated.pop();
+		}
+
+		if !self.migrated.is_empty() {
+			self.net.save_migrated(&self.migrated).await?;
+
+			saved +=
+				self.migrated.shares.len() + usize::from(self.migrated.encrypted_priv.is_some());
+			self.migrated.encrypted_priv = None;
+			self.migrated.shares.clear();
+		}
+
+		Ok(saved)
+	}
+
+	// nodes are locked in this format from now on; pick it with wire::Format::negotiate
+	pub fn set_format(&mut self, format: Format) {
+		self.user.fs.set_format(format);
+	}
+
+	// shares and nodes rejected so far; worth alerting the user about, since the server may have
+	// been compromised
+	pub fn integrity_report(&self) -> IntegrityReport {
+		self.user.integrity_report()
+	}
+
+	// the highest revision seen per node; persist it and restore with track_revisions on the next
+	// launch, so that a server can't roll nodes back in between
+	pub fn revisions(&self) -> &Revisions {
+		self.user.fs.revisions()
+	}
+
+	pub fn track_revisions(&mut self, saved: &Revisions) {
+		self.user.fs.track_revisions(saved);
+	}
+
+	// persist it along with revisions and pass to resume_sync next time, unless starting from a
+	// freshly fetched user, which comes with its own cursor
+	pub fn cursor(&self) -> Cursor {
+		self.cursor
+	}
+
+	pub fn resume_sync(&mut self, cursor: Cursor) {
+		self.cursor = cursor;
+	}
+
+	// observer is called with every change of the tree from now on, be it local or synced; changes
+	// rolled back on failure are never reported
+	pub fn subscribe(&mut self, observer: impl Fn(&Event) + 'static) -> Subscription {
+		let id = Subscription(
+			self.observers
+				.last()
+				.map_or(0, |(Subscription(id), _)| id + 1),
+		);
+
+		self.observers.push((id, Box::new(observer)));
+
+		id
+	}
+
+	pub fn unsubscribe(&mut self, id: Subscription) {
+		self.observers.retain(|(s, _)| *s != id);
+	}
+
+	// catches up with the backend's change log; returns how many changes were applied
+	pub async fn sync(&mut self) -> Result<usize, Error> {
+		let res = self.sync_impl().await;
+
+		// pages applied before a failure are kept
+		self.notify();
+
+		res
+	}
+
+	async fn sync_impl(&mut self) -> Result<usize, Error> {
+		let mut applied = 0;
+
+		loop {
+			let Changes { changes, cursor } = self.net.fetch_changes(self.cursor).await?;
+
+			if changes.is_empty() {
+				break;
+			}
+
+			// a dir failing to verify is unloaded, so the page is done with either way
+			let res = self.user.fs.apply_changes(&changes);
+
+			self.cursor = cursor;
+			res?;
+
+			applied += changes.len();
+		}
+
+		// the current dir might have been deleted or moved out of reach
+		if self
+			.cd
+			.is_some_and(|cd| self.user.fs.node_by_id(cd).is_none())
+		{
+			self.cd = None;
+		}
+
+		Ok(applied)
+	}
+
 	// lists cur dir's content
 	pub async fn ls_cur_mut(&mut self) -> Result<DirView, Error> {
 		self.ls_cur_mut_impl().await
@@ -205,8 +593,8 @@ impl Protocol {
 						.user
 						.fs
 						// TODO: wrap in a channel instead
-						.add_or_update_subtree(&nodes, cd)
-						.map_err(|_| Error::NotFound)?;
+						.add_or_update_subtree(&nodes, cd)?;
+					self.notify();
 
 					// TODO: refactor to avoid recursion
 					self.ls_cur_mut_impl().await
@@ -288,26 +676,1582 @@ impl Protocol {
 		self.ls_cur_mut_impl().await
 	}
 
+	// decrypts a chunk and, unless it's a legacy file, verifies it against the file's signed digest
 	pub async fn chunk_decrypt_for_file(
-		&self,
+		&mut self,
 		chunk: &[u8],
 		file_id: &Uid,
-		chunk_idx: u32,
+		chunk_idx: u64,
 	) -> Result<Vec<u8>, Error> {
-		if let Some(node) = self.user.fs.node_by_id(*file_id) {
+		let info = if let Some(node) = self.user.fs.node_by_id(*file_id) {
 			if let vault::Entry::File { ref info } = node.entry {
-				let pt = info
-					.key_iv
-					.chunk_decrypt_async(chunk_idx, chunk)
-					.await
-					.map_err(|_| Error::NoAccess)?;
-
-				Ok(Vec::from(pt.as_slice()))
+				info.clone()
 			} else {
-				Err(Error::BadOperation)
+				return Err(Error::BadOperation {
+					id: *file_id,
+					reason: "not a file",
+				});
+			}
+		} else {
+			return Err(Error::NotFound(*file_id));
+		};
+		let pt = info
+			.key_iv
+			.chunk_decrypt_async(chunk_idx, chunk)
+			.await
+			.map_err(|e| Error::Locked {
+				id: *file_id,
+				stage: Stage::Decrypt,
+				cause: Cause::new(e),
+			})?;
+
+		if let Some(digest) = info.digest {
+			let key = (*file_id, digest);
+
+			if !self.verifiers.contains_key(&key) {
+				let leaves = self.net.fetch_digests(*file_id).await?;
+				let verifier =
+					merkle::Verifier::new(info.digest_key(), &digest, leaves, info.chunk_count())
+						.ok_or(Error::BadDigest(*file_id))?;
+
+				self.verifiers.insert(key, verifier);
+			}
+
+			let verifier = self
+				.verifiers
+				.get_mut(&key)
+				.ok_or(Error::BadDigest(*file_id))?;
+
+			if !verifier.verify(chunk_idx, &pt) {
+				return Err(Error::BadDigest(*file_id));
 			}
+
+			if verifier.is_complete() {
+				self.verifiers.remove(&key);
+				self.verified.insert(key);
+			}
+		}
+
+		Ok(pt)
+	}
+
+	// true when every chunk of a file has been decrypted and verified against its digest
+	pub fn is_fully_verified(&self, file_id: &Uid) -> bool {
+		match self.user.fs.node_by_id(*file_id).map(|n| &n.entry) {
+			Some(vault::Entry::File {
+				info: vault::FileInfo {
+					digest: Some(digest),
+					..
+				},
+			}) => self.verified.contains(&(*file_id, *digest)),
+			_ => false,
+		}
+	}
+
+	// names, extensions and paths of whatever's been loaded so far, best matches first; see search
+	pub fn search(&self, query: &str, limit: usize) -> Vec<NodeView> {
+		self.user
+			.fs
+			.search(query, limit)
+			.into_iter()
+			.filter_map(|id| self.user.fs.node_by_id(id))
+			.map(NodeView::from)
+			.collect()
+	}
+
+	// touches a new file in parent_id, encrypts src chunk by chunk and hands each chunk to the
+	// network; the node is committed (and inserted locally) only when all chunks are acknowledged
+	pub async fn upload_file<R: Read>(
+		&mut self,
+		parent_id: Uid,
+		name: &str,
+		ext: &str,
+		size: u64,
+		src: &mut R,
+	) -> Result<NodeView, Error> {
+		let prev = self.user.fs.snapshot([parent_id]);
+		let res = self.upload_file_impl(parent_id, name, ext, size, src).await;
+
+		self.restore_on_err(prev, res)
+	}
+
+	async fn upload_file_impl<R: Read>(
+		&mut self,
+		parent_id: Uid,
+		name: &str,
+		ext: &str,
+		size: u64,
+		src: &mut R,
+	) -> Result<NodeView, Error> {
+		// it's about to be listed again, see save_listing
+		self.load_dir(parent_id).await?;
+
+		let NewNodeReq { mut node, .. } =
+			self.user
+				.fs
+				.touch(parent_id, size, name, ext, &self.user.identity)?;
+		let (key_iv, chunk_size, digest_key) = if let vault::Entry::File { ref info } = node.entry {
+			(info.key_iv.clone(), info.chunk_size(), info.digest_key())
 		} else {
-			Err(Error::NotFound)
+			return Err(Error::BadOperation {
+				id: node.id,
+				reason: "not a file",
+			});
+		};
+		let mut leaves = Vec::new();
+		let mut buf = vec![0u8; chunk_size as usize];
+		let mut uploaded: u64 = 0;
+		let mut chunk_idx: u64 = 0;
+
+		loop {
+			let read = read_chunk(src, &mut buf)?;
+
+			// an empty file still gets one (empty) chunk to carry the auth tag
+			if read == 0 && chunk_idx > 0 {
+				break;
+			}
+
+			if uploaded + read as u64 > size {
+				return Err(Error::BadOperation {
+					id: node.id,
+					reason: "larger than declared",
+				});
+			}
+
+			let ct = key_iv.chunk_encrypt_async(chunk_idx, &buf[..read]).await;
+
+			self.net.upload_chunk(node.id, chunk_idx, &ct).await?;
+			leaves.push(merkle::leaf(&digest_key, chunk_idx, &buf[..read]));
+
+			uploaded += read as u64;
+			chunk_idx += 1;
+
+			if read < buf.len() {
+				break;
+			}
+		}
+
+		if uploaded != size {
+			return Err(Error::BadOperation {
+				id: node.id,
+				reason: "smaller than declared",
+			});
+		}
+
+		// the digest is known only now, so sign the node again
+		if let vault::Entry::File { ref mut info } = node.entry {
+			info.digest = Some(merkle::root(&leaves));
+		}
+
+		let locked_node = self.user.fs.lock(&node, &self.user.identity)?;
+
+		self.net.upload_digests(node.id, &leaves).await?;
+		self.user.fs.insert_node(node.clone())?;
+		self.save_with_listing(&locked_node, None, parent_id)
+			.await?;
+
+		Ok(node.into())
+	}
+
+	// fetches and decrypts a chunk; see chunk_decrypt_for_file
+	pub async fn download_chunk(&mut self, file_id: Uid, chunk_idx: u64) -> Result<Vec<u8>, Error> {
+		let ct = self.net.fetch_chunk(file_id, chunk_idx).await?;
+
+		self.chunk_decrypt_for_file(&ct, &file_id, chunk_idx).await
+	}
+
+	pub async fn mkdir(&mut self, parent_id: Uid, name: &str) -> Result<NodeView, Error> {
+		let prev = self.user.fs.snapshot([parent_id]);
+		let res = self.mkdir_impl(parent_id, name).await;
+
+		self.restore_on_err(prev, res)
+	}
+
+	async fn mkdir_impl(&mut self, parent_id: Uid, name: &str) -> Result<NodeView, Error> {
+		self.load_dir(parent_id).await?;
+
+		let NewNodeReq { node, locked_node } =
+			self.user.fs.mkdir(parent_id, name, &self.user.identity)?;
+
+		self.user.fs.insert_node(node.clone())?;
+		self.save_with_listing(&locked_node, None, parent_id)
+			.await?;
+
+		Ok(node.into())
+	}
+
+	pub async fn rename(&mut self, id: Uid, name: &str) -> Result<NodeView, Error> {
+		let prev = self.user.fs.snapshot(self.parent_id_of(id));
+		let res = self.rename_impl(id, name).await;
+
+		self.restore_on_err(prev, res)
+	}
+
+	async fn rename_impl(&mut self, id: Uid, name: &str) -> Result<NodeView, Error> {
+		self.load_dir(self.parented_node(id)?.parent_id).await?;
+
+		let mut node = self.parented_node(id)?.clone();
+
+		node.name = name.to_string();
+		node.revision = self.user.fs.revisions().get(id) + 1;
+		node.created_by = self.user.identity.public().clone();
+
+		let locked_node = self.user.fs.lock(&node, &self.user.identity)?;
+		let base = self.user.fs.base(id);
+
+		self.user.fs.update_node(node.clone())?;
+
+		if let Err(e) = self
+			.save_with_listing(&locked_node, base.as_ref(), node.parent_id)
+			.await
+		{
+			if let Error::Conflict { ref theirs, .. } = e {
+				self.conflicts.insert(id, (node, (**theirs).clone()));
+			}
+
+			return Err(e);
+		}
+
+		Ok(node.into())
+	}
+
+	// ids of nodes whose edits are waiting to be resolved
+	pub fn conflicts(&self) -> Vec<Uid> {
+		self.conflicts.keys().cloned().collect()
+	}
+
+	// settles a conflicting edit of id; Both saves mine as a copy, so is for files only
+	pub async fn resolve(&mut self, id: Uid, resolution: Resolution) -> Result<(), Error> {
+		let (mine, theirs) = self.conflicts.remove(&id).ok_or(Error::NotFound(id))?;
+		// mine may have been moved, and theirs may be moved back
+		let prev = self.user.fs.snapshot(
+			[
+				Some(mine.parent_id),
+				Some(theirs.parent_id),
+				self.parent_id_of(id),
+			]
+			.into_iter()
+			.flatten(),
+		);
+		let res = self.resolve_impl(&mine, &theirs, resolution).await;
+
+		if let Err(ref e) = res {
+			// to try again, against whatever is stored now, if it has changed yet again
+			let theirs = match e {
+				Error::Conflict { theirs, .. } => (**theirs).clone(),
+				_ => theirs,
+			};
+
+			self.conflicts.insert(id, (mine, theirs));
+		}
+
+		self.restore_on_err(prev, res)
+	}
+
+	async fn resolve_impl(
+		&mut self,
+		mine: &Node,
+		theirs: &LockedNode,
+		resolution: Resolution,
+	) -> Result<(), Error> {
+		let base = theirs.hash();
+
+		self.load_dir(mine.parent_id).await?;
+
+		for locked_node in self
+			.user
+			.fs
+			.resolve(mine, theirs, resolution, &self.user.identity)?
+		{
+			if locked_node.id == theirs.id {
+				self.save_with_listing(&locked_node, Some(&base), locked_node.parent_id)
+					.await?;
+			} else {
+				self.net.copy_file(theirs.id, locked_node.id).await?;
+				self.save_with_listing(&locked_node, None, locked_node.parent_id)
+					.await?;
+			}
+		}
+
+		Ok(())
+	}
+
+	// deletes a node along with its subtree
+	pub async fn delete(&mut self, id: Uid) -> Result<(), Error> {
+		let prev = self.user.fs.snapshot(self.parent_id_of(id));
+		let res = self.delete_impl(id).await;
+
+		self.restore_on_err(prev, res)
+	}
+
+	async fn delete_impl(&mut self, id: Uid) -> Result<(), Error> {
+		let parent_id = self.parented_node(id)?.parent_id;
+
+		self.load_dir(parent_id).await?;
+		self.user.fs.delete_node(id)?;
+		// listed first: should the delete fail then, the node is merely left out, rather than
+		// missing from a listing others would fail on
+		self.save_listing(parent_id).await?;
+		self.net.delete_node(id).await?;
+
+		// the current dir might have been deleted as well
+		if self
+			.cd
+			.is_some_and(|cd| self.user.fs.node_by_id(cd).is_none())
+		{
+			self.cd = Some(parent_id);
+		}
+
+		Ok(())
+	}
+
+	// shares a node (and its subtree, if a dir) with an existing user
+	pub async fn share(&mut self, id: Uid, receiver: &identity::Public) -> Result<(), Error> {
+		let mut bundle = Bundle::new();
+
+		bundle.set_fs(id, self.user.fs.share_node(id)?);
+
+		let env = self.user.fs.env();
+		let share = LockedShare::new_with(&self.user.identity, receiver, &bundle, &mut **env.rng());
+
+		self.net.upload_share(&share).await?;
+		self.user.exports.push(share.export);
+
+		Ok(())
+	}
+
+	// a password-locked archive of id and everything below it, contents included, each file verified
+	// as it's fetched and written to out chunk by chunk; see archive and import
+	pub async fn export<W: Write>(
+		&mut self,
+		id: Uid,
+		pass: &str,
+		out: &mut W,
+	) -> Result<(), Error> {
+		let env = self.user.fs.env();
+		let mut archive = archive::seal(out, id, pass, &mut **env.rng())?;
+		let mut items = 0;
+		// with the index of their parent's item
+		let mut to_visit = vec![(id, None)];
+
+		while let Some((id, parent)) = to_visit.pop() {
+			let node = self.user.fs.node_by_id(id).ok_or(Error::NotFound(id))?;
+			let name = node.name.clone();
+
+			match node.entry {
+				vault::Entry::Dir { .. } => {
+					self.load_dir(id).await?;
+
+					for child in self.user.fs.ls_dir(id)? {
+						to_visit.push((child.id, Some(items)));
+					}
+
+					archive.item(archive::Item {
+						parent,
+						name,
+						kind: archive::Kind::Dir,
+					})?;
+				}
+				vault::Entry::File { ref info } => {
+					archive.item(archive::Item {
+						parent,
+						name,
+						kind: archive::Kind::File {
+							ext: info.ext.clone(),
+							size: info.size,
+						},
+					})?;
+					// a file failing its digest leaves the archive unfinished, so it won't open
+					self.fetch_file(id, &mut |pt| archive.contents(pt)).await?;
+				}
+			}
+
+			items += 1;
+		}
+
+		archive.finish()
+	}
+
+	// rebuilds an exported subtree under parent_id, one item at a time; nothing is imported unless
+	// the whole archive is read through and opens, but a failed upload keeps what's been imported
+	// so far
+	pub async fn import<R: Read + Seek>(
+		&mut self,
+		src: &mut R,
+		pass: &str,
+		parent_id: Uid,
+	) -> Result<NodeView, Error> {
+		let mut archive = archive::open(src, pass, parent_id)?;
+
+		archive.verify()?;
+		archive.rewind()?;
+
+		// the new ids of the items imported so far
+		let mut ids: Vec<Uid> = Vec::new();
+		let mut imported = None;
+
+		while let Some(item) = archive.next_item()? {
+			let parent_id = match item.parent {
+				None => parent_id,
+				Some(idx) => *ids.get(idx).ok_or(Error::BadOperation {
+					id: parent_id,
+					reason: "a child before its dir",
+				})?,
+			};
+			let view = match item.kind {
+				archive::Kind::Dir => self.mkdir(parent_id, &item.name).await?,
+				archive::Kind::File { ref ext, size } => {
+					self.upload_file(
+						parent_id,
+						&item.name,
+						ext,
+						size,
+						&mut archive.contents(size),
+					)
+					.await?
+				}
+			};
+
+			ids.push(view.id());
+			imported.get_or_insert(view);
 		}
+
+		imported.ok_or(Error::NotFound(parent_id))
+	}
+
+	// imports a local dir tree into parent_id, files first, then subdirs; progress is reported
+	// along with what's been committed so far, and a journal kept from an interrupted import skips
+	// whatever it lists as done and settles whatever it lists as pending; returns the final progress
+	pub async fn import_dir(
+		&mut self,
+		src: &Path,
+		parent_id: Uid,
+		journal: &mut Journal,
+		on_progress: &mut dyn FnMut(&Progress, &Journal),
+	) -> Result<Progress, Error> {
+		let (total_files, total_bytes) = bulk::scan(src)?;
+		let mut progress = Progress {
+			total_files,
+			total_bytes,
+			..Default::default()
+		};
+		let mut to_visit = vec![(src.to_path_buf(), String::new(), parent_id)];
+
+		self.settle_pending(journal).await?;
+
+		while let Some((dir, rel, dir_id)) = to_visit.pop() {
+			let entries = bulk::entries(&dir)?;
+			let mut batch = Batch::default();
+			let mut dirs = Vec::new();
+
+			self.load_dir(dir_id).await?;
+
+			for (name, path, is_dir) in entries {
+				let rel = if rel.is_empty() {
+					name.clone()
+				} else {
+					format!("{}/{}", rel, name)
+				};
+
+				if is_dir {
+					dirs.push((name, path, rel));
+				} else if journal.done.contains_key(&rel) {
+					progress.files += 1;
+					progress.bytes += std::fs::metadata(&path).map_or(0, |m| m.len());
+				} else {
+					let uploaded = bulk::upload_file(
+						&*self.net,
+						&self.user.fs,
+						dir_id,
+						&path,
+						&self.user.identity,
+					)
+					.await?;
+
+					progress.files += 1;
+					progress.bytes += batch.push(rel, uploaded);
+					progress.path = path;
+
+					on_progress(&progress, journal);
+
+					if batch.bytes >= bulk::BATCH_SIZE {
+						self.commit_batch(
+							dir_id,
+							std::mem::take(&mut batch),
+							journal,
+							&progress,
+							on_progress,
+						)
+						.await?;
+					}
+				}
+			}
+
+			if !batch.nodes.is_empty() {
+				self.commit_batch(dir_id, batch, journal, &progress, on_progress)
+					.await?;
+			}
+
+			// reversed, so that subdirs are visited by name
+			for (name, path, rel) in dirs.into_iter().rev() {
+				let id = match journal.done.get(&rel) {
+					Some(id) => *id,
+					None => {
+						// committed like a batch of its own, so that it's journaled as pending
+						// before it's saved and a resumed import doesn't make it again
+						let NewNodeReq { node, locked_node } =
+							self.user.fs.mkdir(dir_id, &name, &self.user.identity)?;
+						let id = node.id;
+						let mut batch = Batch::default();
+
+						batch.push(rel.clone(), (node, locked_node));
+						self.commit_batch(dir_id, batch, journal, &progress, on_progress)
+							.await?;
+						progress.path = path.clone();
+						on_progress(&progress, journal);
+
+						id
+					}
+				};
+
+				to_visit.push((path, rel, id));
+			}
+		}
+
+		Ok(progress)
+	}
+
+	// a node saved by an interrupted batch is committed, if its dir got to list it, and an orphan
+	// otherwise, as nothing refers to it
+	async fn settle_pending(&mut self, journal: &mut Journal) -> Result<(), Error> {
+		while let Some((rel, (dir_id, id))) = journal.pending.pop_first() {
+			let res = match self.load_dir(dir_id).await {
+				Ok(()) if self.user.fs.node_by_id(id).is_some() => {
+					journal.done.insert(rel.clone(), id);
+
+					Ok(())
+				}
+				Ok(()) => match self.net.delete_node(id).await {
+					Err(Error::NotFound(_)) => Ok(()),
+					res => res,
+				},
+				Err(e) => Err(e),
+			};
+
+			if res.is_err() {
+				journal.pending.insert(rel, (dir_id, id));
+
+				return res;
+			}
+		}
+
+		Ok(())
+	}
+
+	// saves the nodes, whose contents are uploaded already, and then the listing of their dir; the
+	// nodes are journaled as pending (and reported) beforehand, so that a resumed import can settle
+	// them
+	async fn commit_batch(
+		&mut self,
+		dir_id: Uid,
+		batch: Batch,
+		journal: &mut Journal,
+		progress: &Progress,
+		on_progress: &mut dyn FnMut(&Progress, &Journal),
+	) -> Result<(), Error> {
+		for (rel, node, _) in &batch.nodes {
+			journal.pending.insert(rel.clone(), (dir_id, node.id));
+		}
+
+		on_progress(progress, journal);
+
+		let prev = self.user.fs.snapshot([dir_id]);
+		let res = self.commit_batch_impl(dir_id, &batch).await;
+
+		if res.is_ok() {
+			for (rel, node, _) in batch.nodes {
+				journal.pending.remove(&rel);
+				journal.done.insert(rel, node.id);
+			}
+		}
+
+		self.restore_on_err(prev, res)
+	}
+
+	async fn commit_batch_impl(&mut self, dir_id: Uid, batch: &Batch) -> Result<(), Error> {
+		for (_, node, locked_node) in &batch.nodes {
+			self.net.save_node(locked_node, None).await?;
+			self.user.fs.set_base(locked_node);
+			self.user.fs.insert_node(node.clone())?;
+		}
+
+		self.save_listing(dir_id).await
+	}
+
+	// writes dir id and everything below it to dst, created if needed, fetching whatever's not
+	// loaded yet; progress is reported per chunk and per file, and files which fail to download,
+	// verify or write are reported and skipped, rather than left half written (see write_file)
+	pub async fn download_dir(
+		&mut self,
+		id: Uid,
+		dst: &Path,
+		on_progress: &mut dyn FnMut(&Progress),
+	) -> Result<Download, Error> {
+		let io = |path: &Path, e: std::io::Error| Error::Io(format!("{}: {}", path.display(), e));
+		let mut download = Download::default();
+		// (id, size) by local path, resolved first to know the totals
+		let mut files = Vec::new();
+		let mut to_visit = vec![(id, dst.to_path_buf())];
+
+		while let Some((dir_id, path)) = to_visit.pop() {
+			let children: Vec<Node> = match self.load_dir(dir_id).await {
+				Ok(()) => self.user.fs.ls_dir(dir_id)?.into_iter().cloned().collect(),
+				// the root is a must, but subdirs are skipped like files
+				Err(e) if dir_id == id => return Err(e),
+				Err(e) => {
+					download.failed.push((path, e));
+					continue;
+				}
+			};
+
+			if let Err(e) = std::fs::create_dir_all(&path) {
+				download.failed.push((path.clone(), io(&path, e)));
+				continue;
+			}
+
+			let mut names = HashSet::new();
+
+			for child in children {
+				let Some(name) = bulk::local_name(&child).filter(|n| names.insert(n.clone()))
+				else {
+					download.failed.push((
+						path.join(child.id.to_string()),
+						Error::BadOperation {
+							id: child.id,
+							reason: "bad or duplicate name",
+						},
+					));
+					continue;
+				};
+
+				match child.entry {
+					vault::Entry::Dir { .. } => to_visit.push((child.id, path.join(name))),
+					vault::Entry::File { ref info } => {
+						download.progress.total_files += 1;
+						download.progress.total_bytes += info.size;
+						files.push((child.id, info.size, path.join(name)));
+					}
+				}
+			}
+		}
+
+		for (file_id, size, path) in files {
+			let bytes = download.progress.bytes;
+
+			download.progress.path = path.clone();
+
+			if let Err(e) = self
+				.write_file(file_id, &path, &mut download.progress, on_progress)
+				.await
+			{
+				download.failed.push((path, e));
+			}
+
+			// failed files count as well, so that bytes adds up to total_bytes in the end
+			download.progress.files += 1;
+			download.progress.bytes = bytes + size;
+
+			on_progress(&download.progress);
+		}
+
+		Ok(download)
+	}
+
+	// writes file id, loaded along with its dir (see cd_to_dir), to path, reporting progress per
+	// chunk; see write_file
+	pub async fn download_file(
+		&mut self,
+		id: Uid,
+		path: &Path,
+		on_progress: &mut dyn FnMut(&Progress),
+	) -> Result<(), Error> {
+		let mut progress = Progress {
+			path: path.to_path_buf(),
+			total_files: 1,
+			total_bytes: self.file_of(id)?.size,
+			..Default::default()
+		};
+
+		self.write_file(id, path, &mut progress, on_progress)
+			.await?;
+
+		progress.files = 1;
+		on_progress(&progress);
+
+		Ok(())
+	}
+
+	// writes to a temp file next to path first, renamed over it once the file is fully downloaded
+	// and verified, so that path is never left half written or replaced by a file which failed;
+	// progress.bytes grows with each chunk written
+	async fn write_file(
+		&mut self,
+		id: Uid,
+		path: &Path,
+		progress: &mut Progress,
+		on_progress: &mut dyn FnMut(&Progress),
+	) -> Result<(), Error> {
+		let io = |e: std::io::Error| Error::Io(format!("{}: {}", path.display(), e));
+		let mut tmp = path.as_os_str().to_owned();
+
+		tmp.push(format!(".{}.tmp", std::process::id()));
+
+		let tmp = PathBuf::from(tmp);
+		let res = match std::fs::File::create(&tmp) {
+			Ok(mut dst) => {
+				self.fetch_file(id, &mut |pt| {
+					dst.write_all(pt).map_err(io)?;
+					progress.bytes += pt.len() as u64;
+					on_progress(progress);
+
+					Ok(())
+				})
+				.await
+			}
+			Err(e) => Err(io(e)),
+		}
+		.and_then(|()| std::fs::rename(&tmp, path).map_err(io));
+
+		if res.is_err() {
+			_ = std::fs::remove_file(&tmp);
+		}
+
+		res
+	}
+
+	fn file_of(&self, id: Uid) -> Result<&vault::FileInfo, Error> {
+		match self.user.fs.node_by_id(id).map(|n| &n.entry) {
+			Some(vault::Entry::File { info }) => Ok(info),
+			Some(_) => Err(Error::BadOperation {
+				id,
+				reason: "not a file",
+			}),
+			None => Err(Error::NotFound(id)),
+		}
+	}
+
+	// passes file id's chunks to on_chunk in order, and fails unless they add up to its size and,
+	// unless it's a legacy file, verify against its digest
+	async fn fetch_file(
+		&mut self,
+		id: Uid,
+		on_chunk: &mut dyn FnMut(&[u8]) -> Result<(), Error>,
+	) -> Result<(), Error> {
+		let info = self.file_of(id)?;
+		let (size, has_digest) = (info.size, info.digest.is_some());
+		let mut read = 0;
+		let mut chunk_idx = 0;
+
+		// an empty file still has one chunk
+		while read < size || chunk_idx == 0 {
+			let pt = self.download_chunk(id, chunk_idx).await?;
+
+			on_chunk(&pt)?;
+			read += pt.len() as u64;
+			chunk_idx += 1;
+
+			// short of size, but no more to read
+			if pt.is_empty() {
+				break;
+			}
+		}
+
+		if read != size || (has_digest && !self.is_fully_verified(&id)) {
+			Err(Error::BadDigest(id))
+		} else {
+			Ok(())
+		}
+	}
+
+	// invites a user yet to sign up with ref_src to fs_ids (None means everything available); the
+	// share itself is sent by finish_invites, once the invitee acknowledges the intent
+	pub async fn invite(
+		&mut self,
+		ref_src: &str,
+		user_id: Uid,
+		fs_ids: Option<Vec<Uid>>,
+	) -> Result<InviteIntent, Error> {
+		// Protocol is about the vault only, so no db seeds
+		let db_ids = Some(Vec::new());
+		let sig = self
+			.user
+			.identity
+			.private()
+			.sign(&InviteIntent::ctx_to_sign(
+				&self.user.identity.id(),
+				ref_src,
+				&user_id,
+				fs_ids.as_deref(),
+				db_ids.as_deref(),
+			));
+		let intent = InviteIntent {
+			ref_src: ref_src.to_string(),
+			sender: self.user.identity.public().clone(),
+			sig,
+			user_id,
+			receiver: None,
+			fs_ids,
+			db_ids,
+		};
+
+		self.net.post_invite_intent(&intent).await?;
+
+		Ok(intent)
+	}
+
+	// sends shares for all acknowledged invite intents; returns how many
+	pub async fn finish_invites(&mut self) -> Result<usize, Error> {
+		let intents = self
+			.net
+			.fetch_invite_intents(self.user.identity.id())
+			.await?;
+		let env = self.user.fs.env();
+		let mut finished = 0;
+
+		for intent in intents {
+			let Some(ref receiver) = intent.receiver else {
+				continue;
+			};
+			let to_sign = InviteIntent::ctx_to_sign(
+				&intent.sender.id(),
+				&intent.ref_src,
+				&intent.user_id,
+				intent.fs_ids.as_deref(),
+				intent.db_ids.as_deref(),
+			);
+
+			// a server could otherwise widen the scope or swap the invitee; the receiver's keys are
+			// still only as trustworthy as the server that vouches for them
+			let verified = if intent.sender != *self.user.identity.public()
+				|| receiver.id() != intent.user_id
+			{
+				Err(Cause::default())
+			} else {
+				intent
+					.sender
+					.try_verify(&intent.sig, &to_sign)
+					.map_err(Cause::new)
+			};
+
+			if let Err(cause) = verified {
+				return Err(Error::Locked {
+					id: intent.user_id,
+					stage: Stage::Verify,
+					cause,
+				});
+			}
+
+			let bundle = self
+				.user
+				.seeds_for_ids(intent.fs_ids.as_deref(), intent.db_ids.as_deref());
+			let share =
+				LockedShare::new_with(&self.user.identity, receiver, &bundle, &mut **env.rng());
+			let export = share.export.clone();
+
+			self.net
+				.finish_invite_intent(&FinishInviteIntent {
+					ref_src: intent.ref_src.clone(),
+					share,
+				})
+				.await?;
+			self.user.exports.push(export);
+
+			finished += 1;
+		}
+
+		Ok(finished)
+	}
+
+	// fetches a dir's subtree, unless it's known already
+	async fn load_dir(&mut self, id: Uid) -> Result<(), Error> {
+		if self
+			.user
+			.fs
+			.node_by_id(id)
+			.ok_or(Error::NotFound(id))?
+			.dirty
+		{
+			let nodes = self.net.fetch_subtree(id).await?;
+
+			self.user.fs.add_or_update_subtree(&nodes, id)?;
+			self.notify();
+		}
+
+		Ok(())
+	}
+
+	// a node which can be edited in place: its parent is known, so that its listing can be updated
+	fn parented_node(&self, id: Uid) -> Result<&Node, Error> {
+		let node = self.user.fs.node_by_id(id).ok_or(Error::NotFound(id))?;
+
+		if self.user.fs.node_by_id(node.parent_id).is_some() {
+			Ok(node)
+		} else {
+			Err(Error::BadOperation {
+				id,
+				reason: "a root",
+			})
+		}
+	}
+
+	// the dir an edit of id touches, if it's there at all; see restore_on_err
+	fn parent_id_of(&self, id: Uid) -> Option<Uid> {
+		self.user.fs.node_by_id(id).map(|node| node.parent_id)
+	}
+
+	// commits a node and then the listing of its parent, so that others can tell, if the node is
+	// ever omitted or replayed
+	async fn save_with_listing(
+		&mut self,
+		locked_node: &LockedNode,
+		base: Option<&hmac::Digest>,
+		parent_id: Uid,
+	) -> Result<(), Error> {
+		self.net.save_node(locked_node, base).await?;
+		self.user.fs.set_base(locked_node);
+		self.save_listing(parent_id).await
+	}
+
+	// re-signs the dir with its children listed, so it has to be loaded (see load_dir) before
+	// they're changed; listings are derived, so a conflicting one is simply replaced: children
+	// added elsewhere in the meantime and not synced yet are left out, until whoever syncs them
+	// re-lists the dir
+	async fn save_listing(&mut self, dir_id: Uid) -> Result<(), Error> {
+		let mut attempts = 3;
+
+		loop {
+			let listing = self.user.fs.list(dir_id, &self.user.identity)?;
+			let base = self.user.fs.base(dir_id);
+
+			match self.net.save_node(&listing, base.as_ref()).await {
+				Ok(()) => {
+					self.user.fs.set_base(&listing);
+
+					return Ok(());
+				}
+				Err(Error::Conflict { theirs, .. }) if attempts > 1 => {
+					self.user.fs.rebase_listing(&theirs)?;
+
+					attempts -= 1;
+				}
+				Err(e) => return Err(e),
+			}
+		}
+	}
+
+	// local changes, revisions included, are undone, if a mutation fails half way; otherwise, the
+	// local tree would get ahead of the server's
+	fn restore_on_err<T>(&mut self, prev: Snapshot, res: Result<T, Error>) -> Result<T, Error> {
+		if res.is_err() {
+			self.user.fs.restore(prev);
+		}
+
+		self.notify();
+
+		res
+	}
+
+	fn notify(&mut self) {
+		for event in self.user.fs.take_events() {
+			// whatever was verified for a replaced or removed file is of no use any longer
+			if let Event::Updated(id) | Event::Removed { id, .. } | Event::ShareRevoked(id) = event
+			{
+				self.verifiers.retain(|(file_id, _), _| *file_id != id);
+				self.verified.retain(|(file_id, _)| *file_id != id);
+			}
+
+			for (_, observer) in &self.observers {
+				observer(&event);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+	use async_trait::async_trait;
+	use futures::executor::block_on;
+
+	use super::{Error, Network, NoNetwork, Protocol};
+	use crate::{
+		env::Env,
+		error::Cause,
+		fixtures, hmac,
+		id::Uid,
+		password_lock,
+		register::Migrated,
+		seeds::{Bundle, FinishInviteIntent, InviteIntent, LockedShare, ROOT_ID},
+		sync::{Changes, Cursor},
+		vault::{Event, LockedNode, CHUNK_SIZE},
+		version::Versioned,
+		wire::{self, Format},
+	};
+
+	const CHUNK_LEN: usize = CHUNK_SIZE as usize;
+
+	#[derive(Default)]
+	struct Backend {
+		chunks: HashMap<(Uid, u64), Vec<u8>>,
+		digests: HashMap<Uid, Vec<hmac::Digest>>,
+		nodes: Vec<LockedNode>,
+		migrated: Vec<Migrated>,
+		// reject saving nodes, if set
+		read_only: bool,
+	}
+
+	struct TestNetwork {
+		backend: Rc<RefCell<Backend>>,
+		// reject uploads of this chunk, if any
+		fail_at: Option<u64>,
+	}
+
+	#[async_trait(?Send)]
+	impl Network for TestNetwork {
+		async fn fetch_subtree(&self, _id: Uid) -> Result<Vec<LockedNode>, Error> {
+			Ok(Vec::new())
+		}
+
+		async fn upload_chunk(&self, file_id: Uid, chunk_idx: u64, ct: &[u8]) -> Result<(), Error> {
+			if self.fail_at == Some(chunk_idx) {
+				return Err(Error::NoNetwork("upload failed".to_string()));
+			}
+
+			self.backend
+				.borrow_mut()
+				.chunks
+				.insert((file_id, chunk_idx), ct.to_vec());
+
+			Ok(())
+		}
+
+		async fn upload_digests(
+			&self,
+			file_id: Uid,
+			digests: &[hmac::Digest],
+		) -> Result<(), Error> {
+			self.backend
+				.borrow_mut()
+				.digests
+				.insert(file_id, digests.to_vec());
+
+			Ok(())
+		}
+
+		async fn fetch_digests(&self, file_id: Uid) -> Result<Vec<hmac::Digest>, Error> {
+			self.backend
+				.borrow()
+				.digests
+				.get(&file_id)
+				.cloned()
+				.ok_or(Error::NotFound(file_id))
+		}
+
+		async fn fetch_chunk(&self, file_id: Uid, chunk_idx: u64) -> Result<Vec<u8>, Error> {
+			self.backend
+				.borrow()
+				.chunks
+				.get(&(file_id, chunk_idx))
+				.cloned()
+				.ok_or(Error::NotFound(file_id))
+		}
+
+		async fn save_node(
+			&self,
+			node: &LockedNode,
+			_base: Option<&hmac::Digest>,
+		) -> Result<(), Error> {
+			if self.backend.borrow().read_only {
+				return Err(Error::NoNetwork("read only".to_string()));
+			}
+
+			self.backend.borrow_mut().nodes.push(node.clone());
+
+			Ok(())
+		}
+
+		async fn delete_node(&self, id: Uid) -> Result<(), Error> {
+			self.backend.borrow_mut().nodes.retain(|n| n.id != id);
+
+			Ok(())
+		}
+
+		async fn copy_file(&self, _from: Uid, _to: Uid) -> Result<(), Error> {
+			Ok(())
+		}
+
+		async fn fetch_changes(&self, since: Cursor) -> Result<Changes, Error> {
+			Ok(Changes {
+				changes: Vec::new(),
+				cursor: since,
+			})
+		}
+
+		async fn upload_share(&self, _share: &LockedShare) -> Result<(), Error> {
+			Ok(())
+		}
+
+		async fn fetch_shares(&self, _user_id: Uid) -> Result<Vec<LockedShare>, Error> {
+			Ok(Vec::new())
+		}
+
+		async fn post_invite_intent(&self, _intent: &InviteIntent) -> Result<(), Error> {
+			Ok(())
+		}
+
+		async fn fetch_invite_intents(&self, _sender_id: Uid) -> Result<Vec<InviteIntent>, Error> {
+			Ok(Vec::new())
+		}
+
+		async fn finish_invite_intent(&self, _finish: &FinishInviteIntent) -> Result<(), Error> {
+			Ok(())
+		}
+
+		async fn save_migrated(&self, migrated: &Migrated) -> Result<(), Error> {
+			if self.backend.borrow().read_only {
+				return Err(Error::NoNetwork("read only".to_string()));
+			}
+
+			self.backend.borrow_mut().migrated.push(migrated.clone());
+
+			Ok(())
+		}
+	}
+
+	fn god_protocol(fail_at: Option<u64>) -> (Protocol, Rc<RefCell<Backend>>) {
+		let (god, locked) = fixtures::god();
+		let backend = Rc::new(RefCell::new(Backend::default()));
+		let net = TestNetwork {
+			backend: backend.clone(),
+			fail_at,
+		};
+
+		(
+			Protocol::new_with_params(
+				god.private().clone(),
+				locked,
+				Box::new(net),
+				Rc::new(Env::system()),
+			)
+			.unwrap(),
+			backend,
+		)
+	}
+
+	#[test]
+	fn test_new_from_cbor() {
+		let (god, locked) = fixtures::god();
+		let json = wire::encode(&locked, Format::Json);
+		let cbor = wire::encode(&locked, Format::Cbor);
+
+		assert!(cbor.len() < json.len());
+
+		let mut protocol = Protocol::new_with_env(
+			god.private().clone(),
+			&cbor,
+			Box::new(NoNetwork),
+			Rc::new(Env::system()),
+		)
+		.unwrap();
+
+		assert!(block_on(protocol.ls_cur_mut()).unwrap().items.is_empty());
+		assert!(protocol.integrity_report().is_clean());
+		assert_eq!(
+			Protocol::new_with_env(
+				god.private().clone(),
+				&cbor[1..],
+				Box::new(NoNetwork),
+				Rc::new(Env::system()),
+			)
+			.err(),
+			Some(Error::BadEncoding(wire::Error::BadEncoding(
+				Cause::default()
+			)))
+		);
+	}
+
+	#[test]
+	fn test_migrate() {
+		let (god, mut locked) = fixtures::god();
+		// received from self, for simplicity
+		let mut share = LockedShare::new(&god, god.public(), &Bundle::new());
+		let mut lock = password_lock::lock(god.private(), "pass").unwrap();
+
+		locked.roots[0].version = 0;
+		share.payload.version = 0;
+		locked.shares.push(share.clone());
+		lock.version = 0;
+		locked.encrypted_priv = Some(lock);
+
+		let backend = Rc::new(RefCell::new(Backend {
+			read_only: true,
+			..Backend::default()
+		}));
+		let net = TestNetwork {
+			backend: backend.clone(),
+			fail_at: None,
+		};
+		let mut protocol =
+			Protocol::unlock(&wire::encode(&locked, Format::Json), "pass", Box::new(net)).unwrap();
+
+		// kept for the next attempt
+		assert!(block_on(protocol.migrate()).is_err());
+
+		backend.borrow_mut().read_only = false;
+
+		// the node, the share and the lock
+		assert_eq!(block_on(protocol.migrate()), Ok(3));
+		assert_eq!(block_on(protocol.migrate()), Ok(0));
+		assert_eq!(backend.borrow().nodes.len(), 1);
+		assert_eq!(backend.borrow().nodes[0].id, Uid::new(ROOT_ID));
+		assert!(!backend.borrow().nodes[0].is_outdated());
+
+		let migrated = backend.borrow().migrated.clone();
+
+		assert_eq!(migrated.len(), 1);
+		assert_eq!(migrated[0].user_id, god.id());
+		assert_eq!(migrated[0].shares.len(), 1);
+		assert_eq!(migrated[0].shares[0].0, share.id());
+		assert!(!migrated[0].shares[0].1.payload.is_outdated());
+		assert_eq!(
+			migrated[0].share_for(&share),
+			Some(&migrated[0].shares[0].1)
+		);
+
+		let lock = migrated[0].encrypted_priv.as_ref().unwrap();
+
+		assert!(!lock.is_outdated());
+		assert_eq!(
+			password_lock::unlock(lock, "pass").unwrap(),
+			password_lock::unlock(locked.encrypted_priv.as_ref().unwrap(), "pass").unwrap()
+		);
+	}
+
+	#[test]
+	fn test_upload_file() {
+		let (mut protocol, backend) = god_protocol(None);
+		let content = (0..CHUNK_LEN * 2 + 7)
+			.map(|i| (i % 251) as u8)
+			.collect::<Vec<_>>();
+
+		let view = block_on(protocol.upload_file(
+			Uid::new(ROOT_ID),
+			"movie",
+			"mp4",
+			content.len() as u64,
+			&mut content.as_slice(),
+		))
+		.unwrap();
+
+		assert_eq!(view.name(), "movie");
+		assert_eq!(view.ext(), Some("mp4".to_string()));
+		assert_eq!(view.size(), content.len() as u64);
+		// the file and its parent's listing
+		assert_eq!(backend.borrow().nodes.len(), 2);
+		assert_eq!(backend.borrow().nodes[0].id, view.id());
+		assert_eq!(backend.borrow().nodes[1].id, Uid::new(ROOT_ID));
+		assert_eq!(backend.borrow().chunks.len(), 3);
+
+		let mut downloaded = Vec::new();
+
+		for idx in 0..3 {
+			let ct = backend.borrow().chunks[&(view.id(), idx)].clone();
+			let pt = block_on(protocol.chunk_decrypt_for_file(&ct, &view.id(), idx)).unwrap();
+
+			downloaded.extend(pt);
+		}
+
+		assert_eq!(downloaded, content);
+		assert!(protocol.is_fully_verified(&view.id()));
+		// its leaves are no longer needed
+		assert!(protocol.verifiers.is_empty());
+	}
+
+	#[test]
+	fn test_download_detects_tampered_digests() {
+		let (mut protocol, backend) = god_protocol(None);
+		let content = vec![1u8; CHUNK_LEN + 1];
+		let view = block_on(protocol.upload_file(
+			Uid::new(ROOT_ID),
+			"a",
+			"bin",
+			content.len() as u64,
+			&mut content.as_slice(),
+		))
+		.unwrap();
+		let ct = backend.borrow().chunks[&(view.id(), 0)].clone();
+
+		// a truncated list of digests
+		backend
+			.borrow_mut()
+			.digests
+			.get_mut(&view.id())
+			.unwrap()
+			.pop();
+
+		assert_eq!(
+			block_on(protocol.chunk_decrypt_for_file(&ct, &view.id(), 0)),
+			Err(Error::BadDigest(view.id()))
+		);
+
+		// a forged leaf
+		backend
+			.borrow_mut()
+			.digests
+			.get_mut(&view.id())
+			.unwrap()
+			.push(hmac::Digest([0u8; hmac::Digest::SIZE]));
+
+		assert_eq!(
+			block_on(protocol.chunk_decrypt_for_file(&ct, &view.id(), 0)),
+			Err(Error::BadDigest(view.id()))
+		);
+		assert!(!protocol.is_fully_verified(&view.id()));
+	}
+
+	#[test]
+	fn test_partial_download_is_verified_chunk_by_chunk() {
+		let (mut protocol, backend) = god_protocol(None);
+		let content = vec![1u8; CHUNK_LEN * 2 + 1];
+		let view = block_on(protocol.upload_file(
+			Uid::new(ROOT_ID),
+			"a",
+			"bin",
+			content.len() as u64,
+			&mut content.as_slice(),
+		))
+		.unwrap();
+		let ct1 = backend.borrow().chunks[&(view.id(), 1)].clone();
+
+		assert_eq!(
+			block_on(protocol.chunk_decrypt_for_file(&ct1, &view.id(), 1)),
+			Ok(vec![1u8; CHUNK_LEN])
+		);
+		assert!(!protocol.is_fully_verified(&view.id()));
+		assert_eq!(protocol.verifiers.len(), 1);
+
+		// a removed file isn't downloaded any further
+		block_on(protocol.delete(view.id())).unwrap();
+
+		assert!(protocol.verifiers.is_empty());
+	}
+
+	#[test]
+	fn test_upload_empty_file() {
+		let (mut protocol, backend) = god_protocol(None);
+		let view = block_on(protocol.upload_file(
+			Uid::new(ROOT_ID),
+			"empty",
+			"txt",
+			0,
+			&mut [0u8; 0].as_slice(),
+		))
+		.unwrap();
+
+		assert_eq!(backend.borrow().nodes.len(), 2);
+		assert_eq!(backend.borrow().chunks.len(), 1);
+
+		let ct = backend.borrow().chunks[&(view.id(), 0)].clone();
+
+		assert_eq!(
+			block_on(protocol.chunk_decrypt_for_file(&ct, &view.id(), 0)),
+			Ok(Vec::new())
+		);
+	}
+
+	#[test]
+	fn test_upload_not_committed_on_failure() {
+		let (mut protocol, backend) = god_protocol(Some(1));
+		let content = vec![7u8; CHUNK_LEN + 1];
+		let res = block_on(protocol.upload_file(
+			Uid::new(ROOT_ID),
+			"a",
+			"bin",
+			content.len() as u64,
+			&mut content.as_slice(),
+		));
+
+		assert_eq!(
+			res.err(),
+			Some(Error::NoNetwork("upload failed".to_string()))
+		);
+		assert!(backend.borrow().nodes.is_empty());
+		assert!(protocol
+			.user
+			.fs
+			.ls_dir(Uid::new(ROOT_ID))
+			.unwrap()
+			.is_empty());
+	}
+
+	#[test]
+	fn test_upload_size_mismatch() {
+		let (mut protocol, backend) = god_protocol(None);
+		let content = vec![7u8; 10];
+
+		assert!(matches!(
+			block_on(protocol.upload_file(
+				Uid::new(ROOT_ID),
+				"a",
+				"bin",
+				11,
+				&mut content.as_slice()
+			))
+			.err(),
+			Some(Error::BadOperation {
+				reason: "smaller than declared",
+				..
+			})
+		));
+		assert!(matches!(
+			block_on(protocol.upload_file(
+				Uid::new(ROOT_ID),
+				"a",
+				"bin",
+				9,
+				&mut content.as_slice()
+			))
+			.err(),
+			Some(Error::BadOperation {
+				reason: "larger than declared",
+				..
+			})
+		));
+		assert!(backend.borrow().nodes.is_empty());
+	}
+
+	#[test]
+	fn test_no_network() {
+		let (god, locked) = fixtures::god();
+		let json = String::from_utf8(wire::encode(&locked, Format::Json)).unwrap();
+		let mut protocol = Protocol::new_no_network(god.private().clone(), &json).unwrap();
+
+		assert!(block_on(protocol.ls_cur_mut()).unwrap().items().is_empty());
+		assert!(matches!(
+			block_on(protocol.mkdir(Uid::new(ROOT_ID), "docs")),
+			Err(Error::NoNetwork(_))
+		));
+		assert!(matches!(
+			block_on(protocol.sync()),
+			Err(Error::NoNetwork(_))
+		));
+	}
+
+	#[test]
+	fn test_mkdir_rename_delete() {
+		let (mut protocol, backend) = god_protocol(None);
+		let root = Uid::new(ROOT_ID);
+		let docs = block_on(protocol.mkdir(root, "docs")).unwrap();
+
+		assert_eq!(backend.borrow().nodes.len(), 2);
+		assert_eq!(backend.borrow().nodes[1].id, root);
+
+		let renamed = block_on(protocol.rename(docs.id(), "papers")).unwrap();
+
+		assert_eq!(renamed.name(), "papers");
+		assert_eq!(protocol.revisions().get(docs.id()), 2);
+		assert_eq!(backend.borrow().nodes.len(), 4);
+
+		block_on(protocol.cd_to_dir(&docs.id())).unwrap();
+
+		// nothing is deleted on the server, unless the parent is listed without it first
+		backend.borrow_mut().read_only = true;
+
+		assert!(block_on(protocol.delete(docs.id())).is_err());
+		assert!(backend.borrow().nodes.iter().any(|n| n.id == docs.id()));
+		assert!(protocol.user.fs.node_by_id(docs.id()).is_some());
+
+		backend.borrow_mut().read_only = false;
+		block_on(protocol.delete(docs.id())).unwrap();
+
+		let cur = block_on(protocol.ls_cur_mut()).unwrap();
+
+		assert!(cur.items().is_empty());
+		assert!(backend.borrow().nodes.iter().all(|n| n.id != docs.id()));
+		assert!(matches!(
+			block_on(protocol.rename(root, "~")),
+			Err(Error::BadOperation {
+				reason: "a root",
+				..
+			})
+		));
+	}
+
+	#[test]
+	fn test_observers() {
+		let (mut protocol, backend) = god_protocol(None);
+		let root = Uid::new(ROOT_ID);
+		let events = Rc::new(RefCell::new(Vec::new()));
+		let sub = protocol.subscribe({
+			let events = events.clone();
+
+			move |e: &Event| events.borrow_mut().push(e.clone())
+		});
+		let docs = block_on(protocol.mkdir(root, "docs")).unwrap();
+
+		block_on(protocol.rename(docs.id(), "papers")).unwrap();
+
+		assert_eq!(
+			events.take(),
+			vec![
+				Event::Added {
+					id: docs.id(),
+					parent_id: root
+				},
+				Event::Renamed {
+					id: docs.id(),
+					name: "papers".to_string()
+				},
+			]
+		);
+
+		// nothing happened in the end
+		backend.borrow_mut().read_only = true;
+
+		assert!(block_on(protocol.mkdir(root, "music")).is_err());
+		assert!(events.borrow().is_empty());
+
+		backend.borrow_mut().read_only = false;
+		protocol.unsubscribe(sub);
+		block_on(protocol.delete(docs.id())).unwrap();
+
+		assert!(events.borrow().is_empty());
+	}
+
+	#[test]
+	fn test_mutations_rolled_back_on_failure() {
+		let (mut protocol, backend) = god_protocol(None);
+		let root = Uid::new(ROOT_ID);
+		let docs = block_on(protocol.mkdir(root, "docs")).unwrap();
+		let revisions = protocol.revisions().clone();
+		let base = protocol.user.fs.base(root);
+
+		backend.borrow_mut().read_only = true;
+
+		assert!(block_on(protocol.mkdir(root, "music")).is_err());
+		assert!(block_on(protocol.rename(docs.id(), "papers")).is_err());
+		assert!(block_on(protocol.delete(docs.id())).is_err());
+
+		let items = block_on(protocol.ls_cur_mut()).unwrap().items();
+
+		assert_eq!(items.len(), 1);
+		assert_eq!(items[0].name(), "docs");
+		// or the next listing would look like a rollback to others
+		assert_eq!(protocol.revisions(), &revisions);
+		assert_eq!(protocol.user.fs.base(root), base);
+		assert!(protocol.search("papers", 10).is_empty());
+		assert!(protocol.search("music", 10).is_empty());
+		assert_eq!(protocol.search("docs", 10).len(), 1);
 	}
 }
@@ -1,21 +1,35 @@
 use serde::{Deserialize, Serialize};
 
 use crate::{
+	id::Uid,
 	identity::{self},
 	password_lock,
 	seeds::{InviteIntent, LockedShare},
+	sync::Cursor,
 	vault::LockedNode,
 };
 
 #[derive(PartialEq, Debug)]
 pub enum Error {
 	WrongPass,
-	// FIXME: include json string
-	BadJson,
+	// the offending json and what serde had to say about it
+	BadJson { json: String, reason: String },
 	ForgedSig,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::WrongPass => f.write_str("wrong password"),
+			Error::BadJson { reason, .. } => write!(f, "bad json: {}", reason),
+			Error::ForgedSig => f.write_str("forged signature"),
+		}
+	}
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct LockedUser {
 	// password-encrypted identity::Private; used by admins only
 	pub encrypted_priv: Option<password_lock::Lock>,
@@ -27,6 +41,49 @@ pub struct LockedUser {
 	// sent and optionally acked shares (could be useful to cancel, if not yet accepted)
 	pub pending_invite_intents: Vec<InviteIntent>,
 	// get_nodes(locked_shares(user_id == share.receiver | user_id == 0 then node_id_root).export.fs.ids + children)
-	// TODO: include a hash of the hierarchy for later checks
+	// dirs commit to their children in their own content (see FileSystem::list)
 	pub roots: Vec<LockedNode>,
+	// where the backend's change log was, when roots were collected; see sync
+	#[serde(default)]
+	pub cursor: Cursor,
+}
+
+// what unlocking found in outdated layouts and migrated, to be stored in place of the originals;
+// see Protocol::migrate
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Migrated {
+	pub user_id: Uid,
+	// replaces LockedUser::encrypted_priv, if set
+	pub encrypted_priv: Option<password_lock::Lock>,
+	// shares received by user_id with their payloads re-encrypted, by the ids of the ones they
+	// replace (see LockedShare::id)
+	pub shares: Vec<(Uid, LockedShare)>,
+}
+
+impl Migrated {
+	pub fn is_empty(&self) -> bool {
+		self.encrypted_priv.is_none() && self.shares.is_empty()
+	}
+
+	// what share is to be replaced with, if anything: only its receiver may re-encrypt its
+	// payload, the rest is signed by its sender and stays as is
+	pub fn share_for(&self, share: &LockedShare) -> Option<&LockedShare> {
+		if share.export.receiver != self.user_id {
+			return None;
+		}
+
+		let id = share.id();
+
+		self.shares
+			.iter()
+			.find(|(old, new)| {
+				*old == id
+					&& *new
+						== LockedShare {
+							payload: new.payload.clone(),
+							..share.clone()
+						}
+			})
+			.map(|(_, new)| new)
+	}
 }
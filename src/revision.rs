@@ -0,0 +1,88 @@
+// every locked node carries a signed revision, which grows with each edit; the server could still
+// serve an older, validly signed node, so clients remember the highest revision seen per node and
+// reject anything below it
+//
+// the store is only as good as its persistence: a fresh one trusts whatever comes first, so clients
+// are expected to save it (it's serializable) and restore it with FileSystem::track_revisions
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::id::Uid;
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug)]
+pub struct Revisions {
+	seen: HashMap<Uid, u64>,
+}
+
+impl Revisions {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	// 0 for unknown and legacy nodes
+	pub fn get(&self, id: Uid) -> u64 {
+		self.seen.get(&id).copied().unwrap_or(0)
+	}
+
+	// records a revision; Err with the highest one seen, if it goes backwards
+	pub fn observe(&mut self, id: Uid, revision: u64) -> Result<(), u64> {
+		let seen = self.seen.entry(id).or_default();
+
+		if revision < *seen {
+			Err(*seen)
+		} else {
+			*seen = revision;
+
+			Ok(())
+		}
+	}
+
+	// what's been seen of id, if anything, to be put back with reset; see FileSystem::snapshot
+	pub(crate) fn seen(&self, id: Uid) -> Option<u64> {
+		self.seen.get(&id).copied()
+	}
+
+	// puts back what was seen of id, once an edit which failed half way is undone
+	pub(crate) fn reset(&mut self, id: Uid, seen: Option<u64>) {
+		match seen {
+			Some(seen) => _ = self.seen.insert(id, seen),
+			None => _ = self.seen.remove(&id),
+		}
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = (Uid, u64)> + '_ {
+		self.seen.iter().map(|(id, rev)| (*id, *rev))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::Revisions;
+	use crate::{
+		id::Uid,
+		wire::{self, Format},
+	};
+
+	#[test]
+	fn test_observe() {
+		let mut revs = Revisions::new();
+		let id = Uid::new(1);
+
+		assert_eq!(revs.get(id), 0);
+		assert_eq!(revs.observe(id, 2), Ok(()));
+		// replays of the same revision are fine
+		assert_eq!(revs.observe(id, 2), Ok(()));
+		assert_eq!(revs.observe(id, 1), Err(2));
+		assert_eq!(revs.get(id), 2);
+		assert_eq!(revs.observe(id, 3), Ok(()));
+
+		for format in [Format::Json, Format::Cbor] {
+			assert_eq!(
+				wire::decode::<Revisions>(&wire::encode(&revs, format)).unwrap(),
+				revs
+			);
+		}
+	}
+}
@@ -1,9 +1,9 @@
 use crate::{
 	base64_blobs::{deserialize_array_base64, serialize_array_base64},
+	env::Rng,
 	hmac,
 };
 use rand::rngs::OsRng;
-use rand::RngCore;
 use serde::{Deserialize, Serialize};
 
 const SALT_SIZE: usize = hmac::Key::SIZE;
@@ -21,8 +21,12 @@ impl Salt {
 	pub const SIZE: usize = SALT_SIZE;
 
 	pub fn generate() -> Self {
+		Self::generate_with(&mut OsRng)
+	}
+
+	pub fn generate_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
 		let mut bytes = [0u8; Self::SIZE];
-		OsRng.fill_bytes(&mut bytes);
+		rng.fill_bytes(&mut bytes);
 
 		Self { bytes }
 	}
@@ -0,0 +1,315 @@
+// an in-memory index over the decrypted names, extensions and paths of a tree, kept up to date by
+// FileSystem as nodes are attached and detached; it's plaintext, so it's never persisted
+//
+// a query is a list of whitespace-separated terms, all of which must match: a term with a / is
+// looked up in paths, any other one matches words of names and extensions by prefix or, if it's
+// long enough, with a typo or two
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::{
+	id::Uid,
+	vault::{Entry, Node},
+};
+
+// terms shorter than this are matched by prefix only
+const FUZZY_MIN_LEN: usize = 3;
+
+#[derive(Debug, Clone)]
+struct Indexed {
+	name: String,
+	parent_id: Uid,
+	words: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Index {
+	nodes: HashMap<Uid, Indexed>,
+	// lowercase words to the nodes having them
+	words: BTreeMap<String, HashSet<Uid>>,
+}
+
+// a name, its parts and its ext, lowercase; "Q3 report.final.PDF" gives q3 report.final, q3, report,
+// final and pdf
+fn words_of(node: &Node) -> Vec<String> {
+	let name = node.name.to_lowercase();
+	let mut words: Vec<String> = name
+		.split(|c: char| !c.is_alphanumeric())
+		.filter(|w| !w.is_empty())
+		.map(str::to_string)
+		.collect();
+
+	if words.len() > 1 {
+		words.push(name);
+	}
+
+	if let Entry::File { ref info } = node.entry {
+		if !info.ext.is_empty() {
+			words.push(info.ext.to_lowercase());
+		}
+	}
+
+	words.sort();
+	words.dedup();
+
+	words
+}
+
+// at most max edits apart
+fn within(a: &str, b: &str, max: usize) -> bool {
+	let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+
+	if a.len().abs_diff(b.len()) > max {
+		return false;
+	}
+
+	let mut prev: Vec<usize> = (0..=b.len()).collect();
+
+	for (i, ca) in a.iter().enumerate() {
+		let mut cur = vec![i + 1; b.len() + 1];
+
+		for (j, cb) in b.iter().enumerate() {
+			cur[j + 1] = (prev[j] + (ca != cb) as usize)
+				.min(prev[j + 1] + 1)
+				.min(cur[j] + 1);
+		}
+
+		if cur.iter().min().is_some_and(|&m| m > max) {
+			return false;
+		}
+
+		prev = cur;
+	}
+
+	prev[b.len()] <= max
+}
+
+impl Index {
+	pub fn of(roots: &[Node]) -> Self {
+		let mut index = Self::default();
+
+		for root in roots {
+			index.insert(root);
+		}
+
+		index
+	}
+
+	// node and its subtree
+	pub fn insert(&mut self, node: &Node) {
+		let words = words_of(node);
+
+		for word in &words {
+			self.words.entry(word.clone()).or_default().insert(node.id);
+		}
+
+		self.nodes.insert(
+			node.id,
+			Indexed {
+				name: node.name.clone(),
+				parent_id: node.parent_id,
+				words,
+			},
+		);
+
+		if let Entry::Dir { ref children, .. } = node.entry {
+			for child in children {
+				self.insert(child);
+			}
+		}
+	}
+
+	// node and its subtree
+	pub fn remove(&mut self, node: &Node) {
+		if let Some(indexed) = self.nodes.remove(&node.id) {
+			for word in indexed.words {
+				if let Some(ids) = self.words.get_mut(&word) {
+					ids.remove(&node.id);
+
+					if ids.is_empty() {
+						self.words.remove(&word);
+					}
+				}
+			}
+		}
+
+		if let Entry::Dir { ref children, .. } = node.entry {
+			for child in children {
+				self.remove(child);
+			}
+		}
+	}
+
+	// /-separated names from the topmost indexed ancestor, which is / for god
+	pub fn path(&self, id: Uid) -> Option<String> {
+		let mut names = Vec::new();
+		let mut cur = self.nodes.get(&id)?;
+
+		loop {
+			if cur.name != "/" {
+				names.push(cur.name.as_str());
+			}
+
+			match self.nodes.get(&cur.parent_id) {
+				Some(parent) => cur = parent,
+				None => break,
+			}
+		}
+
+		names.reverse();
+
+		Some(format!("/{}", names.join("/")))
+	}
+
+	// scores of the nodes matching term: 3 for a whole word, 2 for a prefix and 1 for a typo
+	fn matches(&self, term: &str) -> HashMap<Uid, u32> {
+		let mut scores: HashMap<Uid, u32> = HashMap::new();
+		let mut add = |id: Uid, score: u32| {
+			let cur = scores.entry(id).or_default();
+
+			*cur = (*cur).max(score);
+		};
+
+		if term.contains('/') {
+			for id in self.nodes.keys() {
+				if self
+					.path(*id)
+					.is_some_and(|p| p.to_lowercase().contains(term))
+				{
+					add(*id, 2);
+				}
+			}
+		} else {
+			for (word, ids) in self.words.range(term.to_string()..) {
+				if !word.starts_with(term) {
+					break;
+				}
+
+				ids.iter()
+					.for_each(|id| add(*id, if word == term { 3 } else { 2 }));
+			}
+
+			if term.chars().count() >= FUZZY_MIN_LEN {
+				let max = if term.chars().count() >= 6 { 2 } else { 1 };
+
+				for (word, ids) in &self.words {
+					if within(term, word, max) {
+						ids.iter().for_each(|id| add(*id, 1));
+					}
+				}
+			}
+		}
+
+		scores
+	}
+
+	// best matches first, at most limit of them
+	pub fn search(&self, query: &str, limit: usize) -> Vec<Uid> {
+		let query = query.to_lowercase();
+		let mut terms = query.split_whitespace();
+		let Some(first) = terms.next() else {
+			return Vec::new();
+		};
+		let mut scores = self.matches(first);
+
+		for term in terms {
+			let matches = self.matches(term);
+
+			scores.retain(|id, score| match matches.get(id) {
+				Some(s) => {
+					*score += s;
+
+					true
+				}
+				None => false,
+			});
+		}
+
+		let mut hits: Vec<(u32, &str, Uid)> = scores
+			.into_iter()
+			.map(|(id, score)| (score, self.nodes[&id].name.as_str(), id))
+			.collect();
+
+		hits.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)).then(a.2.cmp(&b.2)));
+
+		hits.into_iter().take(limit).map(|(.., id)| id).collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use futures::executor::block_on;
+
+	use super::within;
+	use crate::{
+		backend::Backend,
+		fixtures::{login, register_god},
+		id::Uid,
+		identity::Identity,
+		seeds::ROOT_ID,
+		user::{User, GOD_ID},
+		vault::FileSystem,
+	};
+
+	#[test]
+	fn test_within() {
+		assert!(within("report", "report", 0));
+		assert!(within("reprot", "report", 2));
+		assert!(!within("reprot", "report", 1));
+		assert!(within("invoce", "invoice", 1));
+		assert!(!within("cat", "dog", 2));
+	}
+
+	#[test]
+	fn test_index_follows_tree() {
+		let god = Identity::generate(Uid::new(GOD_ID));
+		let (mut fs, _) = FileSystem::new(&User::fs_seed(god.private()), &god);
+		let root = Uid::new(ROOT_ID);
+		let (docs, _) = fs.mkdir_mut(root, "Docs", &god).unwrap();
+		let (q3, _) = fs.touch_mut(docs, 0, "Q3 report", "pdf", &god).unwrap();
+		let (q4, _) = fs.touch_mut(docs, 0, "Q4 report", "txt", &god).unwrap();
+		let (invoice, _) = fs.touch_mut(root, 0, "invoice", "pdf", &god).unwrap();
+
+		assert_eq!(fs.search("rep", 10).len(), 2);
+		assert_eq!(fs.search("report pdf", 10), vec![q3]);
+		assert_eq!(fs.search("invoce", 10), vec![invoice]);
+		assert_eq!(fs.search("/docs/q4", 10), vec![q4]);
+		assert_eq!(fs.search("docs", 10)[0], docs);
+		assert!(fs.search("", 10).is_empty());
+
+		let mut renamed = fs.node_by_id(q4).unwrap().clone();
+
+		renamed.name = "summary".to_string();
+		fs.update_node(renamed).unwrap();
+
+		assert_eq!(fs.search("rep", 10), vec![q3]);
+		assert_eq!(fs.search("summ", 10), vec![q4]);
+
+		fs.delete_node(docs).unwrap();
+
+		assert!(fs.search("summary", 10).is_empty());
+		assert!(fs.search("report", 10).is_empty());
+		assert_eq!(fs.search("pdf", 10), vec![invoice]);
+	}
+
+	#[test]
+	fn test_protocol_search_after_sync() {
+		let backend = Backend::new();
+		let god = register_god(&backend);
+		let mut laptop = login(&backend, &god);
+		let mut phone = login(&backend, &god);
+		let docs = block_on(laptop.mkdir(Uid::new(ROOT_ID), "docs")).unwrap();
+
+		block_on(laptop.upload_file(docs.id(), "minutes", "md", 2, &mut b"hi".as_slice())).unwrap();
+
+		assert!(phone.search("minutes", 10).is_empty());
+
+		block_on(phone.sync()).unwrap();
+
+		let hits = phone.search("minuets", 10);
+
+		assert_eq!(hits.len(), 1);
+		assert_eq!((hits[0].name(), hits[0].size()), ("minutes".to_string(), 2));
+		assert_eq!(phone.search("/docs/", 10).len(), 1);
+	}
+}
@@ -1,21 +1,26 @@
 use std::collections::HashMap;
 
-use rand::{rngs::OsRng, RngCore};
+use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroize;
 
 use crate::{
 	base64_blobs::{deserialize_array_base64, serialize_array_base64},
-	database, ed25519, hmac,
+	database, ed25519,
+	env::{Env, Rng},
+	hmac,
 	id::Uid,
 	identity, password_lock,
 	vault::LockedNode,
+	version::Versioned,
 };
 
 pub const SEED_SIZE: usize = 32;
 pub const ROOT_ID: u64 = 0;
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Hash)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Seed {
 	#[serde(
 		serialize_with = "serialize_array_base64::<_, SEED_SIZE>",
@@ -26,13 +31,35 @@ pub struct Seed {
 
 impl Seed {
 	pub fn generate() -> Self {
+		Self::generate_with(&mut OsRng)
+	}
+
+	pub fn generate_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
 		let mut bytes = [0u8; SEED_SIZE];
-		OsRng.fill_bytes(&mut bytes);
+		rng.fill_bytes(&mut bytes);
 
 		Self { bytes }
 	}
 }
 
+impl PartialEq for Seed {
+	fn eq(&self, other: &Self) -> bool {
+		self.bytes.ct_eq(&other.bytes).into()
+	}
+}
+
+impl std::fmt::Debug for Seed {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Seed").finish_non_exhaustive()
+	}
+}
+
+impl Drop for Seed {
+	fn drop(&mut self) {
+		self.bytes.zeroize();
+	}
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 // sender can share as many bundles as he wants
 pub struct Import {
@@ -89,22 +116,88 @@ impl Export {
 		hmac::Digest(sha.into())
 	}
 }
-pub fn ctx_to_sign(sender: &identity::Public, export: &Export) -> Vec<u8> {
-	[sender.id().as_bytes().as_slice(), export.hash().as_bytes()].concat()
+// version is LockedShare::version; shares predating versioning are signed without it
+pub fn ctx_to_sign(sender: &identity::Public, export: &Export, version: u32) -> Vec<u8> {
+	let mut ctx = [sender.id().as_bytes().as_slice(), export.hash().as_bytes()].concat();
+
+	if version > 0 {
+		ctx.extend_from_slice(&version.to_be_bytes());
+	}
+
+	ctx
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 // when unlocking, the backend is to return all LockedShare where id == sender.id() || export.receiver
 pub struct LockedShare {
+	// see Versioned; 0 for shares predating versioning
+	#[serde(default)]
+	pub version: u32,
 	pub sender: identity::Public,
 	// ids of the share (convenient to return roots to unlock)
 	pub export: Export,
 	// encrypted content of the share
 	pub payload: identity::Encrypted,
-	// sign({ sender, exports })
+	// sign({ sender, exports, version })
 	pub sig: ed25519::Signature,
 }
 
+impl Versioned for LockedShare {
+	const VERSION: u32 = 1;
+
+	fn version(&self) -> u32 {
+		self.version
+	}
+}
+
+impl LockedShare {
+	pub fn new(sender: &identity::Identity, receiver: &identity::Public, bundle: &Bundle) -> Self {
+		Self::new_with(sender, receiver, bundle, &mut OsRng)
+	}
+
+	pub fn new_with<R: Rng + ?Sized>(
+		sender: &identity::Identity,
+		receiver: &identity::Public,
+		bundle: &Bundle,
+		rng: &mut R,
+	) -> Self {
+		let export = Export::from_bundle(bundle, receiver.id());
+		let sig = sender
+			.private()
+			.sign(&ctx_to_sign(sender.public(), &export, Self::VERSION));
+
+		Self {
+			version: Self::VERSION,
+			sender: sender.public().clone(),
+			payload: receiver.encrypt_with(bundle, rng),
+			export,
+			sig,
+		}
+	}
+
+	// shares have no ids of their own; the payload is freshly encrypted for each share, while the
+	// signature is not, so both are needed to tell a forged copy from the original
+	pub fn id(&self) -> Uid {
+		Uid::from_bytes(&[self.sig.as_bytes().as_slice(), self.payload.ct()].concat())
+	}
+
+	// the payload is not signed, so its receiver can re-encrypt an outdated one to itself; the
+	// share's own version is signed, so only its sender can bump it, by sharing again. None, if the
+	// payload is up to date
+	pub fn migrate(
+		&self,
+		receiver: &identity::Identity,
+		env: &Env,
+	) -> Result<Option<Self>, identity::Error> {
+		let payload = self.payload.migrate(receiver, &mut **env.rng())?;
+
+		Ok(payload.map(|payload| Self {
+			payload,
+			..self.clone()
+		}))
+	}
+}
+
 // used by pin-based invites only
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct Invite {
@@ -162,7 +255,7 @@ impl InviteIntent {
 	}
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct FinishInviteIntent {
 	// email or activation code
 	pub ref_src: String,
@@ -215,3 +308,59 @@ impl Bundle {
 		self.db.insert(id, seed);
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{ctx_to_sign, Bundle, LockedShare, Seed};
+	use crate::{env::Env, id::Uid, identity::Identity, version::Versioned};
+
+	#[test]
+	fn test_migrate_share() {
+		let env = Env::system();
+		let sender = Identity::generate(Uid::new(0));
+		let receiver = Identity::generate(Uid::new(1));
+		let mut bundle = Bundle::new();
+		bundle.set_fs(Uid::new(7), Seed::generate());
+
+		let mut share = LockedShare::new(&sender, receiver.public(), &bundle);
+
+		assert!(share.migrate(&receiver, &env).unwrap().is_none());
+
+		share.payload.version = 0;
+
+		assert!(share.migrate(&sender, &env).is_err());
+
+		let migrated = share.migrate(&receiver, &env).unwrap().unwrap();
+
+		assert!(!migrated.payload.is_outdated());
+		assert_eq!(migrated.version, share.version);
+		assert_eq!(migrated.sig, share.sig);
+		assert_eq!(
+			receiver.private().decrypt(&migrated.payload).unwrap(),
+			receiver.private().decrypt(&share.payload).unwrap()
+		);
+
+		share.payload.version = LockedShare::VERSION + 1;
+
+		assert!(receiver.private().decrypt(&share.payload).is_err());
+	}
+
+	#[test]
+	fn test_version_is_signed() {
+		let sender = Identity::generate(Uid::new(0));
+		let receiver = Identity::generate(Uid::new(1));
+		let mut share = LockedShare::new(&sender, receiver.public(), &Bundle::new());
+		let verify = |share: &LockedShare| {
+			sender.public().verify(
+				&share.sig,
+				&ctx_to_sign(&share.sender, &share.export, share.version),
+			)
+		};
+
+		assert!(verify(&share));
+
+		share.version = 0;
+
+		assert!(!verify(&share));
+	}
+}
@@ -0,0 +1,78 @@
+// a backend keeps an ordered log of node changes, so that clients can catch up incrementally
+// instead of refetching whole subtrees: each page of changes comes with a cursor to continue from
+//
+// the log is compacted: a node appears once, at the position of its latest change, as saved (its
+// current locked state, moves included) or deleted
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{id::Uid, vault::LockedNode};
+
+// a position in a change log; 0 is its very beginning
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Cursor(pub u64);
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum Change {
+	Saved(LockedNode),
+	Deleted(Uid),
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Changes {
+	// oldest first; empty, when up to date
+	pub changes: Vec<Change>,
+	// to fetch the next page from
+	pub cursor: Cursor,
+}
+
+// at most this many changes per page
+const CHANGES_PER_PAGE: usize = 256;
+
+// the log as kept by a backend: node ids by the position of their latest change, and vice versa
+#[derive(Serialize, Deserialize, Default, PartialEq, Debug)]
+pub struct ChangeLog {
+	log: BTreeMap<u64, Uid>,
+	positions: HashMap<Uid, u64>,
+	// the latest position; never reused, even if compacted away
+	cursor: Cursor,
+}
+
+impl ChangeLog {
+	// moves id to the end of the log, whether it was saved or deleted
+	pub fn log(&mut self, id: Uid) {
+		if let Some(pos) = self.positions.get(&id) {
+			self.log.remove(pos);
+		}
+
+		self.cursor.0 += 1;
+		self.log.insert(self.cursor.0, id);
+		self.positions.insert(id, self.cursor.0);
+	}
+
+	pub fn cursor(&self) -> Cursor {
+		self.cursor
+	}
+
+	// the page after since; node is the current state of a changed node, None once it's deleted
+	pub fn page<E>(
+		&self,
+		since: Cursor,
+		mut node: impl FnMut(Uid) -> Result<Option<LockedNode>, E>,
+	) -> Result<Changes, E> {
+		let mut changes = Vec::new();
+		let mut cursor = since;
+
+		for (&pos, &id) in self.log.range(since.0 + 1..).take(CHANGES_PER_PAGE) {
+			changes.push(match node(id)? {
+				Some(node) => Change::Saved(node),
+				None => Change::Deleted(id),
+			});
+			cursor = Cursor(pos);
+		}
+
+		Ok(Changes { changes, cursor })
+	}
+}
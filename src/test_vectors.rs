@@ -0,0 +1,506 @@
+// known-answer vectors for every derivation other implementations (php backend, mobile and web
+// clients) need to match; they live in vectors/kat.json and are regenerated from a seeded rng and a
+// fixed clock, so any change to a derivation or to the wire format shows up here first
+//
+// to regenerate after an intentional change: QVAULT_WRITE_VECTORS=1 cargo test test_vectors
+
+use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	aes_gcm::Aes,
+	base64_blobs::{deserialize_vec_base64, serialize_vec_base64},
+	database, ed25519,
+	env::{Env, FixedClock},
+	hmac,
+	id::Uid,
+	identity::Identity,
+	merkle,
+	salt::Salt,
+	seeds::{self, Bundle, LockedShare, Seed, ROOT_ID},
+	user::User,
+	vault::{self, Entry, FileInfo, LockedNode, Node},
+	version::Versioned,
+	wire::{self, Format},
+};
+
+const FIXTURE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/vectors/kat.json");
+const FIXTURE: &str = include_str!("../vectors/kat.json");
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Bytes(
+	#[serde(
+		serialize_with = "serialize_vec_base64",
+		deserialize_with = "deserialize_vec_base64"
+	)]
+	Vec<u8>,
+);
+
+// seed_from_parent_for_node
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct NodeSeedVector {
+	parent: Seed,
+	id: Uid,
+	seed: Seed,
+}
+
+// aes_from_node_seed
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct NodeKeyVector {
+	seed: Seed,
+	salt: Salt,
+	key_iv: Aes,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct LockedNodeVector {
+	// the parent dir's seed; None for the root, which is locked with User::fs_seed as is
+	parent_seed: Option<Seed>,
+	node_seed: Seed,
+	salt: Salt,
+	signer: Identity,
+	// plaintext json encrypted into locked.content
+	content: String,
+	locked: LockedNode,
+}
+
+// Aes::key_for_chunk_idx
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct ChunkVector {
+	key_iv: Aes,
+	idx: u64,
+	chunk_key_iv: Aes,
+	pt: Bytes,
+	ct: Bytes,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct MerkleVector {
+	key_iv: Aes,
+	digest_key: Bytes,
+	chunk_size: u32,
+	chunks: Vec<Bytes>,
+	leaves: Vec<hmac::Digest>,
+	root: hmac::Digest,
+}
+
+// database::derive_*
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct DatabaseVector {
+	root: Seed,
+	table: String,
+	column: String,
+	salt: Salt,
+	table_id: Uid,
+	column_id: Uid,
+	table_seed: Seed,
+	column_seed: Seed,
+	entry_seed: Seed,
+}
+
+// User::fs_seed, User::db_seed
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct UserSeedsVector {
+	identity: Identity,
+	fs_seed: Seed,
+	db_seed: Seed,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct SignatureVector {
+	signer: Identity,
+	msg: Bytes,
+	sig: ed25519::Signature,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct ShareVector {
+	sender: Identity,
+	receiver: Identity,
+	bundle: Bundle,
+	export_hash: hmac::Digest,
+	// seeds::ctx_to_sign
+	to_sign: Bytes,
+	share: LockedShare,
+}
+
+// wire::encode with Format::Cbor of locked_nodes and share above
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct CborVector {
+	locked_nodes: Vec<Bytes>,
+	share: Bytes,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+struct Vectors {
+	node_seeds: Vec<NodeSeedVector>,
+	node_keys: Vec<NodeKeyVector>,
+	locked_nodes: Vec<LockedNodeVector>,
+	chunks: Vec<ChunkVector>,
+	merkle: MerkleVector,
+	database: DatabaseVector,
+	user_seeds: UserSeedsVector,
+	signatures: Vec<SignatureVector>,
+	share: ShareVector,
+	cbor: CborVector,
+}
+
+fn seed(env: &Env) -> Seed {
+	Seed::generate_with(&mut **env.rng())
+}
+
+fn salt(env: &Env) -> Salt {
+	Salt::generate_with(&mut **env.rng())
+}
+
+fn identity(id: u64, env: &Env) -> Identity {
+	Identity::generate_with(Uid::new(id), &mut **env.rng())
+}
+
+fn lock_node(
+	node: &Node,
+	parent_seed: Option<&Seed>,
+	node_seed: Seed,
+	signer: &Identity,
+	env: &Env,
+) -> LockedNodeVector {
+	let salt = salt(env);
+	let locked = Node::encrypt_with_salt(
+		node,
+		node.locked_entry(),
+		&node_seed,
+		signer.private(),
+		salt.clone(),
+		Format::Json,
+	);
+	let content = vault::aes_from_node_seed(&node_seed, &salt)
+		.decrypt(&locked.content.ct)
+		.unwrap();
+
+	LockedNodeVector {
+		parent_seed: parent_seed.cloned(),
+		node_seed,
+		salt,
+		signer: signer.clone(),
+		content: String::from_utf8(content).unwrap(),
+		locked,
+	}
+}
+
+fn generate() -> Vectors {
+	let env = Env::new(
+		ChaCha20Rng::from_seed([0x42; 32]),
+		FixedClock(1_700_000_000_000),
+	);
+
+	let node_seeds = [ROOT_ID, 1, u64::MAX]
+		.into_iter()
+		.map(|id| {
+			let parent = seed(&env);
+			let id = Uid::new(id);
+			let seed = vault::seed_from_parent_for_node(&parent, id);
+
+			NodeSeedVector { parent, id, seed }
+		})
+		.collect();
+
+	let node_keys = (0..2)
+		.map(|_| {
+			let seed = seed(&env);
+			let salt = salt(&env);
+			let key_iv = vault::aes_from_node_seed(&seed, &salt);
+
+			NodeKeyVector { seed, salt, key_iv }
+		})
+		.collect();
+
+	let chunk_key_iv = Aes::generate_with(&mut **env.rng());
+	let chunks = [0, 1, u32::MAX as u64, u32::MAX as u64 + 1]
+		.into_iter()
+		.map(|idx| {
+			let pt = format!("chunk #{}", idx).into_bytes();
+
+			ChunkVector {
+				key_iv: chunk_key_iv.clone(),
+				idx,
+				chunk_key_iv: chunk_key_iv.key_for_chunk_idx(idx),
+				ct: Bytes(chunk_key_iv.chunk_encrypt(idx, &pt)),
+				pt: Bytes(pt),
+			}
+		})
+		.collect();
+
+	// a file of three chunks, 16 bytes each
+	let chunk_size = 16;
+	let content = b"the quick brown fox jumps over the lazy dog".to_vec();
+	let mut file_info = FileInfo {
+		size: content.len() as u64,
+		key_iv: Aes::generate_with(&mut **env.rng()),
+		ext: "txt".to_string(),
+		chunk_size: Some(chunk_size),
+		digest: None,
+	};
+	let digest_key = file_info.digest_key();
+	let leaves = content
+		.chunks(chunk_size as usize)
+		.enumerate()
+		.map(|(idx, pt)| merkle::leaf(&digest_key, idx as u64, pt))
+		.collect::<Vec<_>>();
+	let root = merkle::root(&leaves);
+	file_info.digest = Some(root);
+
+	let merkle = MerkleVector {
+		key_iv: file_info.key_iv.clone(),
+		digest_key: Bytes(digest_key.as_bytes().to_vec()),
+		chunk_size,
+		chunks: content
+			.chunks(chunk_size as usize)
+			.map(|c| Bytes(c.to_vec()))
+			.collect(),
+		leaves,
+		root,
+	};
+
+	let owner = identity(0, &env);
+	let fs_seed = User::fs_seed(owner.private());
+	let root_seed = seed(&env);
+	let root = Node {
+		id: Uid::new(ROOT_ID),
+		parent_id: Uid::new(vault::NO_PARENT_ID),
+		created_at: env.now(),
+		name: "/".to_string(),
+		entry: Entry::Dir {
+			seed: root_seed.clone(),
+			children: vec![],
+		},
+		dirty: false,
+		created_by: owner.public().clone(),
+		revision: 0,
+	};
+	// rng borrows are kept out of struct literals, since temporaries live until the end of it
+	let dir_seed = seed(&env);
+	let dir_id = Uid::generate_with(&mut **env.rng());
+	let dir = Node {
+		id: dir_id,
+		parent_id: Uid::new(ROOT_ID),
+		created_at: env.now(),
+		name: "docs".to_string(),
+		entry: Entry::Dir {
+			seed: dir_seed.clone(),
+			children: vec![],
+		},
+		dirty: false,
+		created_by: owner.public().clone(),
+		revision: 0,
+	};
+	let legacy_id = Uid::generate_with(&mut **env.rng());
+	let legacy_file = Node {
+		id: legacy_id,
+		parent_id: dir.id,
+		created_at: env.now(),
+		name: "legacy".to_string(),
+		entry: Entry::File {
+			info: FileInfo {
+				size: 1024,
+				key_iv: Aes::generate_with(&mut **env.rng()),
+				ext: "bin".to_string(),
+				chunk_size: None,
+				digest: None,
+			},
+		},
+		dirty: false,
+		created_by: owner.public().clone(),
+		revision: 0,
+	};
+	let file_id = Uid::generate_with(&mut **env.rng());
+	let file = Node {
+		id: file_id,
+		parent_id: dir.id,
+		created_at: env.now(),
+		name: "fox".to_string(),
+		entry: Entry::File { info: file_info },
+		dirty: false,
+		created_by: owner.public().clone(),
+		// signed along with the rest, unlike the revision 0 of the nodes above
+		revision: 2,
+	};
+	let child = |node: &Node, parent_seed: &Seed| {
+		let node_seed = vault::seed_from_parent_for_node(parent_seed, node.id);
+
+		lock_node(node, Some(parent_seed), node_seed, &owner, &env)
+	};
+	let locked_nodes = vec![
+		lock_node(&root, None, fs_seed, &owner, &env),
+		child(&dir, &root_seed),
+		child(&legacy_file, &dir_seed),
+		child(&file, &dir_seed),
+	];
+	// what a share of dir boils down to
+	let dir_share = locked_nodes[1].node_seed.clone();
+
+	let db_root = seed(&env);
+	let (table, column) = ("users", "email");
+	let db_salt = salt(&env);
+	let table_seed = database::derive_table_seed_from_root(&db_root, table);
+	let column_seed = database::derive_column_seed_from_table(&table_seed, column);
+	let database = DatabaseVector {
+		entry_seed: database::derive_entry_seed_from_column(&column_seed, &db_salt),
+		table_id: database::id_for_table(table),
+		column_id: database::id_for_column(table, column),
+		root: db_root,
+		table: table.to_string(),
+		column: column.to_string(),
+		salt: db_salt,
+		table_seed,
+		column_seed,
+	};
+
+	let user_seeds = UserSeedsVector {
+		fs_seed: User::fs_seed(owner.private()),
+		db_seed: User::db_seed(owner.private()),
+		identity: owner.clone(),
+	};
+
+	let user_seeds_table_seed = database::derive_table_seed_from_root(&user_seeds.db_seed, table);
+
+	let signatures = [b"".as_slice(), b"qvault"]
+		.into_iter()
+		.map(|msg| SignatureVector {
+			signer: owner.clone(),
+			msg: Bytes(msg.to_vec()),
+			sig: owner.private().sign(msg),
+		})
+		.collect();
+
+	// one seed per map, so that the serialized bundle does not depend on HashMap order
+	let receiver = identity(1, &env);
+	let mut bundle = Bundle::new();
+	bundle.set_fs(dir.id, dir_share);
+	bundle.set_db(database::id_for_table(table), user_seeds_table_seed);
+	let locked_share = LockedShare::new_with(&owner, receiver.public(), &bundle, &mut **env.rng());
+	let to_sign = seeds::ctx_to_sign(owner.public(), &locked_share.export, LockedShare::VERSION);
+	let share = ShareVector {
+		export_hash: locked_share.export.hash(),
+		share: locked_share,
+		to_sign: Bytes(to_sign),
+		sender: owner,
+		receiver,
+		bundle,
+	};
+
+	let cbor = CborVector {
+		locked_nodes: locked_nodes
+			.iter()
+			.map(|v| Bytes(wire::encode(&v.locked, Format::Cbor)))
+			.collect(),
+		share: Bytes(wire::encode(&share.share, Format::Cbor)),
+	};
+
+	Vectors {
+		node_seeds,
+		node_keys,
+		locked_nodes,
+		chunks,
+		merkle,
+		database,
+		user_seeds,
+		signatures,
+		share,
+		cbor,
+	}
+}
+
+fn fixture() -> Vectors {
+	serde_json::from_str(FIXTURE).unwrap()
+}
+
+#[test]
+fn test_vectors_match_fixture() {
+	let vectors = generate();
+
+	if std::env::var_os("QVAULT_WRITE_VECTORS").is_some() {
+		let json = serde_json::to_string_pretty(&vectors).unwrap();
+
+		std::fs::write(FIXTURE_PATH, json + "\n").unwrap();
+	} else {
+		assert!(
+			vectors == fixture(),
+			"derivations no longer match {}; regenerate it, if intended",
+			FIXTURE_PATH
+		);
+	}
+}
+
+// checks the fixture is self-consistent when consumed the way other clients would
+#[test]
+fn test_fixture_decodes() {
+	let Vectors {
+		node_seeds,
+		locked_nodes,
+		chunks,
+		merkle,
+		signatures,
+		share,
+		cbor,
+		..
+	} = fixture();
+
+	for (v, bytes) in locked_nodes.iter().zip(cbor.locked_nodes) {
+		assert_eq!(v.locked, wire::decode(&bytes.0).unwrap());
+	}
+
+	assert_eq!(share.share, wire::decode(&cbor.share.0).unwrap());
+
+	for v in node_seeds {
+		assert_eq!(v.seed, vault::seed_from_parent_for_node(&v.parent, v.id));
+	}
+
+	for v in locked_nodes {
+		let bundles = [(v.locked.id, v.node_seed)].into_iter().collect();
+		let fs = vault::FileSystem::from_locked_nodes(std::slice::from_ref(&v.locked), &bundles)
+			.unwrap();
+		let node = fs.node_by_id(v.locked.id).unwrap();
+
+		assert_eq!(node.created_by, *v.signer.public());
+		assert!(v.content.contains(&node.name));
+	}
+
+	for v in chunks {
+		assert_eq!(
+			v.chunk_key_iv.decrypt(&v.ct.0).unwrap(),
+			v.key_iv.chunk_decrypt(v.idx, &v.ct.0).unwrap()
+		);
+		assert_eq!(v.pt.0, v.key_iv.chunk_decrypt(v.idx, &v.ct.0).unwrap());
+	}
+
+	let digest_key = hmac::Key::new(merkle.digest_key.0.try_into().unwrap());
+	let mut verifier = merkle::Verifier::new(
+		digest_key,
+		&merkle.root,
+		merkle.leaves,
+		merkle.chunks.len() as u64,
+	)
+	.unwrap();
+
+	for (idx, chunk) in merkle.chunks.iter().enumerate() {
+		assert!(verifier.verify(idx as u64, &chunk.0));
+		assert!(chunk.0.len() <= merkle.chunk_size as usize);
+	}
+
+	for v in signatures {
+		assert!(v.signer.public().verify(&v.sig, &v.msg.0));
+	}
+
+	let user = crate::user::unlock_with_params(
+		share.receiver.private(),
+		share.receiver.public(),
+		&[share.share],
+		&[],
+		std::rc::Rc::new(Env::system()),
+	)
+	.unwrap();
+
+	assert_eq!(user.imports.len(), 1);
+	assert_eq!(user.imports[0].bundle, share.bundle);
+	assert_eq!(user.imports[0].sender, *share.sender.public());
+}
@@ -1,22 +1,58 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, rc::Rc};
+
+use zeroize::Zeroizing;
 
 use crate::{
 	database::{self},
+	env::Env,
+	error::Stage,
 	hkdf,
 	id::Uid,
 	identity::{self, Identity},
 	seeds::{self, ctx_to_sign, Bundle, Export, Import, LockedShare, Seed, Sorted, ROOT_ID},
-	vault::{FileSystem, LockedNode},
+	vault::{self, FileSystem, LockedNode},
+	version::Versioned,
+	wire,
 };
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Error {
-	BadJson,
-	NoAccess,
+	// id is LockedShare::id
+	Share { id: Uid, sender: Uid, stage: Stage },
+	// the tree under the roots doesn't match its listings, eg a server omitted a node
+	Vault(vault::Error),
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::Share { id, sender, stage } => {
+				write!(f, "share {} from {} failed at {}", id, sender, stage)
+			}
+			Error::Vault(e) => write!(f, "{}", e),
+		}
+	}
 }
 
+impl std::error::Error for Error {}
+
 pub const GOD_ID: u64 = 0;
 
+// everything that was present, but couldn't be opened or trusted, eg forged by a compromised
+// server; neither prevents unlocking
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IntegrityReport {
+	// vault::Error::Locked with the node's id and the failing stage
+	pub nodes: Vec<vault::Error>,
+	pub shares: Vec<Error>,
+}
+
+impl IntegrityReport {
+	pub fn is_clean(&self) -> bool {
+		self.nodes.is_empty() && self.shares.is_empty()
+	}
+}
+
 #[derive(PartialEq, Debug, Clone)]
 pub struct User {
 	pub identity: Identity,
@@ -25,15 +61,25 @@ pub struct User {
 	// things I share with others
 	pub exports: Vec<Export>,
 	pub fs: FileSystem,
+	// shares addressed to or sent by me which failed to open
+	pub rejected_shares: Vec<Error>,
 }
 
 impl User {
+	// includes nodes rejected when refetching subtrees since unlocking
+	pub fn integrity_report(&self) -> IntegrityReport {
+		IntegrityReport {
+			nodes: self.fs.rejected().to_vec(),
+			shares: self.rejected_shares.clone(),
+		}
+	}
+
 	pub fn is_god(&self) -> bool {
 		self.identity.id() == GOD_ID
 	}
 
 	// None means `all available`
-	fn seeds_for_ids(
+	pub(crate) fn seeds_for_ids(
 		&mut self,
 		fs_ids: Option<&[Uid]>,
 		db_ids: Option<&[database::Index]>,
@@ -145,16 +191,17 @@ impl User {
 
 	fn derive_seed_with_label(identity: &identity::Private, label: &[u8]) -> Seed {
 		// hash identity's private keys to "root"
-		let root = hkdf::Hkdf::from_ikm(
-			&[
+		let ikm = Zeroizing::new(
+			[
 				identity.x448.as_bytes(),
 				identity.ed25519.as_bytes().as_slice(),
 			]
 			.concat(),
-		)
-		.expand::<{ seeds::SEED_SIZE }>(b"root");
+		);
+		let root =
+			Zeroizing::new(hkdf::Hkdf::from_ikm(&ikm).expand::<{ seeds::SEED_SIZE }>(b"root"));
 		// and then the resulted hash to label
-		let bytes = hkdf::Hkdf::from_ikm(&root).expand::<{ seeds::SEED_SIZE }>(label);
+		let bytes = hkdf::Hkdf::from_ikm(&*root).expand::<{ seeds::SEED_SIZE }>(label);
 
 		Seed { bytes }
 	}
@@ -173,66 +220,60 @@ pub fn unlock_with_params(
 	_pub: &identity::Public,
 	shares: &[LockedShare],
 	roots: &[LockedNode],
+	env: Rc<Env>,
 ) -> Result<User, Error> {
 	// for god, there should be one LockedNode (or more, if root's children) and no imports, so
 	// use use.fs_seed instead for admins, there could be several LockedNodes (subroots +
 	// children depending on depth) and LockedShares needed to decrypt the nodes
 
 	// failing always, even if there's just one forged share is not an option, since it's a potential
-	// ddos initiated by a compromised server basically, hence, I simply ignore any fake shares
-	// TODO: alternatively, a log could be introduced to collect any forged shares for manual inspection
-
-	// filter locked shares for export and import
-
-	let imports = shares
-		.iter()
-		.filter_map(|s| {
-			if s.export.receiver == _pub.id() {
-				if let Ok(ref bytes) = _priv.decrypt(&s.payload) {
-					if let Ok(bundle) = serde_json::from_slice::<Bundle>(bytes) {
-						let to_sign = ctx_to_sign(&s.sender, &s.export);
-						// make sure exports haven't been forged: verify sig + quantity
-						if s.sender.verify(&s.sig, &to_sign)
-							&& bundle.fs.keys().cloned().collect::<Vec<_>>().sorted()
-								== s.export.fs.sorted()
-							&& bundle.db.keys().cloned().collect::<Vec<_>>().sorted()
-								== s.export.db.sorted()
-						{
-							Some(Import {
-								sender: s.sender.clone(),
-								bundle,
-							})
-						} else {
-							None
-						}
+	// ddos initiated by a compromised server basically, hence, fake shares are skipped, but kept in
+	// rejected_shares for manual inspection
+	let mut rejected_shares = Vec::new();
+	let mut imports = Vec::new();
+	let mut exports = Vec::new();
+
+	for s in shares {
+		let reject = |stage| Error::Share {
+			id: s.id(),
+			sender: s.sender.id(),
+			stage,
+		};
+		let to_sign = ctx_to_sign(&s.sender, &s.export, s.version);
+
+		if s.export.receiver == _pub.id() {
+			match open_share(_priv, s) {
+				Ok(bundle) => {
+					// make sure exports haven't been forged: verify sig + quantity
+					if s.sender.verify(&s.sig, &to_sign)
+						&& bundle.fs.keys().cloned().collect::<Vec<_>>().sorted()
+							== s.export.fs.sorted()
+						&& bundle.db.keys().cloned().collect::<Vec<_>>().sorted()
+							== s.export.db.sorted()
+					{
+						imports.push(Import {
+							sender: s.sender.clone(),
+							bundle,
+						});
 					} else {
-						None
+						rejected_shares.push(reject(Stage::Verify));
 					}
-				} else {
-					None
 				}
-			} else {
-				None
+				Err(stage) => rejected_shares.push(reject(stage)),
 			}
-		})
-		.collect::<Vec<_>>();
-	let exports = shares
-		.iter()
-		.filter_map(|s| {
-			// I can't decrypt payloads here, since each is encrypted to a recipient's public key
-			if s.sender.id() == _pub.id() {
-				let to_sign = ctx_to_sign(&s.sender, &s.export);
+		}
 
-				if s.sender.verify(&s.sig, &to_sign) {
-					Some(s.export.clone())
-				} else {
-					None
-				}
+		if s.sender.id() == _pub.id() {
+			// I can't decrypt payloads here, since each is encrypted to a recipient's public key
+			if s.check_version().is_err() {
+				rejected_shares.push(reject(Stage::Version));
+			} else if s.sender.verify(&s.sig, &to_sign) {
+				exports.push(s.export.clone());
 			} else {
-				None
+				rejected_shares.push(reject(Stage::Verify));
 			}
-		})
-		.collect();
+		}
+	}
 
 	let bundles = if _pub.is_god() {
 		[(Uid::new(ROOT_ID), User::fs_seed(&_priv))]
@@ -243,7 +284,7 @@ pub fn unlock_with_params(
 	};
 
 	// this is what is required for a Mode user to rebuild
-	let fs = FileSystem::from_locked_nodes(&roots, &bundles);
+	let fs = FileSystem::from_locked_nodes_with_env(&roots, &bundles, env).map_err(Error::Vault)?;
 
 	Ok(User {
 		identity: Identity {
@@ -253,5 +294,77 @@ pub fn unlock_with_params(
 		imports,
 		exports,
 		fs,
+		rejected_shares,
 	})
 }
+
+fn open_share(_priv: &identity::Private, s: &LockedShare) -> Result<Bundle, Stage> {
+	s.check_version().map_err(|_| Stage::Version)?;
+
+	let bytes = _priv.decrypt(&s.payload).map_err(|e| match e {
+		identity::Error::UnsupportedVersion => Stage::Version,
+		identity::Error::BadKey => Stage::Decrypt,
+	})?;
+
+	wire::decode(&bytes).map_err(|_| Stage::Decode)
+}
+
+#[cfg(test)]
+mod tests {
+	use std::rc::Rc;
+
+	use super::{unlock_with_params, Error};
+	use crate::{
+		env::Env,
+		error::Stage,
+		id::Uid,
+		identity::Identity,
+		seeds::{Bundle, LockedShare, Seed},
+		version::Versioned,
+	};
+
+	#[test]
+	fn test_rejected_shares() {
+		let god = Identity::generate(Uid::new(0));
+		let admin = Identity::generate(Uid::new(1));
+		let mut bundle = Bundle::new();
+		bundle.set_fs(Uid::new(7), Seed::generate());
+
+		let share = LockedShare::new(&god, admin.public(), &bundle);
+		let mut forged = LockedShare::new(&god, admin.public(), &bundle);
+		forged.export.fs.push(Uid::new(8));
+		let mut newer = LockedShare::new(&god, admin.public(), &bundle);
+		newer.version = LockedShare::VERSION + 1;
+		let (forged_id, newer_id) = (forged.id(), newer.id());
+
+		let user = unlock_with_params(
+			admin.private(),
+			admin.public(),
+			&[share, forged, newer],
+			&[],
+			Rc::new(Env::system()),
+		)
+		.unwrap();
+
+		let report = user.integrity_report();
+
+		assert_eq!(user.imports.len(), 1);
+		assert!(!report.is_clean());
+		assert!(report.nodes.is_empty());
+		assert_eq!(
+			report.shares,
+			vec![
+				Error::Share {
+					id: forged_id,
+					sender: god.id(),
+					stage: Stage::Verify
+				},
+				Error::Share {
+					id: newer_id,
+					sender: god.id(),
+					stage: Stage::Version
+				}
+			]
+		);
+	}
+}
@@ -1,54 +1,246 @@
-use std::collections::HashMap;
+use std::{
+	collections::{HashMap, HashSet},
+	fmt,
+	rc::Rc,
+};
 
 use crate::{
 	aes_gcm::{self, Aes},
 	ed25519,
 	encrypted::Encrypted,
+	env::Env,
+	error::{Cause, Stage},
 	hkdf::Hkdf,
 	hmac,
 	id::Uid,
 	identity,
+	revision::Revisions,
 	salt::Salt,
+	search::Index,
 	seeds::{self, Seed, Seeds, ROOT_ID},
+	sync::Change,
+	version::{self, Versioned},
+	wire::Format,
 };
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Error {
-	NotFound,
-	// TODO: add a description
-	BadOperation,
-	// TODO: add id
-	NoAccess,
-	ForgedSig,
+	NotFound(Uid),
+	// eg a dir operation on a file
+	BadOperation { id: Uid, reason: &'static str },
+	// no seed to open or lock the node with
+	NoAccess(Uid),
+	// a locked node which couldn't be opened; caused by the decryption, decoding or signature error
+	Locked { id: Uid, stage: Stage, cause: Cause },
+	// a dir's children don't match its signed listing: some of them are missing or stale
+	Unlisted(Uid),
+	// validly signed, but older than a revision seen before
+	RolledBack { id: Uid, revision: u64, seen: u64 },
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Error::NotFound(id) => write!(f, "node {} not found", id),
+			Error::BadOperation { id, reason } => write!(f, "node {}: {}", id, reason),
+			Error::NoAccess(id) => write!(f, "no access to node {}", id),
+			Error::Locked { id, stage, .. } => write!(f, "node {} failed at {}", id, stage),
+			Error::Unlisted(id) => write!(f, "children of node {} don't match its listing", id),
+			Error::RolledBack { id, revision, seen } => write!(
+				f,
+				"node {} rolled back to revision {}, while {} was seen",
+				id, revision, seen
+			),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::Locked { cause, .. } => cause.source(),
+			_ => None,
+		}
+	}
+}
+
+impl Error {
+	pub fn id(&self) -> Uid {
+		match self {
+			Error::NotFound(id)
+			| Error::BadOperation { id, .. }
+			| Error::NoAccess(id)
+			| Error::Locked { id, .. }
+			| Error::Unlisted(id)
+			| Error::RolledBack { id, .. } => *id,
+		}
+	}
 }
 
+// files are encrypted and uploaded in chunks of this size; the last one may be shorter
+pub const CHUNK_SIZE: u32 = 1024 * 1024;
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub struct FileInfo {
-	pub size: u32,
+	// u32 for legacy nodes, which still decodes fine
+	pub size: u64,
 	pub key_iv: Aes,
 	pub ext: String,
+	// None for legacy nodes which were chunked by their clients
+	#[serde(default)]
+	pub chunk_size: Option<u32>,
+	// merkle root over the plaintext chunks; None for legacy nodes
+	#[serde(default)]
+	pub digest: Option<hmac::Digest>,
+}
+
+impl FileInfo {
+	pub fn chunk_size(&self) -> u32 {
+		self.chunk_size.unwrap_or(CHUNK_SIZE)
+	}
+
+	pub fn chunk_count(&self) -> u64 {
+		// an empty file still has one (empty) chunk
+		self.size.div_ceil(self.chunk_size() as u64).max(1)
+	}
+
+	// keys leaves of the content's merkle tree
+	pub fn digest_key(&self) -> hmac::Key {
+		hmac::Key::new(
+			Hkdf::from_ikm(self.key_iv.as_bytes().as_slice())
+				.expand::<{ hmac::Key::SIZE }>(b"digest"),
+		)
+	}
 }
 
 #[derive(Serialize, Deserialize)]
 pub enum LockedEntry {
-	File { info: FileInfo },
+	File {
+		info: FileInfo,
+	},
 	// seed does not improve encryption strength directly, but
 	// rather increases subsequent entropy
-	Dir { seed: Seed },
+	Dir {
+		seed: Seed,
+		// None for dirs predating listings, which can't be verified
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		listing: Option<Listing>,
+	},
+}
+
+// what a dir commits to about its children, so that a server can't omit or replay any of them
+// unnoticed; children which aren't listed (yet) are left out, see FileSystem::list
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Listing {
+	// each child's id and Node::hash, sorted by id
+	pub children: Vec<(Uid, hmac::Digest)>,
+	// each child's signed revision when listed, in the same order: a subdir's listing and a file's
+	// revision aren't hashed, so a replayed older one is told apart by it; empty for legacy listings
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub revisions: Vec<u64>,
+}
+
+impl Listing {
+	pub fn of<'a>(children: impl Iterator<Item = &'a Node>) -> Self {
+		let mut children: Vec<&Node> = children.collect();
+		children.sort_by_key(|n| n.id);
+
+		Self {
+			children: children.iter().map(|n| (n.id, n.hash())).collect(),
+			revisions: children.iter().map(|n| n.revision).collect(),
+		}
+	}
+
+	// ids of the children which aren't listed; fails, if a listed one is stale, older than listed
+	// or missing, unless it's excused, eg present, but rejected (and reported) already
+	fn check<'a>(
+		&self,
+		dir_id: Uid,
+		children: impl Iterator<Item = &'a Node>,
+		excused: impl Fn(Uid) -> bool,
+	) -> Result<Vec<Uid>, Error> {
+		let mut present = HashSet::new();
+		let mut unlisted = Vec::new();
+
+		for child in children {
+			match self.children.binary_search_by_key(&child.id, |(id, _)| *id) {
+				Ok(idx) if self.children[idx].1 == child.hash() => {
+					// it may have been edited since, but not listed again
+					match self.revisions.get(idx) {
+						Some(&listed) if child.revision < listed => {
+							return Err(Error::RolledBack {
+								id: child.id,
+								revision: child.revision,
+								seen: listed,
+							})
+						}
+						_ => _ = present.insert(child.id),
+					}
+				}
+				Ok(_) => return Err(Error::Unlisted(dir_id)),
+				Err(_) => unlisted.push(child.id),
+			}
+		}
+
+		if self
+			.children
+			.iter()
+			.all(|(id, _)| present.contains(id) || excused(*id))
+		{
+			Ok(unlisted)
+		} else {
+			Err(Error::Unlisted(dir_id))
+		}
+	}
 }
 
 impl LockedEntry {
 	pub fn hash(&self) -> hmac::Digest {
 		use LockedEntry::*;
 
-		let bytes = match self {
-			File { info } => [&info.key_iv.as_bytes(), info.ext.as_bytes()].concat(),
-			Dir { seed } => seed.bytes.to_vec(),
-		};
-
-		let sha = Sha256::digest(&bytes);
+		let bytes = Zeroizing::new(match self {
+			// legacy nodes have no digest and are hashed the old way
+			File { info } => match info.digest {
+				Some(ref digest) => [
+					info.key_iv.as_bytes().as_slice(),
+					info.ext.as_bytes(),
+					&info.size.to_be_bytes(),
+					&info.chunk_size().to_be_bytes(),
+					digest.as_bytes(),
+				]
+				.concat(),
+				None => [info.key_iv.as_bytes().as_slice(), info.ext.as_bytes()].concat(),
+			},
+			Dir {
+				seed,
+				listing: None,
+			} => seed.bytes.to_vec(),
+			Dir {
+				seed,
+				listing: Some(listing),
+			} => [
+				seed.bytes.as_slice(),
+				// each part is prefixed with its length, so that it can't spill over to the next
+				&(listing.children.len() as u64).to_be_bytes(),
+				&listing
+					.children
+					.iter()
+					.flat_map(|(id, hash)| [id.as_bytes().as_slice(), hash.as_bytes()].concat())
+					.collect::<Vec<_>>(),
+				&(listing.revisions.len() as u64).to_be_bytes(),
+				&listing
+					.revisions
+					.iter()
+					.flat_map(|revision| revision.to_be_bytes())
+					.collect::<Vec<_>>(),
+			]
+			.concat(),
+		});
+
+		let sha = Sha256::digest(&*bytes);
 
 		hmac::Digest(sha.into())
 	}
@@ -56,6 +248,9 @@ impl LockedEntry {
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct LockedNode {
+	// see Versioned; 0 for nodes predating versioning
+	#[serde(default)]
+	pub version: u32,
 	pub id: Uid,
 	pub parent_id: Uid,
 	pub content: Encrypted,
@@ -64,6 +259,8 @@ pub struct LockedNode {
 
 #[derive(Serialize, Deserialize)]
 struct LockedContent {
+	#[serde(default)]
+	version: u32,
 	created_at: u64,
 	name: String,
 	created_by: identity::Public,
@@ -72,6 +269,14 @@ struct LockedContent {
 	// edit: { editerd_by, edited_at, sig }
 	sig: ed25519::Signature,
 	entry: LockedEntry,
+	// grows with each edit; see Revisions. 0 for nodes predating revisions, which are signed
+	// without it
+	#[serde(default, skip_serializing_if = "is_zero")]
+	revision: u64,
+}
+
+fn is_zero(revision: &u64) -> bool {
+	*revision == 0
 }
 
 pub struct NewNodeReq {
@@ -79,38 +284,172 @@ pub struct NewNodeReq {
 	pub locked_node: LockedNode,
 }
 
+impl Versioned for LockedNode {
+	const VERSION: u32 = 1;
+
+	fn version(&self) -> u32 {
+		self.version
+	}
+}
+
+impl LockedNode {
+	// identifies a stored state of a node; a backend can compute it as well, so edits refer to the
+	// state they are based on to detect concurrent ones (see Network::save_node)
+	pub fn hash(&self) -> hmac::Digest {
+		let bytes = [
+			self.id.as_bytes().as_slice(),
+			self.parent_id.as_bytes().as_slice(),
+			self.content.salt.bytes.as_slice(),
+			&self.content.ct,
+		]
+		.concat();
+
+		hmac::Digest(Sha256::digest(&bytes).into())
+	}
+}
+
+// how to settle an edit that conflicts with someone else's; see FileSystem::resolve
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+	Mine,
+	Theirs,
+	// theirs stays, mine is saved as a copy next to it; files only
+	Both,
+}
+
+// what has changed in a tree, so that views can be updated without polling; see take_events
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+	Added { id: Uid, parent_id: Uid },
+	Removed { id: Uid, parent_id: Uid },
+	Moved { id: Uid, from: Uid, to: Uid },
+	Renamed { id: Uid, name: String },
+	// a new revision otherwise, eg a conflict resolved
+	Updated(Uid),
+	// to be refetched, see mark_dirty
+	Dirty(Uid),
+	// a detached root, ie something shared with us, has appeared or gone away
+	ShareImported(Uid),
+	ShareRevoked(Uid),
+}
+
+impl Versioned for LockedContent {
+	const VERSION: u32 = 1;
+
+	fn version(&self) -> u32 {
+		self.version
+	}
+}
+
 impl LockedContent {
+	#[allow(clippy::too_many_arguments)]
+	fn new(
+		created_at: u64,
+		name: &str,
+		created_by: &identity::Public,
+		entry: LockedEntry,
+		revision: u64,
+		id: Uid,
+		parent_id: Uid,
+		sign_by: &identity::Private,
+	) -> Self {
+		let to_sign = Self::ctx_to_sign(
+			Self::VERSION,
+			created_at,
+			name,
+			created_by,
+			&entry,
+			revision,
+			id,
+			parent_id,
+		);
+
+		Self {
+			version: Self::VERSION,
+			created_at,
+			name: name.to_string(),
+			created_by: created_by.clone(),
+			sig: sign_by.sign(&to_sign),
+			entry,
+			revision,
+		}
+	}
+
+	fn lock(&self, id: Uid, parent_id: Uid, seed: &Seed, salt: Salt, format: Format) -> LockedNode {
+		let aes = aes_from_node_seed(seed, &salt);
+		let ct = aes.encrypt_serializable_as(self, format);
+
+		LockedNode {
+			version: LockedNode::VERSION,
+			id,
+			parent_id,
+			content: Encrypted { ct, salt },
+			dirty: false,
+		}
+	}
+
 	fn try_from_encrypted(ct: &[u8], aes: Aes, id: Uid, parent_id: Uid) -> Result<Self, Error> {
-		let pt = aes.decrypt(ct).map_err(|_| Error::BadOperation)?;
-		let content: LockedContent =
-			serde_json::from_slice(&pt).map_err(|_| Error::BadOperation)?;
-
-		if content.created_by.verify(
-			&content.sig,
-			&Self::ctx_to_sign(
-				content.created_at,
-				&content.name,
-				&content.created_by,
-				&content.entry,
-				id,
-				parent_id,
-			),
-		) {
-			Ok(content)
-		} else {
-			Err(Error::ForgedSig)
+		let locked = |stage, cause| Error::Locked { id, stage, cause };
+		let pt = aes
+			.decrypt(ct)
+			.map_err(|e| locked(Stage::Decrypt, Cause::new(e)))?;
+		let content: LockedContent = version::decode(&pt).map_err(|e| match e {
+			version::Error::Unsupported(_) => locked(Stage::Version, Cause::new(e)),
+			version::Error::BadEncoding(_) => locked(Stage::Decode, Cause::new(e)),
+		})?;
+
+		match &content.entry {
+			// no file could ever be chunked by it, see FileInfo::chunk_count
+			LockedEntry::File {
+				info: FileInfo {
+					chunk_size: Some(0),
+					..
+				},
+			} => return Err(locked(Stage::Decode, Cause::default())),
+			// a revision per child, unless it's a legacy listing
+			LockedEntry::Dir {
+				listing: Some(listing),
+				..
+			} if !listing.revisions.is_empty()
+				&& listing.revisions.len() != listing.children.len() =>
+			{
+				return Err(locked(Stage::Decode, Cause::default()))
+			}
+			_ => {}
 		}
+
+		content
+			.created_by
+			.try_verify(
+				&content.sig,
+				&Self::ctx_to_sign(
+					content.version,
+					content.created_at,
+					&content.name,
+					&content.created_by,
+					&content.entry,
+					content.revision,
+					id,
+					parent_id,
+				),
+			)
+			.map_err(|e| locked(Stage::Verify, Cause::new(e)))?;
+
+		Ok(content)
 	}
 
+	#[allow(clippy::too_many_arguments)]
 	fn ctx_to_sign(
+		version: u32,
 		created_at: u64,
 		name: &str,
 		created_by: &identity::Public,
 		entry: &LockedEntry,
+		revision: u64,
 		id: Uid,
 		parent_id: Uid,
 	) -> Vec<u8> {
-		[
+		let mut ctx = [
 			created_at.to_be_bytes().as_slice(),
 			name.as_bytes(),
 			created_by.hash().as_bytes(),
@@ -118,7 +457,19 @@ impl LockedContent {
 			id.as_bytes().as_slice(),
 			parent_id.as_bytes().as_slice(),
 		]
-		.concat()
+		.concat();
+
+		// keeps signatures of nodes predating versioning valid
+		if version > 0 {
+			ctx.extend_from_slice(&version.to_be_bytes());
+		}
+
+		// keeps signatures of nodes predating revisions valid
+		if revision > 0 {
+			ctx.extend_from_slice(&revision.to_be_bytes());
+		}
+
+		ctx
 	}
 }
 
@@ -130,8 +481,10 @@ pub struct Node {
 	pub name: String,
 	pub entry: Entry,
 	pub dirty: bool,
+	// whoever signed the current revision, which is not necessarily the first one
 	pub created_by: identity::Public,
-	// last_edited_by
+	// bump before re-locking an edited node; see Revisions
+	pub revision: u64,
 }
 
 #[derive(Clone, Debug)]
@@ -164,10 +517,200 @@ impl PartialEq for Entry {
 }
 
 // Use to share access to a particular file/dir and paste to aes_from_node_seed_and_salt
-fn seed_from_parent_for_node(parent: &Seed, id: Uid) -> Seed {
+pub(crate) fn seed_from_parent_for_node(parent: &Seed, id: Uid) -> Seed {
+	let ikm = Zeroizing::new([parent.bytes.as_slice(), &id.as_bytes()].concat());
+
 	Seed {
-		bytes: Hkdf::from_ikm(&[parent.bytes.as_slice(), &id.as_bytes()].concat())
-			.expand_no_info::<{ seeds::SEED_SIZE }>(),
+		bytes: Hkdf::from_ikm(&ikm).expand_no_info::<{ seeds::SEED_SIZE }>(),
+	}
+}
+
+// id and all of its descendants among locked nodes, if any; useful to whoever stores nodes as is,
+// eg a backend or an offline cache
+pub(crate) fn locked_subtree(nodes: &HashMap<Uid, LockedNode>, id: Uid) -> Vec<LockedNode> {
+	let mut children: HashMap<Uid, Vec<&LockedNode>> = HashMap::new();
+
+	for node in nodes.values() {
+		children.entry(node.parent_id).or_default().push(node);
+	}
+
+	let mut subtree: Vec<LockedNode> = nodes.get(&id).cloned().into_iter().collect();
+	let mut to_visit = vec![id];
+
+	while let Some(id) = to_visit.pop() {
+		for &child in children.get(&id).into_iter().flatten() {
+			to_visit.push(child.id);
+			subtree.push(child.clone());
+		}
+	}
+
+	subtree
+}
+
+// decrypts and verifies a locked node; a dir comes with its listing, if any
+fn open_locked(
+	locked_node: &LockedNode,
+	aes: Aes,
+	revisions: &mut Revisions,
+) -> Result<(Node, Option<Listing>), Error> {
+	let content = LockedContent::try_from_encrypted(
+		&locked_node.content.ct,
+		aes,
+		locked_node.id,
+		locked_node.parent_id,
+	)?;
+
+	let (entry, listing) = match content.entry {
+		LockedEntry::File { info } => (Entry::File { info }, None),
+		LockedEntry::Dir { seed, listing } => (
+			Entry::Dir {
+				seed,
+				children: vec![],
+			},
+			listing,
+		),
+	};
+	let node = Node {
+		id: locked_node.id,
+		parent_id: locked_node.parent_id,
+		created_at: content.created_at,
+		name: content.name,
+		entry,
+		dirty: locked_node.dirty,
+		created_by: content.created_by,
+		revision: content.revision,
+	};
+
+	// only once it's fully opened, so that whatever's rejected doesn't count as seen
+	revisions
+		.observe(node.id, node.revision)
+		.map_err(|seen| Error::RolledBack {
+			id: node.id,
+			revision: node.revision,
+			seen,
+		})?;
+
+	Ok((node, listing))
+}
+
+// leaves out the children of dirs which can't be verified: dirty ones, whose children are to be
+// fetched still, and unlisted children, which are reported; a dir with a listed child which is
+// stale or missing (rather than rejected) has its children left out as well and is returned along
+// with the error, legacy dirs with no listing are taken as is
+fn verify_listings(
+	listings: &HashMap<Uid, Listing>,
+	nodes: &HashMap<Uid, Node>,
+	branches: &mut HashMap<Uid, Vec<Uid>>,
+	rejected: &mut Vec<Error>,
+) -> Vec<(Uid, Error)> {
+	for (dir_id, node) in nodes {
+		if node.dirty {
+			branches.remove(dir_id);
+		}
+	}
+
+	let excused: HashSet<Uid> = rejected.iter().map(Error::id).collect();
+	let mut failed = Vec::new();
+
+	for (dir_id, listing) in listings {
+		if nodes.get(dir_id).is_some_and(|n| n.dirty) {
+			continue;
+		}
+
+		let children = branches
+			.get(dir_id)
+			.into_iter()
+			.flatten()
+			.filter_map(|id| nodes.get(id));
+
+		match listing.check(*dir_id, children, |id| excused.contains(&id)) {
+			Ok(unlisted) => {
+				if let Some(child_ids) = branches.get_mut(dir_id) {
+					child_ids.retain(|id| !unlisted.contains(id));
+				}
+
+				rejected.extend(unlisted.into_iter().map(|id| Error::Locked {
+					id,
+					stage: Stage::Listing,
+					cause: Cause::default(),
+				}));
+			}
+			Err(e) => {
+				branches.remove(dir_id);
+				failed.push((*dir_id, e));
+			}
+		}
+	}
+
+	failed
+}
+
+// hashes of the locked nodes which have been opened
+fn bases_of(locked_nodes: &[LockedNode], nodes: &HashMap<Uid, Node>) -> HashMap<Uid, hmac::Digest> {
+	locked_nodes
+		.iter()
+		.filter(|ln| nodes.contains_key(&ln.id))
+		.map(|ln| (ln.id, ln.hash()))
+		.collect()
+}
+
+// what turned prev into cur, if anything; nodes with no prev are new
+fn push_events(prev: Option<&Node>, cur: &Node, events: &mut Vec<Event>) {
+	let id = cur.id;
+
+	match prev {
+		None => events.push(Event::Added {
+			id,
+			parent_id: cur.parent_id,
+		}),
+		Some(prev) if prev.parent_id != cur.parent_id => events.push(Event::Moved {
+			id,
+			from: prev.parent_id,
+			to: cur.parent_id,
+		}),
+		Some(prev) if prev.name != cur.name => events.push(Event::Renamed {
+			id,
+			name: cur.name.clone(),
+		}),
+		Some(prev) if prev.revision != cur.revision => events.push(Event::Updated(id)),
+		Some(_) => {}
+	}
+
+	if cur.dirty && !prev.is_some_and(|p| p.dirty) {
+		events.push(Event::Dirty(id));
+	}
+}
+
+// nodes along with their subtrees
+fn flatten<'a>(nodes: &'a [Node], out: &mut Vec<&'a Node>) {
+	for node in nodes {
+		out.push(node);
+
+		if let Entry::Dir { children, .. } = &node.entry {
+			flatten(children, out);
+		}
+	}
+}
+
+// compares two versions of the same children, subtrees included
+fn diff_subtrees(prev: &[Node], cur: &[Node], events: &mut Vec<Event>) {
+	let (mut before, mut after) = (Vec::new(), Vec::new());
+
+	flatten(prev, &mut before);
+	flatten(cur, &mut after);
+
+	let before_by_id: HashMap<Uid, &Node> = before.iter().map(|n| (n.id, *n)).collect();
+	let after_ids: HashSet<Uid> = after.iter().map(|n| n.id).collect();
+
+	for node in after {
+		push_events(before_by_id.get(&node.id).copied(), node, events);
+	}
+
+	for node in before.into_iter().filter(|n| !after_ids.contains(&n.id)) {
+		events.push(Event::Removed {
+			id: node.id,
+			parent_id: node.parent_id,
+		});
 	}
 }
 
@@ -178,86 +721,179 @@ fn aes_from_parent_seed_for_node(seed: &Seed, id: Uid, salt: &Salt) -> Aes {
 }
 
 // use this to encrypt/decrypt nodes
-fn aes_from_node_seed(seed: &Seed, salt: &Salt) -> Aes {
-	let key_iv = Hkdf::from_ikm(&[seed.bytes.as_slice(), &salt.bytes].concat())
-		.expand_no_info::<{ aes_gcm::Key::SIZE + aes_gcm::Iv::SIZE }>();
+pub(crate) fn aes_from_node_seed(seed: &Seed, salt: &Salt) -> Aes {
+	let ikm = Zeroizing::new([seed.bytes.as_slice(), &salt.bytes].concat());
+	let key_iv = Zeroizing::new(
+		Hkdf::from_ikm(&ikm).expand_no_info::<{ aes_gcm::Key::SIZE + aes_gcm::Iv::SIZE }>(),
+	);
 
-	aes_gcm::Aes::from(&key_iv)
+	aes_gcm::Aes::from(&*key_iv)
 }
 
 impl Node {
 	fn encrypt_with_parent_seed(
 		node: &Node,
+		entry: LockedEntry,
 		parent: &Seed,
 		sign_by: &identity::Private,
+		env: &Env,
+		format: Format,
 	) -> LockedNode {
 		let seed = seed_from_parent_for_node(parent, node.id);
 
-		Self::encrypt(node, &seed, sign_by)
+		Self::encrypt(node, entry, &seed, sign_by, env, format)
 	}
 
-	fn encrypt(node: &Node, node_seed: &Seed, sign_by: &identity::Private) -> LockedNode {
-		let entry = match &node.entry {
-			Entry::File { info } => LockedEntry::File { info: info.clone() },
-			Entry::Dir { seed, .. } => LockedEntry::Dir { seed: seed.clone() },
-		};
-		let to_sign = LockedContent::ctx_to_sign(
+	fn encrypt(
+		node: &Node,
+		entry: LockedEntry,
+		node_seed: &Seed,
+		sign_by: &identity::Private,
+		env: &Env,
+		format: Format,
+	) -> LockedNode {
+		let salt = Salt::generate_with(&mut **env.rng());
+
+		Self::encrypt_with_salt(node, entry, node_seed, sign_by, salt, format)
+	}
+
+	// entry is what node.entry is locked as, see locked_entry
+	pub(crate) fn encrypt_with_salt(
+		node: &Node,
+		entry: LockedEntry,
+		node_seed: &Seed,
+		sign_by: &identity::Private,
+		salt: Salt,
+		format: Format,
+	) -> LockedNode {
+		LockedContent::new(
 			node.created_at,
 			&node.name,
 			&node.created_by,
-			&entry,
+			entry,
+			node.revision,
 			node.id,
 			node.parent_id,
-		);
-		let sig = sign_by.sign(&to_sign);
-		let locked_content = LockedContent {
-			created_at: node.created_at,
-			name: node.name.clone(),
-			entry,
-			created_by: node.created_by.clone(),
-			sig,
-		};
-		let salt = Salt::generate();
-		let aes = aes_from_node_seed(node_seed, &salt);
-		let ct = aes.encrypt_serializable(&locked_content);
-		let encrypted = Encrypted { ct, salt };
-		// something is failing to serialize here
+			sign_by,
+		)
+		.lock(node.id, node.parent_id, node_seed, salt, format)
+	}
 
-		LockedNode {
-			id: node.id,
-			parent_id: node.parent_id,
-			content: encrypted,
-			dirty: false,
+	// a dir lists its current children, so it has to be loaded, see FileSystem::lock
+	pub(crate) fn locked_entry(&self) -> LockedEntry {
+		match &self.entry {
+			Entry::File { info } => LockedEntry::File { info: info.clone() },
+			Entry::Dir { seed, children } => LockedEntry::Dir {
+				seed: seed.clone(),
+				listing: Some(Listing::of(children.iter())),
+			},
 		}
 	}
+
+	// what its parent's listing commits to: its signed content, except for whatever changes when a
+	// dir is listed itself (its listing, revision and signer), so that listing a dir doesn't ripple
+	// up to the root; the revision is listed apart instead, as a lower bound, see Listing::check
+	fn hash(&self) -> hmac::Digest {
+		let entry = match &self.entry {
+			Entry::File { info } => LockedEntry::File { info: info.clone() },
+			Entry::Dir { seed, .. } => LockedEntry::Dir {
+				seed: seed.clone(),
+				listing: None,
+			},
+		};
+		let bytes = [
+			self.created_at.to_be_bytes().as_slice(),
+			self.name.as_bytes(),
+			entry.hash().as_bytes(),
+			self.parent_id.as_bytes().as_slice(),
+		]
+		.concat();
+
+		hmac::Digest(Sha256::digest(&bytes).into())
+	}
 }
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct FileSystem {
 	// a user can have multiple top-level shares belonging to different
 	// subtrees, therefore more than one root is possible
 	roots: Vec<Node>,
 	// a cache of shares
 	cached_seeds: Seeds,
+	// randomness and time for new nodes
+	env: Rc<Env>,
+	// encoding of the content of new nodes; nodes of either format decode fine
+	format: Format,
+	// locked nodes which failed to open when (re)loaded, eg forged or written by newer clients
+	rejected: Vec<Error>,
+	// the highest revision seen per node
+	revisions: Revisions,
+	// hashes of the locked nodes the tree is based on, so that edits can refer to them
+	bases: HashMap<Uid, hmac::Digest>,
+	// what each dir's content lists as last opened or listed, so that refetched children can be
+	// verified against it
+	listings: HashMap<Uid, Listing>,
+	// changes of the tree since take_events was last called
+	events: Vec<Event>,
+	// names and paths of whatever's attached, see search
+	index: Index,
+}
+
+// the subtrees of a few dirs and whatever's kept about their nodes, so that an edit of them which
+// fails half way can be undone without cloning the whole tree; see FileSystem::snapshot
+pub struct Snapshot {
+	dirs: Vec<Node>,
+	revisions: Vec<(Uid, u64)>,
+	bases: Vec<(Uid, hmac::Digest)>,
+	listings: Vec<(Uid, Listing)>,
+	rejected: Vec<Error>,
+	// how many events were pending
+	events: usize,
+}
+
+impl PartialEq for FileSystem {
+	fn eq(&self, other: &Self) -> bool {
+		self.roots == other.roots && self.cached_seeds == other.cached_seeds
+	}
 }
 
 pub const NO_PARENT_ID: u64 = u64::MAX;
 
-fn now() -> u64 {
-	use std::time::{SystemTime, UNIX_EPOCH};
-	let duration = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+// borrows roots only, so that other fields of FileSystem can be borrowed alongside
+fn node_by_id_mut(roots: &mut [Node], id: Uid) -> Option<&mut Node> {
+	let mut stack: Vec<&mut Node> = roots.iter_mut().collect();
+
+	while let Some(node) = stack.pop() {
+		if node.id == id {
+			return Some(node);
+		}
+
+		if let Entry::Dir { children, .. } = &mut node.entry {
+			for child in children {
+				stack.push(child);
+			}
+		}
+	}
 
-	duration.as_secs() * 1000 + duration.subsec_millis() as u64
+	None
 }
 
 impl FileSystem {
 	// returns FileSystem { root_node } & its json
 	pub fn new(fs_seed: &Seed, owner: &identity::Identity) -> (Self, LockedNode) {
+		Self::new_with_env(fs_seed, owner, Rc::new(Env::system()))
+	}
+
+	pub fn new_with_env(
+		fs_seed: &Seed,
+		owner: &identity::Identity,
+		env: Rc<Env>,
+	) -> (Self, LockedNode) {
 		let id = Uid::new(ROOT_ID);
 		let parent_id = Uid::new(NO_PARENT_ID);
-		let created_at = now();
+		let created_at = env.now();
 		let name = "/".to_string();
-		let seed = Seed::generate();
+		let seed = Seed::generate_with(&mut **env.rng());
 		let node = Node {
 			id,
 			parent_id,
@@ -269,176 +905,755 @@ impl FileSystem {
 			},
 			created_by: owner.public().clone(),
 			dirty: false,
+			revision: 1,
 		};
-		let locked_root = Node::encrypt(&node, fs_seed, owner.private());
+		let format = Format::default();
+		let locked_root = Node::encrypt(
+			&node,
+			node.locked_entry(),
+			fs_seed,
+			owner.private(),
+			&env,
+			format,
+		);
 		let cached_seeds = vec![(id, fs_seed.clone())].into_iter().collect();
+		let mut revisions = Revisions::new();
+		_ = revisions.observe(id, node.revision);
+
+		let index = Index::of(std::slice::from_ref(&node));
 
 		(
 			Self {
 				roots: vec![node],
 				cached_seeds,
+				env,
+				format,
+				rejected: Vec::new(),
+				revisions,
+				bases: [(id, locked_root.hash())].into_iter().collect(),
+				listings: [(id, Listing::of([].iter()))].into_iter().collect(),
+				events: Vec::new(),
+				index,
 			},
 			locked_root,
 		)
 	}
 
 	// TODO: for god, remember to pass one share { root_id: seed } manually
-	pub fn from_locked_nodes(locked_nodes: &[LockedNode], bundles: &Seeds) -> FileSystem {
-		let (mut nodes, branches, roots) = Self::parse_locked(locked_nodes, bundles);
+	// fails, if a dir's listing doesn't match its children, see verify_listings
+	pub fn from_locked_nodes(
+		locked_nodes: &[LockedNode],
+		bundles: &Seeds,
+	) -> Result<FileSystem, Error> {
+		Self::from_locked_nodes_with_env(locked_nodes, bundles, Rc::new(Env::system()))
+	}
 
-		FileSystem {
-			roots: Self::build_hierarchy(&mut nodes, &branches, &roots),
+	pub fn from_locked_nodes_with_env(
+		locked_nodes: &[LockedNode],
+		bundles: &Seeds,
+		env: Rc<Env>,
+	) -> Result<FileSystem, Error> {
+		let mut rejected = Vec::new();
+		let mut revisions = Revisions::new();
+		let (mut nodes, branches, roots, listings) =
+			Self::parse_locked(locked_nodes, bundles, &mut rejected, &mut revisions)?;
+		let bases = bases_of(locked_nodes, &nodes);
+		let roots = Self::build_hierarchy(&mut nodes, &branches, &roots);
+
+		Ok(FileSystem {
+			index: Index::of(&roots),
+			roots,
 			cached_seeds: bundles.clone(),
-		}
+			env,
+			format: Format::default(),
+			rejected,
+			revisions,
+			bases,
+			listings,
+			events: Vec::new(),
+		})
 	}
 
-	// a universal method for files as well?
-	// how about removeal?
-	pub fn add_or_update_subtree(
-		&mut self,
-		locked_nodes: &[LockedNode],
-		parent_id: Uid,
-	) -> Result<(), Error> {
-		if let Some(parent) = self.node_by_id_mut(parent_id) {
-			parent.dirty = false;
+	pub fn env(&self) -> Rc<Env> {
+		self.env.clone()
+	}
 
-			if let Entry::Dir {
-				ref seed,
-				ref mut children,
-			} = parent.entry
-			{
-				let mut node_map: HashMap<Uid, Node> = HashMap::new();
-				let mut locked_node_map: HashMap<Uid, &LockedNode> = HashMap::new();
-				let mut branches: HashMap<Uid, Vec<Uid>> = HashMap::new();
-
-				// put each node to a branch, if possible
-				for locked_node in locked_nodes {
-					if locked_nodes.iter().any(|ln| ln.id == locked_node.parent_id)
-						|| locked_node.parent_id == parent_id
-					{
-						branches
-							.entry(locked_node.parent_id)
-							.or_default()
-							.push(locked_node.id);
-					}
+	// nodes that were present, but couldn't be opened; reachable nodes only
+	pub fn rejected(&self) -> &[Error] {
+		&self.rejected
+	}
 
-					locked_node_map.insert(locked_node.id, locked_node);
-				}
+	// to be persisted by clients and passed to track_revisions next time
+	pub fn revisions(&self) -> &Revisions {
+		&self.revisions
+	}
 
-				let roots = branches.get(&parent_id).unwrap_or(&Vec::new()).clone();
-
-				for node_id in &roots {
-					if let Some(locked_node) = locked_node_map.remove(&node_id) {
-						let encrypted = &locked_node.content;
-						let aes =
-							aes_from_parent_seed_for_node(seed, locked_node.id, &encrypted.salt);
-						if let Ok(content) = LockedContent::try_from_encrypted(
-							&encrypted.ct,
-							aes,
-							locked_node.id,
-							locked_node.parent_id,
-						) {
-							let node = Node {
-								id: locked_node.id,
-								parent_id: locked_node.parent_id,
-								created_at: content.created_at,
-								name: content.name,
-								entry: match content.entry {
-									LockedEntry::File { info } => Entry::File { info },
-									LockedEntry::Dir { seed } => Entry::Dir {
-										seed,
-										children: vec![],
-									},
-								},
-								dirty: locked_node.dirty,
-								created_by: content.created_by,
-							};
-							node_map.insert(node.id, node);
-						}
-					}
-				}
+	// restores revisions saved in a previous session; nodes loaded since, but older than what was
+	// seen back then, are reported as rolled back (though still listed)
+	pub fn track_revisions(&mut self, saved: &Revisions) {
+		for (id, seen) in saved.iter() {
+			let revision = self.revisions.get(id);
 
-				let mut to_process: Vec<Uid> = node_map.keys().cloned().collect();
-
-				while let Some(id) = to_process.pop() {
-					let mut new_nodes = Vec::new();
-
-					if let Some(node) = node_map.get(&id) {
-						if let Entry::Dir { seed, .. } = &node.entry {
-							if let Some(child_ids) = branches.get(&id) {
-								for child_id in child_ids {
-									if let Some(locked_node) = locked_node_map.get(&child_id) {
-										let encrypted = &locked_node.content;
-										let aes = aes_from_parent_seed_for_node(
-											seed,
-											*child_id,
-											&encrypted.salt,
-										);
-
-										if let Ok(content) = LockedContent::try_from_encrypted(
-											&encrypted.ct,
-											aes,
-											locked_node.id,
-											locked_node.parent_id,
-										) {
-											let child_node = Node {
-												id: locked_node.id,
-												parent_id: locked_node.parent_id,
-												created_at: content.created_at,
-												name: content.name.clone(),
-												entry: match content.entry {
-													LockedEntry::File { info } => {
-														Entry::File { info }
-													}
-													LockedEntry::Dir { seed } => Entry::Dir {
-														seed,
-														children: vec![],
-													},
-												},
-												dirty: locked_node.dirty,
-												created_by: content.created_by,
-											};
-
-											new_nodes.push((child_id, child_node));
-											to_process.push(*child_id);
-										}
-									}
-								}
+			if self.revisions.observe(id, seen).is_ok() && revision < seen && revision > 0 {
+				self.reject(vec![Error::RolledBack { id, revision, seen }]);
+			}
+		}
+	}
+
+	// drains changes made to the tree since the last call; loading a tree is not a change
+	pub fn take_events(&mut self) -> Vec<Event> {
+		std::mem::take(&mut self.events)
+	}
+
+	// the subtrees of dir_ids, to be put back with restore; ids which aren't attached are skipped
+	pub fn snapshot(&self, dir_ids: impl IntoIterator<Item = Uid>) -> Snapshot {
+		let mut dir_ids: Vec<Uid> = dir_ids.into_iter().collect();
+
+		dir_ids.sort();
+		dir_ids.dedup();
+
+		let dirs: Vec<Node> = dir_ids
+			.into_iter()
+			.filter_map(|id| self.node_by_id(id))
+			.cloned()
+			.collect();
+		let mut nodes = Vec::new();
+
+		flatten(&dirs, &mut nodes);
+
+		let revisions = nodes
+			.iter()
+			.filter_map(|n| Some((n.id, self.revisions.seen(n.id)?)))
+			.collect();
+		let bases = nodes
+			.iter()
+			.filter_map(|n| Some((n.id, *self.bases.get(&n.id)?)))
+			.collect();
+		let listings = nodes
+			.iter()
+			.filter_map(|n| Some((n.id, self.listings.get(&n.id)?.clone())))
+			.collect();
+
+		Snapshot {
+			dirs,
+			revisions,
+			bases,
+			listings,
+			rejected: self.rejected.clone(),
+			events: self.events.len(),
+		}
+	}
+
+	// undoes whatever's happened to the subtrees of snapshot since, events included
+	pub fn restore(&mut self, snapshot: Snapshot) {
+		// all detached first, so that a node moved from one to another is indexed once restored
+		for dir in &snapshot.dirs {
+			if let Some(cur) = node_by_id_mut(&mut self.roots, dir.id) {
+				let mut nodes = Vec::new();
+
+				flatten(std::slice::from_ref(cur), &mut nodes);
+
+				for node in nodes {
+					self.revisions.reset(node.id, None);
+					self.bases.remove(&node.id);
+					self.listings.remove(&node.id);
+				}
+
+				self.index.remove(cur);
+			}
+		}
+
+		for dir in snapshot.dirs {
+			if let Some(cur) = node_by_id_mut(&mut self.roots, dir.id) {
+				self.index.insert(&dir);
+				*cur = dir;
+			}
+		}
+
+		for (id, seen) in snapshot.revisions {
+			self.revisions.reset(id, Some(seen));
+		}
+
+		self.bases.extend(snapshot.bases);
+		self.listings.extend(snapshot.listings);
+		self.rejected = snapshot.rejected;
+		self.events.truncate(snapshot.events);
+	}
+
+	// for nodes locked from now on, eg once a backend has agreed on a binary format
+	pub fn set_format(&mut self, format: Format) {
+		self.format = format;
+	}
+
+	// a universal method for files as well?
+	// how about removeal?
+	// replaces the children of parent_id with the ones fetched; fails and leaves the tree as is, if
+	// they don't match the listing of parent_id or of any dir among them, see verify_listings
+	pub fn add_or_update_subtree(
+		&mut self,
+		locked_nodes: &[LockedNode],
+		parent_id: Uid,
+	) -> Result<(), Error> {
+		let mut rejected = Vec::new();
+
+		// refetched nodes get a fresh verdict
+		self.rejected
+			.retain(|e| e.id() != parent_id && locked_nodes.iter().all(|ln| ln.id != e.id()));
+
+		let seed = match self.node_by_id(parent_id) {
+			Some(Node {
+				entry: Entry::Dir { seed, .. },
+				..
+			}) => seed.clone(),
+			Some(_) => {
+				return Err(Error::BadOperation {
+					id: parent_id,
+					reason: "not a dir",
+				})
+			}
+			None => return Err(Error::NotFound(parent_id)),
+		};
+		// committed only once everything is verified
+		let mut revisions = self.revisions.clone();
+		let mut node_map: HashMap<Uid, Node> = HashMap::new();
+		let mut locked_node_map: HashMap<Uid, &LockedNode> = HashMap::new();
+		let mut branches: HashMap<Uid, Vec<Uid>> = HashMap::new();
+		let mut listings = HashMap::new();
+
+		// put each node to a branch, if possible
+		// nodes written by newer clients are ignored, as if they were forged
+		for locked_node in locked_nodes {
+			if let Err(e) = locked_node.check_version() {
+				rejected.push(Error::Locked {
+					id: locked_node.id,
+					stage: Stage::Version,
+					cause: Cause::new(e),
+				});
+
+				continue;
+			}
+
+			if locked_nodes.iter().any(|ln| ln.id == locked_node.parent_id)
+				|| locked_node.parent_id == parent_id
+			{
+				branches
+					.entry(locked_node.parent_id)
+					.or_default()
+					.push(locked_node.id);
+			}
+
+			locked_node_map.insert(locked_node.id, locked_node);
+		}
+
+		for node_id in branches.get(&parent_id).into_iter().flatten() {
+			if let Some(locked_node) = locked_node_map.remove(node_id) {
+				let encrypted = &locked_node.content;
+				let aes = aes_from_parent_seed_for_node(&seed, locked_node.id, &encrypted.salt);
+
+				if let Some((node, listing)) =
+					self.reopen(locked_node, aes, &mut revisions, &mut rejected)
+				{
+					if let Some(listing) = listing {
+						listings.insert(node.id, listing);
+					}
+
+					node_map.insert(node.id, node);
+				}
+			}
+		}
+
+		let mut to_process: Vec<Uid> = node_map.keys().cloned().collect();
+
+		while let Some(id) = to_process.pop() {
+			let mut new_nodes = Vec::new();
+
+			if let Some(node) = node_map.get(&id) {
+				if let Entry::Dir { seed, .. } = &node.entry {
+					if let Some(child_ids) = branches.get(&id) {
+						for child_id in child_ids {
+							if let Some(locked_node) = locked_node_map.get(child_id) {
+								let encrypted = &locked_node.content;
+								let aes =
+									aes_from_parent_seed_for_node(seed, *child_id, &encrypted.salt);
+
+								if let Some((child_node, listing)) =
+									self.reopen(locked_node, aes, &mut revisions, &mut rejected)
+								{
+									if let Some(listing) = listing {
+										listings.insert(*child_id, listing);
+									}
+
+									new_nodes.push((child_id, child_node));
+									to_process.push(*child_id);
+								}
 							}
 						}
 					}
+				}
+			}
+
+			for (child_id, child_node) in new_nodes {
+				locked_node_map.remove(child_id);
+				node_map.insert(*child_id, child_node);
+			}
+		}
+
+		// parent_id is loaded now, so its own listing is verified as well
+		if let Some(listing) = self.listings.get(&parent_id) {
+			listings.insert(parent_id, listing.clone());
+		}
+
+		// reported either way, eg a rolled back child which leaves its dir incomplete
+		let failed = verify_listings(&listings, &node_map, &mut branches, &mut rejected);
+
+		if let Some((_, e)) = failed.into_iter().next() {
+			self.reject(rejected);
+
+			return Err(e);
+		}
+
+		let roots = branches.get(&parent_id).cloned().unwrap_or_default();
+
+		self.revisions = revisions;
+		self.listings.extend(listings);
+		self.bases.extend(bases_of(locked_nodes, &node_map));
+
+		let children = Self::build_hierarchy(&mut node_map, &branches, &roots);
+		let parent =
+			node_by_id_mut(&mut self.roots, parent_id).ok_or(Error::NotFound(parent_id))?;
+
+		parent.dirty = false;
+
+		if let Entry::Dir {
+			children: ref mut cur,
+			..
+		} = parent.entry
+		{
+			let prev = std::mem::replace(cur, children);
+
+			diff_subtrees(&prev, cur, &mut self.events);
+			prev.iter().for_each(|node| self.index.remove(node));
+			cur.iter().for_each(|node| self.index.insert(node));
+		}
+
+		self.reject(rejected);
+
+		Ok(())
+	}
+
+	// opens a refetched node; a rolled back one is reported, but the version known so far is kept
+	// in its place, and its children are refetched along with it
+	fn reopen(
+		&self,
+		locked_node: &LockedNode,
+		aes: Aes,
+		revisions: &mut Revisions,
+		rejected: &mut Vec<Error>,
+	) -> Option<(Node, Option<Listing>)> {
+		let e = match open_locked(locked_node, aes, revisions) {
+			Ok(opened) => return Some(opened),
+			Err(e) => e,
+		};
+		let kept = match e {
+			Error::RolledBack { id, .. } => self.node_by_id(id).map(|prev| {
+				let entry = match &prev.entry {
+					Entry::File { info } => Entry::File { info: info.clone() },
+					Entry::Dir { seed, .. } => Entry::Dir {
+						seed: seed.clone(),
+						children: Vec::new(),
+					},
+				};
+				let node = Node {
+					entry,
+					dirty: locked_node.dirty,
+					name: prev.name.clone(),
+					created_by: prev.created_by.clone(),
+					..*prev
+				};
+
+				(node, self.listings.get(&id).cloned())
+			}),
+			_ => None,
+		};
+
+		rejected.push(e);
+
+		kept
+	}
 
-					for (child_id, child_node) in new_nodes {
-						locked_node_map.remove(&child_id);
-						node_map.insert(*child_id, child_node);
+	// applies a page of a change log in place, rather than refetching whole subtrees: saved nodes
+	// are added, updated or moved, deleted ones are removed along with their subtrees; changes out
+	// of reach, eg in subtrees not shared with us, are ignored; dirs whose children changed are
+	// verified against their listings, see verify_listed
+	pub fn apply_changes(&mut self, changes: &[Change]) -> Result<(), Error> {
+		let mut rejected = Vec::new();
+
+		self.rejected.retain(|e| {
+			changes.iter().all(|c| match c {
+				Change::Saved(ln) => ln.id != e.id(),
+				Change::Deleted(id) => *id != e.id(),
+			})
+		});
+
+		let mut touched = HashSet::new();
+		// parents may come later in the same page
+		let mut unresolved = Vec::new();
+
+		for change in changes {
+			match change {
+				Change::Saved(locked_node) => {
+					if !self.apply_saved(locked_node, &mut touched, &mut rejected) {
+						unresolved.push(locked_node);
+					}
+				}
+				Change::Deleted(id) => {
+					if let Ok(node) = self.delete_node(*id) {
+						touched.insert(node.parent_id);
 					}
 				}
+			}
+		}
+
+		loop {
+			let count = unresolved.len();
+
+			unresolved.retain(|ln| !self.apply_saved(ln, &mut touched, &mut rejected));
+
+			if unresolved.len() == count {
+				break;
+			}
+		}
+
+		// out of reach now, so known ones must have been moved elsewhere
+		for locked_node in unresolved {
+			_ = self.delete_node(locked_node.id);
+		}
+
+		let res = touched
+			.into_iter()
+			.map(|dir_id| self.verify_listed(dir_id, &mut rejected))
+			.fold(Ok(()), Result::and);
+
+		self.reject(rejected);
+
+		res
+	}
+
+	// leaves out children of dir_id which aren't listed (yet), or unloads all of them (so that
+	// they're refetched), if a listed one is missing or stale
+	fn verify_listed(&mut self, dir_id: Uid, rejected: &mut Vec<Error>) -> Result<(), Error> {
+		let Some(listing) = self.listings.get(&dir_id) else {
+			return Ok(());
+		};
+		let Some(Node {
+			entry: Entry::Dir { children, .. },
+			dirty: false,
+			..
+		}) = self.node_by_id(dir_id)
+		else {
+			return Ok(());
+		};
+
+		let excused = |id| {
+			self.rejected
+				.iter()
+				.chain(rejected.iter())
+				.any(|e| e.id() == id)
+		};
 
-				*children = Self::build_hierarchy(&mut node_map, &branches, &roots);
-				// TODO: move dirty from Node to Entry::Dir and set to false here
+		match listing.check(dir_id, children.iter(), excused) {
+			Ok(unlisted) => {
+				for id in unlisted {
+					_ = self.delete_node(id);
+					rejected.push(Error::Locked {
+						id,
+						stage: Stage::Listing,
+						cause: Cause::default(),
+					});
+				}
 
 				Ok(())
-			} else {
-				Err(Error::BadOperation)
 			}
+			Err(e) => {
+				if let Some(Node {
+					entry: Entry::Dir { children, .. },
+					..
+				}) = node_by_id_mut(&mut self.roots, dir_id)
+				{
+					let prev = std::mem::take(children);
+
+					diff_subtrees(&prev, &[], &mut self.events);
+					prev.iter().for_each(|node| self.index.remove(node));
+				}
+
+				self.mark_dirty(dir_id)?;
+
+				Err(e)
+			}
+		}
+	}
+
+	// one report per node, the latest one, so that refetching doesn't pile them up
+	fn reject(&mut self, rejected: Vec<Error>) {
+		for e in rejected {
+			self.rejected.retain(|r| r.id() != e.id());
+			self.rejected.push(e);
+		}
+	}
+
+	// false, if there's no seed to open the node with (yet)
+	fn apply_saved(
+		&mut self,
+		locked_node: &LockedNode,
+		touched: &mut HashSet<Uid>,
+		rejected: &mut Vec<Error>,
+	) -> bool {
+		if let Err(e) = locked_node.check_version() {
+			rejected.push(Error::Locked {
+				id: locked_node.id,
+				stage: Stage::Version,
+				cause: Cause::new(e),
+			});
+
+			return true;
+		}
+
+		let Some(aes) = self.aes_for(locked_node) else {
+			return false;
+		};
+
+		match open_locked(locked_node, aes, &mut self.revisions) {
+			Ok((node, listing)) => {
+				if let Some(listing) = listing {
+					self.listings.insert(node.id, listing);
+					touched.insert(node.id);
+				}
+
+				if let Some(prev) = self.node_by_id(node.id) {
+					touched.insert(prev.parent_id);
+				}
+
+				touched.insert(node.parent_id);
+				self.bases.insert(node.id, locked_node.hash());
+				self.upsert(node);
+			}
+			Err(e) => rejected.push(e),
+		}
+
+		true
+	}
+
+	// under its parent, if known, or its share otherwise
+	fn aes_for(&self, locked_node: &LockedNode) -> Option<Aes> {
+		let salt = &locked_node.content.salt;
+
+		if let Some(Node {
+			entry: Entry::Dir { seed, .. },
+			..
+		}) = self.node_by_id(locked_node.parent_id)
+		{
+			Some(aes_from_parent_seed_for_node(seed, locked_node.id, salt))
+		} else {
+			self.cached_seeds
+				.get(&locked_node.id)
+				.map(|seed| aes_from_node_seed(seed, salt))
+		}
+	}
+
+	// adds a node or replaces it, wherever it was, keeping its children
+	fn upsert(&mut self, mut node: Node) {
+		let mut prev = self.detach(node.id).ok();
+
+		// children are not a part of a dir's content, so keep the ones known so far
+		if let (
+			Some(Node {
+				entry: Entry::Dir { children, .. },
+				..
+			}),
+			Entry::Dir {
+				children: ref mut new_children,
+				..
+			},
+		) = (&mut prev, &mut node.entry)
+		{
+			*new_children = std::mem::take(children);
+		}
+
+		if self.node_by_id(node.parent_id).is_some() {
+			push_events(prev.as_ref(), &node, &mut self.events);
+			_ = self.attach(node);
 		} else {
-			Err(Error::NotFound)
+			if prev.is_some() {
+				push_events(prev.as_ref(), &node, &mut self.events);
+			} else {
+				self.events.push(Event::ShareImported(node.id));
+			}
+
+			self.index.insert(&node);
+			self.roots.push(node);
+		}
+	}
+
+	// the hash of the locked node an edit of id is to be based on; None for nodes not saved yet
+	pub fn base(&self, id: Uid) -> Option<hmac::Digest> {
+		self.bases.get(&id).cloned()
+	}
+
+	// to be called once a locked node is saved, so that further edits are based on it
+	pub fn set_base(&mut self, locked_node: &LockedNode) {
+		self.bases.insert(locked_node.id, locked_node.hash());
+	}
+
+	// takes a dir as stored by someone else into account, so that the next listing is based on it;
+	// its children aren't verified, since they're about to be listed again anyway
+	pub fn rebase_listing(&mut self, theirs: &LockedNode) -> Result<(), Error> {
+		let aes = self.aes_for(theirs).ok_or(Error::NoAccess(theirs.id))?;
+		let (mut node, listing) = open_locked(theirs, aes, &mut self.revisions)?;
+
+		let Some(listing) = listing else {
+			return Err(Error::BadOperation {
+				id: theirs.id,
+				reason: "not a dir",
+			});
+		};
+
+		// its children are kept as loaded (or not)
+		node.dirty = self.node_by_id(node.id).is_some_and(|n| n.dirty);
+		self.listings.insert(node.id, listing);
+		self.bases.insert(theirs.id, theirs.hash());
+		self.upsert(node);
+
+		Ok(())
+	}
+
+	// settles a conflict between mine, an edit based on an outdated node, and theirs, the node as
+	// currently stored: theirs is applied to the tree first, and then whatever else the resolution
+	// needs; returns new nodes to save on top of theirs (see base), if any
+	pub fn resolve(
+		&mut self,
+		mine: &Node,
+		theirs: &LockedNode,
+		resolution: Resolution,
+		sign_by: &identity::Identity,
+	) -> Result<Vec<LockedNode>, Error> {
+		let aes = self.aes_for(theirs).ok_or(Error::NoAccess(theirs.id))?;
+		let (theirs_node, listing) = open_locked(theirs, aes, &mut self.revisions)?;
+
+		if let Some(listing) = listing {
+			self.listings.insert(theirs.id, listing);
+		}
+
+		self.bases.insert(theirs.id, theirs.hash());
+		self.upsert(theirs_node);
+
+		// theirs may list children which aren't known yet, so that they're refetched
+		if let (
+			Some(listing),
+			Some(Node {
+				entry: Entry::Dir { children, .. },
+				dirty: false,
+				..
+			}),
+		) = (self.listings.get(&theirs.id), self.node_by_id(theirs.id))
+		{
+			if listing
+				.check(theirs.id, children.iter(), |_| false)
+				.is_err()
+			{
+				self.mark_dirty(theirs.id)?;
+			}
+		}
+
+		match resolution {
+			Resolution::Theirs => Ok(Vec::new()),
+			Resolution::Mine => {
+				// only what an edit changes is taken from mine, the rest may have changed since
+				let mut node = self
+					.node_by_id(mine.id)
+					.ok_or(Error::NotFound(mine.id))?
+					.clone();
+
+				node.name = mine.name.clone();
+				node.parent_id = mine.parent_id;
+
+				// re-signed with its current children listed
+				if let Entry::Dir { .. } = node.entry {
+					if !node.dirty {
+						self.upsert(node);
+
+						return Ok(vec![self.list(mine.id, sign_by)?]);
+					}
+				}
+
+				node.revision = self.revisions.get(node.id) + 1;
+				node.created_by = sign_by.public().clone();
+
+				let locked = self.lock(&node, sign_by)?;
+
+				_ = self.revisions.observe(node.id, node.revision);
+				self.upsert(node);
+
+				Ok(vec![locked])
+			}
+			Resolution::Both => {
+				if let Entry::File { .. } = mine.entry {
+					let copy = Node {
+						id: Uid::generate_with(&mut **self.env.rng()),
+						name: format!("{} (conflict)", mine.name),
+						created_by: sign_by.public().clone(),
+						revision: 1,
+						..mine.clone()
+					};
+					let locked = self.lock(&copy, sign_by)?;
+
+					self.insert_node(copy)?;
+
+					Ok(vec![locked])
+				} else {
+					Err(Error::BadOperation {
+						id: mine.id,
+						reason: "not a file",
+					})
+				}
+			}
 		}
 	}
 
-	// returns (nodes, branches, roots)
+	// returns (nodes, branches, roots, listings)
+	#[allow(clippy::type_complexity)]
 	fn parse_locked(
 		locked_nodes: &[LockedNode],
 		bundles: &Seeds,
-	) -> (HashMap<Uid, Node>, HashMap<Uid, Vec<Uid>>, Vec<Uid>) {
+		rejected: &mut Vec<Error>,
+		revisions: &mut Revisions,
+	) -> Result<
+		(
+			HashMap<Uid, Node>,
+			HashMap<Uid, Vec<Uid>>,
+			Vec<Uid>,
+			HashMap<Uid, Listing>,
+		),
+		Error,
+	> {
 		// rebuild from self.nodes? – rathe rnot
 		let mut node_map: HashMap<Uid, Node> = HashMap::new();
 		let mut locked_node_map: HashMap<Uid, &LockedNode> = HashMap::new();
 		let mut branches: HashMap<Uid, Vec<Uid>> = HashMap::new();
+		let mut listings = HashMap::new();
 		let mut roots = Vec::new();
 
 		// put each node to a branch, if possible
 		for locked_node in locked_nodes {
+			if let Err(e) = locked_node.check_version() {
+				rejected.push(Error::Locked {
+					id: locked_node.id,
+					stage: Stage::Version,
+					cause: Cause::new(e),
+				});
+
+				continue;
+			}
+
 			if locked_nodes.iter().any(|ln| ln.id == locked_node.parent_id) {
 				branches
 					.entry(locked_node.parent_id)
@@ -456,28 +1671,15 @@ impl FileSystem {
 				let encrypted = &locked_node.content;
 				let aes = aes_from_node_seed(seed, &encrypted.salt);
 
-				if let Ok(content) = LockedContent::try_from_encrypted(
-					&encrypted.ct,
-					aes,
-					locked_node.id,
-					locked_node.parent_id,
-				) {
-					let node = Node {
-						id: locked_node.id,
-						parent_id: locked_node.parent_id,
-						created_at: content.created_at,
-						name: content.name,
-						entry: match content.entry {
-							LockedEntry::File { info } => Entry::File { info },
-							LockedEntry::Dir { seed } => Entry::Dir {
-								seed,
-								children: vec![],
-							},
-						},
-						dirty: locked_node.dirty,
-						created_by: content.created_by,
-					};
-					node_map.insert(node.id, node);
+				match open_locked(locked_node, aes, revisions) {
+					Ok((node, listing)) => {
+						if let Some(listing) = listing {
+							listings.insert(node.id, listing);
+						}
+
+						node_map.insert(node.id, node);
+					}
+					Err(e) => rejected.push(e),
 				}
 			}
 		}
@@ -496,30 +1698,16 @@ impl FileSystem {
 								let aes =
 									aes_from_parent_seed_for_node(seed, *child_id, &encrypted.salt);
 
-								if let Ok(content) = LockedContent::try_from_encrypted(
-									&encrypted.ct,
-									aes,
-									locked_node.id,
-									locked_node.parent_id,
-								) {
-									let child_node = Node {
-										id: locked_node.id,
-										parent_id: locked_node.parent_id,
-										created_at: content.created_at,
-										name: content.name.clone(),
-										entry: match content.entry {
-											LockedEntry::File { info } => Entry::File { info },
-											LockedEntry::Dir { seed } => Entry::Dir {
-												seed,
-												children: vec![],
-											},
-										},
-										dirty: locked_node.dirty,
-										created_by: content.created_by,
-									};
+								match open_locked(locked_node, aes, revisions) {
+									Ok((child_node, listing)) => {
+										if let Some(listing) = listing {
+											listings.insert(*child_id, listing);
+										}
 
-									new_nodes.push((child_id, child_node));
-									to_process.push(*child_id);
+										new_nodes.push((child_id, child_node));
+										to_process.push(*child_id);
+									}
+									Err(e) => rejected.push(e),
 								}
 							}
 						}
@@ -533,6 +1721,16 @@ impl FileSystem {
 			}
 		}
 
+		// failing the lot is not an option, since a server could then lock a user out by leaving a
+		// single child out; such dirs are left dirty instead, to be refetched
+		for (dir_id, e) in verify_listings(&listings, &node_map, &mut branches, rejected) {
+			if let Some(dir) = node_map.get_mut(&dir_id) {
+				dir.dirty = true;
+			}
+
+			rejected.push(e);
+		}
+
 		for (id, node) in &node_map {
 			if !node_map.contains_key(&node.parent_id) {
 				roots.push(*id);
@@ -540,7 +1738,7 @@ impl FileSystem {
 		}
 
 		// (node_map, branches, bundles.keys().cloned().collect()
-		(node_map, branches, roots)
+		Ok((node_map, branches, roots, listings))
 	}
 
 	fn build_hierarchy(
@@ -618,22 +1816,13 @@ impl FileSystem {
 		None
 	}
 
-	pub fn node_by_id_mut(&mut self, id: Uid) -> Option<&mut Node> {
-		let mut stack: Vec<&mut Node> = self.roots.iter_mut().collect();
-
-		while let Some(node) = stack.pop() {
-			if node.id == id {
-				return Some(node);
-			}
-
-			if let Entry::Dir { children, .. } = &mut node.entry {
-				for child in children {
-					stack.push(child);
-				}
-			}
-		}
+	// ids of the best matches for query, see search
+	pub fn search(&self, query: &str, limit: usize) -> Vec<Uid> {
+		self.index.search(query, limit)
+	}
 
-		None
+	pub fn node_by_id_mut(&mut self, id: Uid) -> Option<&mut Node> {
+		node_by_id_mut(&mut self.roots, id)
 	}
 
 	pub fn ls_dir(&self, id: Uid) -> Result<Vec<&Node>, Error> {
@@ -641,10 +1830,13 @@ impl FileSystem {
 			if let Entry::Dir { ref children, .. } = node.entry {
 				Ok(children.iter().collect())
 			} else {
-				Err(Error::BadOperation)
+				Err(Error::BadOperation {
+					id,
+					reason: "not a dir",
+				})
 			}
 		} else {
-			Err(Error::NotFound)
+			Err(Error::NotFound(id))
 		}
 	}
 
@@ -673,36 +1865,61 @@ impl FileSystem {
 				seed: ref parent_seed,
 			} = node.entry
 			{
-				let id = Uid::generate();
+				let id = Uid::generate_with(&mut **self.env.rng());
 				let new_node = Node {
 					id,
 					parent_id,
-					created_at: now(),
+					created_at: self.env.now(),
 					name: name.to_string(),
 					entry: Entry::Dir {
-						seed: Seed::generate(),
+						seed: Seed::generate_with(&mut **self.env.rng()),
 						children: vec![],
 					},
 					dirty: false,
 					created_by: owner.public().clone(),
+					revision: 1,
 				};
-				let locked_node =
-					Node::encrypt_with_parent_seed(&new_node, parent_seed, owner.private());
+				let locked_node = Node::encrypt_with_parent_seed(
+					&new_node,
+					new_node.locked_entry(),
+					parent_seed,
+					owner.private(),
+					&self.env,
+					self.format,
+				);
 
 				Ok(NewNodeReq {
 					node: new_node,
 					locked_node,
 				})
 			} else {
-				Err(Error::BadOperation)
+				Err(Error::BadOperation {
+					id: parent_id,
+					reason: "not a dir",
+				})
 			}
 		} else {
-			Err(Error::NoAccess)
+			Err(Error::NoAccess(parent_id))
 		}
 	}
 
 	pub fn insert_node(&mut self, node: Node) -> Result<Uid, Error> {
-		if let Some(parent) = self.node_by_id_mut(node.parent_id) {
+		let (id, parent_id, dirty) = (node.id, node.parent_id, node.dirty);
+
+		self.attach(node)?;
+		self.events.push(Event::Added { id, parent_id });
+
+		if dirty {
+			self.events.push(Event::Dirty(id));
+		}
+
+		Ok(id)
+	}
+
+	fn attach(&mut self, node: Node) -> Result<Uid, Error> {
+		let parent_id = node.parent_id;
+
+		if let Some(parent) = node_by_id_mut(&mut self.roots, parent_id) {
 			if let Entry::Dir {
 				ref mut children,
 				seed: _,
@@ -710,188 +1927,925 @@ impl FileSystem {
 			{
 				let id = node.id;
 
+				_ = self.revisions.observe(id, node.revision);
+				self.index.insert(&node);
 				children.push(node);
 
 				Ok(id)
 			} else {
-				Err(Error::BadOperation)
+				Err(Error::BadOperation {
+					id: parent_id,
+					reason: "not a dir",
+				})
 			}
 		} else {
-			Err(Error::NotFound)
+			Err(Error::NotFound(parent_id))
 		}
 	}
 
-	pub fn delete_node(&mut self, id: Uid) -> Result<(), Error> {
-		if let Some(node) = self.node_by_id(id) {
-			if let Some(parent) = self.node_by_id_mut(node.parent_id) {
-				if let Entry::Dir {
-					ref mut children,
-					seed: _,
-				} = parent.entry
-				{
-					children.retain(|n| n.id != id);
+	// removes a node along with its subtree and returns it
+	pub fn delete_node(&mut self, id: Uid) -> Result<Node, Error> {
+		let node = self.detach(id)?;
+
+		if self.node_by_id(node.parent_id).is_some() || id == ROOT_ID {
+			self.events.push(Event::Removed {
+				id,
+				parent_id: node.parent_id,
+			});
+		} else {
+			self.events.push(Event::ShareRevoked(id));
+		}
+
+		Ok(node)
+	}
+
+	fn detach(&mut self, id: Uid) -> Result<Node, Error> {
+		let parent_id = self.node_by_id(id).ok_or(Error::NotFound(id))?.parent_id;
+		let siblings = if self.roots.iter().any(|n| n.id == id) {
+			&mut self.roots
+		} else if let Some(Node {
+			entry: Entry::Dir {
+				ref mut children, ..
+			},
+			..
+		}) = node_by_id_mut(&mut self.roots, parent_id)
+		{
+			children
+		} else {
+			return Err(Error::NotFound(id));
+		};
+		let idx = siblings
+			.iter()
+			.position(|n| n.id == id)
+			.ok_or(Error::NotFound(id))?;
+		let node = siblings.remove(idx);
+
+		self.index.remove(&node);
+
+		Ok(node)
+	}
+
+	// replaces a node in place, eg when renamed; bump its revision first
+	pub fn update_node(&mut self, node: Node) -> Result<(), Error> {
+		let revisions = &mut self.revisions;
+
+		if let Some(existing) = node_by_id_mut(&mut self.roots, node.id) {
+			_ = revisions.observe(node.id, node.revision);
+			push_events(Some(&*existing), &node, &mut self.events);
+			self.index.remove(existing);
+			self.index.insert(&node);
+			*existing = node;
+
+			Ok(())
+		} else {
+			Err(Error::NotFound(node.id))
+		}
+	}
+
+	// eg when a server reports that a dir has changed, so that it's refetched when listed next
+	pub fn mark_dirty(&mut self, id: Uid) -> Result<(), Error> {
+		let node = node_by_id_mut(&mut self.roots, id).ok_or(Error::NotFound(id))?;
+
+		if !node.dirty {
+			// what it'd be locked with now, see locked_entry
+			if let Entry::Dir { children, .. } = &node.entry {
+				self.listings
+					.entry(id)
+					.or_insert_with(|| Listing::of(children.iter()));
+			}
+
+			node.dirty = true;
+			self.events.push(Event::Dirty(id));
+		}
+
+		Ok(())
+	}
+
+	// touch and immediately apply its transaction; use Protocol::upload_file instead to commit
+	// the node only after its content is uploaded
+	pub fn touch_mut(
+		&mut self,
+		parent_id: Uid,
+		size: u64,
+		name: &str,
+		ext: &str,
+		owner: &identity::Identity,
+	) -> Result<(Uid, LockedNode), Error> {
+		let NewNodeReq { node, locked_node } = self.touch(parent_id, size, name, ext, owner)?;
+		let id = self.insert_node(node)?;
 
-					return Ok(());
+		Ok((id, locked_node))
+	}
+
+	pub fn touch(
+		&self,
+		parent_id: Uid,
+		size: u64,
+		name: &str,
+		ext: &str,
+		owner: &identity::Identity,
+	) -> Result<NewNodeReq, Error> {
+		if let Some(node) = self.node_by_id(parent_id) {
+			if let Entry::Dir {
+				children: _,
+				seed: ref parent_seed,
+			} = node.entry
+			{
+				let id = Uid::generate_with(&mut **self.env.rng());
+				let new_node = Node {
+					id,
+					parent_id,
+					created_at: self.env.now(),
+					name: name.to_string(),
+					entry: Entry::File {
+						info: FileInfo {
+							size,
+							key_iv: Aes::generate_with(&mut **self.env.rng()),
+							ext: ext.to_string(),
+							chunk_size: Some(CHUNK_SIZE),
+							digest: None,
+						},
+					},
+					dirty: false,
+					created_by: owner.public().clone(),
+					revision: 1,
+				};
+				let locked_node = Node::encrypt_with_parent_seed(
+					&new_node,
+					new_node.locked_entry(),
+					parent_seed,
+					owner.private(),
+					&self.env,
+					self.format,
+				);
+
+				Ok(NewNodeReq {
+					node: new_node,
+					locked_node,
+				})
+			} else {
+				Err(Error::BadOperation {
+					id: parent_id,
+					reason: "not a dir",
+				})
+			}
+		} else {
+			Err(Error::NoAccess(parent_id))
+		}
+	}
+
+	// re-locks outdated nodes in the current layout; nodes created by others are left as is, since
+	// only their authors can re-sign them, but they still decode fine; so are rejected ones
+	pub fn migrate(
+		&self,
+		locked_nodes: &[LockedNode],
+		sign_by: &identity::Identity,
+	) -> Result<Vec<LockedNode>, Error> {
+		let mut migrated = Vec::new();
+
+		for locked_node in locked_nodes.iter().filter(|ln| ln.is_outdated()) {
+			match self.node_by_id(locked_node.id) {
+				Some(node) if node.created_by == *sign_by.public() => {
+					migrated.push(self.lock(node, sign_by)?);
 				}
+				_ => {}
+			}
+		}
+
+		Ok(migrated)
+	}
+
+	// (re)encrypts and signs a node under its parent (or its share, if detached); useful to
+	// commit a node which has been updated before being inserted, eg when its digest is known
+	pub fn lock(&self, node: &Node, sign_by: &identity::Identity) -> Result<LockedNode, Error> {
+		let entry = self.locked_entry(node);
+
+		if let Some(parent) = self.node_by_id(node.parent_id) {
+			if let Entry::Dir { ref seed, .. } = parent.entry {
+				Ok(Node::encrypt_with_parent_seed(
+					node,
+					entry,
+					seed,
+					sign_by.private(),
+					&self.env,
+					self.format,
+				))
 			} else {
-				// remove from the roots
-				self.roots.retain(|n| n.id != id);
+				Err(Error::BadOperation {
+					id: node.parent_id,
+					reason: "not a dir",
+				})
 			}
+		} else if let Some(seed) = self.cached_seeds.get(&node.id) {
+			Ok(Node::encrypt(
+				node,
+				entry,
+				seed,
+				sign_by.private(),
+				&self.env,
+				self.format,
+			))
+		} else {
+			Err(Error::NoAccess(node.id))
+		}
+	}
+
+	// a dirty dir keeps the listing it was last opened with, since its children aren't known
+	fn locked_entry(&self, node: &Node) -> LockedEntry {
+		match &node.entry {
+			Entry::Dir { seed, .. } if node.dirty => LockedEntry::Dir {
+				seed: seed.clone(),
+				listing: self.listings.get(&node.id).cloned(),
+			},
+			_ => node.locked_entry(),
+		}
+	}
+
+	// re-signs a dir with its current children listed, so that others can tell, if a server omits
+	// or replays any of them; to be saved whenever its children change (see Protocol::save_listing)
+	pub fn list(&mut self, dir_id: Uid, sign_by: &identity::Identity) -> Result<LockedNode, Error> {
+		let revision = self.revisions.get(dir_id) + 1;
+		let dir = node_by_id_mut(&mut self.roots, dir_id).ok_or(Error::NotFound(dir_id))?;
+
+		let Entry::Dir { children, .. } = &dir.entry else {
+			return Err(Error::BadOperation {
+				id: dir_id,
+				reason: "not a dir",
+			});
+		};
+
+		if dir.dirty {
+			return Err(Error::BadOperation {
+				id: dir_id,
+				reason: "not loaded",
+			});
+		}
+
+		let listing = Listing::of(children.iter());
+
+		dir.revision = revision;
+		dir.created_by = sign_by.public().clone();
+
+		_ = self.revisions.observe(dir_id, revision);
+		self.listings.insert(dir_id, listing);
+
+		self.lock(
+			self.node_by_id(dir_id).ok_or(Error::NotFound(dir_id))?,
+			sign_by,
+		)
+	}
+
+	pub fn share_node(&mut self, id: Uid) -> Result<Seed, Error> {
+		if let Some(seed) = self.cached_seeds.get(&id) {
+			Ok(seed.clone())
+		} else if let Some(node) = self.node_by_id(id) {
+			if let Some(parent) = self.node_by_id(node.parent_id) {
+				if let Entry::Dir { ref seed, .. } = parent.entry {
+					let share = seed_from_parent_for_node(seed, id);
+
+					self.cached_seeds.insert(id, share.clone());
+
+					Ok(share)
+				} else {
+					Err(Error::BadOperation {
+						id: node.parent_id,
+						reason: "not a dir",
+					})
+				}
+			} else {
+				// we'll probably never get here
+				Err(Error::NoAccess(id))
+			}
+		} else {
+			Err(Error::NotFound(id))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use identity::Identity;
+
+	use crate::{env::FixedClock, wire};
+
+	use super::*;
+
+	fn is_dir(fs: &FileSystem, id: Uid, name: &str, parent: Uid) -> bool {
+		fs.node_by_id(id).map_or(false, |n| {
+			matches!(n.entry, Entry::Dir { .. }) && n.name == name && n.parent_id == parent
+		})
+	}
+
+	fn is_file(fs: &FileSystem, id: Uid, name: &str, parent: Uid) -> bool {
+		fs.node_by_id(id).map_or(false, |n| {
+			matches!(n.entry, Entry::File { .. }) && n.name == name && n.parent_id == parent
+		})
+	}
+
+	// dirs among nodes re-signed with their current children, as Protocol does once they change
+	fn relisted(fs: &mut FileSystem, nodes: &[LockedNode], by: &Identity) -> Vec<LockedNode> {
+		nodes
+			.iter()
+			.map(|ln| match fs.node_by_id(ln.id) {
+				Some(Node {
+					entry: Entry::Dir { .. },
+					..
+				}) => fs.list(ln.id, by).unwrap(),
+				_ => ln.clone(),
+			})
+			.collect()
+	}
+
+	#[test]
+	fn test_create_mkdir_touch() {
+		let god = Identity::generate(Uid::new(0));
+		let seed = Seed::generate();
+		let (mut fs, _) = FileSystem::new(&seed, &god);
+
+		let _1 = fs.mkdir_mut(Uid::new(ROOT_ID), "1", &god).unwrap();
+		let _1_1 = fs.mkdir_mut(_1.0, "1_1", &god).unwrap();
+		let _1_2 = fs.mkdir_mut(_1.0, "1_2", &god).unwrap();
+		let _1_1_1 = fs.mkdir_mut(_1_1.0, "1_1_1", &god).unwrap();
+		let _1_1_1_atxt = fs.touch_mut(_1_1_1.0, 0, "a", "txt", &god).unwrap();
+
+		assert_eq!(fs.ls_dir(Uid::new(ROOT_ID)).unwrap().len(), 1);
+		assert_eq!(fs.ls_dir(_1.0).unwrap().len(), 2);
+		assert_eq!(fs.ls_dir(_1_1.0).unwrap().len(), 1);
+		assert_eq!(fs.ls_dir(_1_2.0).unwrap().len(), 0);
+		assert_eq!(fs.ls_dir(_1_1_1.0).unwrap().len(), 1);
+		assert_eq!(
+			fs.ls_dir(_1_1_1_atxt.0),
+			Err(Error::BadOperation {
+				id: _1_1_1_atxt.0,
+				reason: "not a dir"
+			})
+		);
+
+		assert!(is_dir(&fs, Uid::new(ROOT_ID), "/", Uid::new(NO_PARENT_ID)));
+		assert!(is_dir(&fs, _1.0, "1", Uid::new(ROOT_ID)));
+		assert!(is_dir(&fs, _1_1.0, "1_1", _1.0));
+		assert!(is_dir(&fs, _1_2.0, "1_2", _1.0));
+		assert!(is_dir(&fs, _1_1_1.0, "1_1_1", _1_1.0));
+		assert!(is_file(&fs, _1_1_1_atxt.0, "a", _1_1_1.0));
+	}
+
+	#[test]
+	fn test_from_locked_nodes_for_root() {
+		let seed = Seed::generate();
+		let god = Identity::generate(Uid::new(0));
+		let (mut fs, root) = FileSystem::new(&seed, &god);
+
+		let _1 = fs.mkdir_mut(Uid::new(ROOT_ID), "1", &god).unwrap();
+		let _1_1 = fs.mkdir_mut(_1.0, "1_1", &god).unwrap();
+		let _1_2 = fs.mkdir_mut(_1.0, "1_2", &god).unwrap();
+		let _1_1_1 = fs.mkdir_mut(_1_1.0, "1_1_1", &god).unwrap();
+		let _1_1_1_atxt = fs.touch_mut(_1_1_1.0, 0, "a", "txt", &god).unwrap();
+
+		let locked_nodes = relisted(
+			&mut fs,
+			&[root, _1.1, _1_1.1, _1_2.1, _1_1_1.1, _1_1_1_atxt.1],
+			&god,
+		);
+
+		let bundles = vec![(Uid::new(ROOT_ID), seed.clone())]
+			.into_iter()
+			.collect();
+		let restored = FileSystem::from_locked_nodes(&locked_nodes, &bundles).unwrap();
+
+		assert_eq!(fs, restored);
+	}
+
+	#[test]
+	fn test_from_locked_nodes_of_mixed_formats() {
+		let seed = Seed::generate();
+		let god = Identity::generate(Uid::new(0));
+		let (mut fs, root) = FileSystem::new(&seed, &god);
+
+		let _1 = fs.mkdir_mut(Uid::new(ROOT_ID), "1", &god).unwrap();
+		fs.set_format(Format::Cbor);
+		let _1_1 = fs.mkdir_mut(_1.0, "1_1", &god).unwrap();
+		let _1_1_atxt = fs.touch_mut(_1_1.0, 10, "a", "txt", &god).unwrap();
+
+		// the locked nodes themselves travel in either format as well
+		let locked_nodes = relisted(&mut fs, &[root, _1.1, _1_1.1, _1_1_atxt.1], &god)
+			.iter()
+			.map(|n| wire::decode(&wire::encode(n, Format::Cbor)).unwrap())
+			.collect::<Vec<LockedNode>>();
+		let bundles = vec![(Uid::new(ROOT_ID), seed.clone())]
+			.into_iter()
+			.collect();
+		let restored = FileSystem::from_locked_nodes(&locked_nodes, &bundles).unwrap();
+
+		assert_eq!(fs, restored);
+	}
+
+	#[test]
+	fn test_migrate() {
+		let seed = Seed::generate();
+		let god = Identity::generate(Uid::new(0));
+		let admin = Identity::generate(Uid::new(1));
+		let (mut fs, root) = FileSystem::new(&seed, &god);
+
+		let _1 = fs.mkdir_mut(Uid::new(ROOT_ID), "1", &god).unwrap();
+		let _2 = fs.mkdir_mut(Uid::new(ROOT_ID), "2", &admin).unwrap();
+		let _1_atxt = fs.touch_mut(_1.0, 10, "a", "txt", &god).unwrap();
+
+		let mut locked_nodes = [
+			relisted(&mut fs, &[root, _1.1], &god),
+			relisted(&mut fs, &[_2.1], &admin),
+			vec![_1_atxt.1],
+		]
+		.concat();
+
+		assert!(fs.migrate(&locked_nodes, &god).unwrap().is_empty());
+
+		locked_nodes.iter_mut().for_each(|ln| ln.version = 0);
+
+		let migrated = fs.migrate(&locked_nodes, &god).unwrap();
+
+		// admin's node can only be re-signed by admin
+		assert_eq!(
+			migrated.iter().map(|ln| ln.id).collect::<Vec<_>>(),
+			vec![Uid::new(ROOT_ID), _1.0, _1_atxt.0]
+		);
+		assert!(migrated.iter().all(|ln| !ln.is_outdated()));
+
+		let bundles: Seeds = vec![(Uid::new(ROOT_ID), seed.clone())]
+			.into_iter()
+			.collect();
+
+		// outdated nodes still decode
+		assert_eq!(
+			fs,
+			FileSystem::from_locked_nodes(&locked_nodes, &bundles).unwrap()
+		);
+
+		locked_nodes[3].version = LockedNode::VERSION + 1;
+
+		let restored = FileSystem::from_locked_nodes(&locked_nodes, &bundles).unwrap();
+
+		assert!(restored.node_by_id(_1.0).is_some());
+		assert!(restored.node_by_id(_1_atxt.0).is_none());
+	}
+
+	#[test]
+	fn test_rejected() {
+		let seed = Seed::generate();
+		let god = Identity::generate(Uid::new(0));
+		let admin = Identity::generate(Uid::new(1));
+		let (mut fs, root) = FileSystem::new(&seed, &god);
+
+		let _1 = fs.mkdir_mut(Uid::new(ROOT_ID), "1", &god).unwrap();
+		let _2 = fs.mkdir_mut(Uid::new(ROOT_ID), "2", &god).unwrap();
+		let _3 = fs.mkdir_mut(Uid::new(ROOT_ID), "3", &god).unwrap();
+		let _1_atxt = fs.touch_mut(_1.0, 10, "a", "txt", &god).unwrap();
+
+		let mut newer = _2.1.clone();
+		newer.version = LockedNode::VERSION + 1;
+
+		let mut misplaced = _3.1.clone();
+		misplaced.content = _1.1.content.clone();
+
+		// signed by someone other than its creator
+		let forged = fs.lock(fs.node_by_id(_1_atxt.0).unwrap(), &admin).unwrap();
+
+		let bundles: Seeds = vec![(Uid::new(ROOT_ID), seed)].into_iter().collect();
+		let listed = relisted(&mut fs, &[root, _1.1], &god);
+		// listed, but rejected, so reported rather than failing the lot
+		let mut restored = FileSystem::from_locked_nodes(
+			&[&listed[..], &[newer, misplaced, forged.clone()]].concat(),
+			&bundles,
+		)
+		.unwrap();
+		let rejected = restored.rejected();
+		let expected = [
+			Error::Locked {
+				id: _2.0,
+				stage: Stage::Version,
+				cause: Cause::default(),
+			},
+			Error::Locked {
+				id: _3.0,
+				stage: Stage::Decrypt,
+				cause: Cause::default(),
+			},
+			Error::Locked {
+				id: _1_atxt.0,
+				stage: Stage::Verify,
+				cause: Cause::default(),
+			},
+		];
+
+		// in no particular order
+		assert_eq!(rejected.len(), expected.len());
+		assert!(expected.iter().all(|e| rejected.contains(e)));
+		// each keeps what it failed with
+		assert!(rejected
+			.iter()
+			.all(|e| std::error::Error::source(e).is_some()));
+		assert!(restored.node_by_id(_1.0).is_some());
+		assert_eq!(
+			Error::NotFound(_2.0).to_string(),
+			format!("node {} not found", _2.0)
+		);
+
+		// reported once however many times it's refetched, and not at all once it's fine
+		for _ in 0..2 {
+			restored
+				.add_or_update_subtree(std::slice::from_ref(&forged), _1.0)
+				.unwrap();
 		}
 
-		return Err(Error::NotFound);
-	}
+		assert_eq!(restored.rejected().len(), expected.len());
+
+		restored.add_or_update_subtree(&[_1_atxt.1], _1.0).unwrap();
+
+		assert_eq!(restored.rejected().len(), expected.len() - 1);
+		assert!(restored.node_by_id(_1_atxt.0).is_some());
+	}
+
+	#[test]
+	fn test_listing() {
+		let seed = Seed::generate();
+		let god = Identity::generate(Uid::new(0));
+		let admin = Identity::generate(Uid::new(1));
+		let (mut fs, _) = FileSystem::new(&seed, &god);
+
+		let _1 = fs.mkdir_mut(Uid::new(ROOT_ID), "1", &god).unwrap();
+		let _1_atxt = fs.touch_mut(_1.0, 10, "a", "txt", &admin).unwrap();
+		let _1_btxt = fs.touch_mut(_1.0, 20, "b", "txt", &god).unwrap();
+		let root = fs.list(Uid::new(ROOT_ID), &god).unwrap();
+		// listed by someone other than the dir's creator
+		let listed = fs.list(_1.0, &admin).unwrap();
+
+		assert_eq!(fs.node_by_id(_1.0).unwrap().created_by, *admin.public());
+		assert_eq!(
+			fs.list(_1_atxt.0, &god),
+			Err(Error::BadOperation {
+				id: _1_atxt.0,
+				reason: "not a dir"
+			})
+		);
+
+		let bundles: Seeds = vec![(Uid::new(ROOT_ID), seed.clone())]
+			.into_iter()
+			.collect();
+		let nodes = [
+			root.clone(),
+			listed.clone(),
+			_1_atxt.1.clone(),
+			_1_btxt.1.clone(),
+		];
+		let restored = FileSystem::from_locked_nodes(&nodes, &bundles).unwrap();
+
+		assert!(restored.rejected().is_empty());
+		assert_eq!(restored, fs);
+
+		// an omitted child leaves its dir dirty, to be refetched, rather than failing the lot
+		let omitted =
+			FileSystem::from_locked_nodes(&[&nodes[..2], &nodes[3..]].concat(), &bundles).unwrap();
+
+		assert_eq!(omitted.rejected(), [Error::Unlisted(_1.0)]);
+		assert!(omitted.node_by_id(_1.0).unwrap().dirty);
+		assert!(omitted.ls_dir(_1.0).unwrap().is_empty());
+
+		// a stale child
+		let mut renamed = fs.node_by_id(_1_atxt.0).unwrap().clone();
+		renamed.name = "renamed".to_string();
+		let renamed = fs.lock(&renamed, &admin).unwrap();
+		let stale = FileSystem::from_locked_nodes(
+			&[&nodes[..2], std::slice::from_ref(&renamed), &nodes[3..]].concat(),
+			&bundles,
+		)
+		.unwrap();
+
+		assert_eq!(stale.rejected(), [Error::Unlisted(_1.0)]);
+		assert!(stale.node_by_id(_1.0).unwrap().dirty);
+		assert!(stale.node_by_id(_1_atxt.0).is_none());
+
+		// a child not listed yet is left out
+		let _1_ctxt = fs.touch_mut(_1.0, 30, "c", "txt", &god).unwrap();
+		let mut restored = FileSystem::from_locked_nodes(
+			&[&nodes[..], std::slice::from_ref(&_1_ctxt.1)].concat(),
+			&bundles,
+		)
+		.unwrap();
+
+		assert_eq!(
+			restored.rejected(),
+			[Error::Locked {
+				id: _1_ctxt.0,
+				stage: Stage::Listing,
+				cause: Cause::default(),
+			}]
+		);
+		assert!(restored.node_by_id(_1_ctxt.0).is_none());
+
+		// refetching fails just the same, leaving the tree as is
+		assert_eq!(
+			restored.add_or_update_subtree(&[renamed, _1_btxt.1.clone()], _1.0),
+			Err(Error::Unlisted(_1.0))
+		);
+		assert_eq!(restored.ls_dir(_1.0).unwrap().len(), 2);
+
+		// listing c, but with its children yet to be fetched
+		let mut _1_v3 = fs.list(_1.0, &god).unwrap();
+		_1_v3.dirty = true;
+
+		restored
+			.add_or_update_subtree(&[_1_v3], Uid::new(ROOT_ID))
+			.unwrap();
+
+		assert!(restored.node_by_id(_1.0).unwrap().dirty);
+
+		restored
+			.add_or_update_subtree(&[_1_atxt.1, _1_btxt.1, _1_ctxt.1], _1.0)
+			.unwrap();
+
+		assert!(restored.rejected().is_empty());
+		assert_eq!(restored, fs);
+
+		// dirs with no listing, eg legacy ones, are not verified
+		let legacy = match fs.node_by_id(Uid::new(ROOT_ID)).unwrap() {
+			node @ Node {
+				entry: Entry::Dir {
+					seed: root_seed, ..
+				},
+				..
+			} => Node::encrypt(
+				node,
+				LockedEntry::Dir {
+					seed: root_seed.clone(),
+					listing: None,
+				},
+				&seed,
+				god.private(),
+				&fs.env,
+				Format::Json,
+			),
+			_ => unreachable!(),
+		};
+		let restored = FileSystem::from_locked_nodes(&[legacy], &bundles).unwrap();
+
+		assert!(restored.rejected().is_empty());
+		assert!(restored.ls_root().is_empty());
 
-	// touch and immediately apply its transaction
-	// TODO: the backend should create a PendingUpload(id); when uploaded /save/id should be called
-	pub fn touch_mut(
-		&mut self,
-		parent_id: Uid,
-		size: u32,
-		name: &str,
-		ext: &str,
-		owner: &identity::Identity,
-	) -> Result<(Uid, LockedNode), Error> {
-		let NewNodeReq { node, locked_node } = self.touch(parent_id, size, name, ext, owner)?;
-		let id = self.insert_node(node)?;
+		// but listings with more revisions than children are rejected
+		let extra = match fs.node_by_id(Uid::new(ROOT_ID)).unwrap() {
+			node @ Node {
+				entry: Entry::Dir {
+					seed: root_seed,
+					children,
+				},
+				..
+			} => {
+				let mut listing = Listing::of(children.iter());
+				listing.revisions.push(0);
+
+				Node::encrypt(
+					node,
+					LockedEntry::Dir {
+						seed: root_seed.clone(),
+						listing: Some(listing),
+					},
+					&seed,
+					god.private(),
+					&fs.env,
+					Format::Json,
+				)
+			}
+			_ => unreachable!(),
+		};
+		let restored = FileSystem::from_locked_nodes(&[extra], &bundles).unwrap();
 
-		Ok((id, locked_node))
+		assert_eq!(
+			restored.rejected(),
+			[Error::Locked {
+				id: Uid::new(ROOT_ID),
+				stage: Stage::Decode,
+				cause: Cause::default(),
+			}]
+		);
 	}
 
-	pub fn touch(
-		&self,
-		parent_id: Uid,
-		size: u32,
-		name: &str,
-		ext: &str,
-		owner: &identity::Identity,
-	) -> Result<NewNodeReq, Error> {
-		if let Some(node) = self.node_by_id(parent_id) {
-			if let Entry::Dir {
-				children: _,
-				seed: ref parent_seed,
-			} = node.entry
-			{
-				let id = Uid::generate();
-				let new_node = Node {
-					id,
-					parent_id,
-					created_at: now(),
-					name: name.to_string(),
-					entry: Entry::File {
-						info: FileInfo {
-							size,
-							key_iv: Aes::new(),
-							ext: ext.to_string(),
-						},
-					},
-					dirty: false,
-					created_by: owner.public().clone(),
-				};
-				let locked_node =
-					Node::encrypt_with_parent_seed(&new_node, parent_seed, owner.private());
+	#[test]
+	fn test_replayed_subdir() {
+		let seed = Seed::generate();
+		let god = Identity::generate(Uid::new(0));
+		let (mut fs, root) = FileSystem::new(&seed, &god);
 
-				Ok(NewNodeReq {
-					node: new_node,
-					locked_node,
-				})
-			} else {
-				Err(Error::BadOperation)
-			}
-		} else {
-			Err(Error::NoAccess)
-		}
-	}
+		let _1 = fs.mkdir_mut(Uid::new(ROOT_ID), "1", &god).unwrap();
+		let old = relisted(&mut fs, &[root, _1.1], &god);
+		let _1_atxt = fs.touch_mut(_1.0, 10, "a", "txt", &god).unwrap();
+		// 1 lists its new child, then root lists 1's newer revision
+		let _1_listed = fs.list(_1.0, &god).unwrap();
+		let mut root_listed = fs.list(Uid::new(ROOT_ID), &god).unwrap();
+
+		let bundles: Seeds = vec![(Uid::new(ROOT_ID), seed)].into_iter().collect();
+		let restored = FileSystem::from_locked_nodes(
+			&[root_listed.clone(), _1_listed, _1_atxt.1.clone()],
+			&bundles,
+		)
+		.unwrap();
 
-	pub fn share_node(&mut self, id: Uid) -> Result<Seed, Error> {
-		if let Some(seed) = self.cached_seeds.get(&id) {
-			Ok(seed.clone())
-		} else if let Some(node) = self.node_by_id(id) {
-			if let Some(parent) = self.node_by_id(node.parent_id) {
-				if let Entry::Dir { ref seed, .. } = parent.entry {
-					let share = seed_from_parent_for_node(seed, id);
+		assert!(restored.rejected().is_empty());
 
-					self.cached_seeds.insert(id, share.clone());
+		// 1 as it was before its child was added hashes the same, but is older than listed
+		let rolled_back = Error::RolledBack {
+			id: _1.0,
+			revision: fs.revisions().get(_1.0) - 1,
+			seen: fs.revisions().get(_1.0),
+		};
 
-					Ok(share)
-				} else {
-					Err(Error::BadOperation)
-				}
-			} else {
-				// we'll probably never get here
-				Err(Error::NoAccess)
-			}
-		} else {
-			Err(Error::NotFound)
-		}
-	}
-}
+		let replayed = FileSystem::from_locked_nodes(
+			&[root_listed.clone(), old[1].clone(), _1_atxt.1.clone()],
+			&bundles,
+		)
+		.unwrap();
 
-#[cfg(test)]
-mod tests {
-	use identity::Identity;
+		assert!(replayed.rejected().contains(&rolled_back));
+		assert!(replayed.node_by_id(Uid::new(ROOT_ID)).unwrap().dirty);
+		assert!(replayed.node_by_id(_1.0).is_none());
 
-	use super::*;
+		// and so it is, once fetched
+		root_listed.dirty = true;
 
-	fn is_dir(fs: &FileSystem, id: Uid, name: &str, parent: Uid) -> bool {
-		fs.node_by_id(id).map_or(false, |n| {
-			matches!(n.entry, Entry::Dir { .. }) && n.name == name && n.parent_id == parent
-		})
-	}
+		let mut restored = FileSystem::from_locked_nodes(&[root_listed], &bundles).unwrap();
 
-	fn is_file(fs: &FileSystem, id: Uid, name: &str, parent: Uid) -> bool {
-		fs.node_by_id(id).map_or(false, |n| {
-			matches!(n.entry, Entry::File { .. }) && n.name == name && n.parent_id == parent
-		})
+		assert_eq!(
+			restored.add_or_update_subtree(&[old[1].clone()], Uid::new(ROOT_ID)),
+			Err(rolled_back)
+		);
+		assert!(restored.node_by_id(_1.0).is_none());
 	}
 
 	#[test]
-	fn test_create_mkdir_touch() {
-		let god = Identity::generate(Uid::new(0));
+	fn test_apply_changes() {
 		let seed = Seed::generate();
-		let (mut fs, _) = FileSystem::new(&seed, &god);
+		let god = Identity::generate(Uid::new(0));
+		let (mut fs, root) = FileSystem::new(&seed, &god);
+		let bundles: Seeds = vec![(Uid::new(ROOT_ID), seed)].into_iter().collect();
+		// another device, up to date as of now
+		let mut other = FileSystem::from_locked_nodes(&[root], &bundles).unwrap();
 
 		let _1 = fs.mkdir_mut(Uid::new(ROOT_ID), "1", &god).unwrap();
-		let _1_1 = fs.mkdir_mut(_1.0, "1_1", &god).unwrap();
-		let _1_2 = fs.mkdir_mut(_1.0, "1_2", &god).unwrap();
-		let _1_1_1 = fs.mkdir_mut(_1_1.0, "1_1_1", &god).unwrap();
-		let _1_1_1_atxt = fs.touch_mut(_1_1_1.0, 0, "a", "txt", &god).unwrap();
+		let _2 = fs.mkdir_mut(Uid::new(ROOT_ID), "2", &god).unwrap();
+		let _1_atxt = fs.touch_mut(_1.0, 10, "a", "txt", &god).unwrap();
+
+		// children may come before their parents
+		other
+			.apply_changes(&[
+				Change::Saved(_1_atxt.1.clone()),
+				Change::Saved(_1.1),
+				Change::Saved(fs.list(_1.0, &god).unwrap()),
+				Change::Saved(_2.1),
+				Change::Saved(fs.list(Uid::new(ROOT_ID), &god).unwrap()),
+			])
+			.unwrap();
 
-		assert_eq!(fs.ls_dir(Uid::new(ROOT_ID)).unwrap().len(), 1);
-		assert_eq!(fs.ls_dir(_1.0).unwrap().len(), 2);
-		assert_eq!(fs.ls_dir(_1_1.0).unwrap().len(), 1);
-		assert_eq!(fs.ls_dir(_1_2.0).unwrap().len(), 0);
-		assert_eq!(fs.ls_dir(_1_1_1.0).unwrap().len(), 1);
-		assert_eq!(fs.ls_dir(_1_1_1_atxt.0), Err(Error::BadOperation));
+		assert!(other.rejected().is_empty());
+		assert_eq!(other.ls_root().len(), 2);
+		assert_eq!(other.ls_dir(_1.0).unwrap().len(), 1);
+
+		// move 1/a.txt to 2/b.txt
+		let mut moved = fs.delete_node(_1_atxt.0).unwrap();
+		moved.parent_id = _2.0;
+		moved.name = "b".to_string();
+		moved.revision += 1;
+		let locked = fs.lock(&moved, &god).unwrap();
+		fs.insert_node(moved).unwrap();
+
+		other
+			.apply_changes(&[
+				Change::Saved(locked),
+				Change::Saved(fs.list(_1.0, &god).unwrap()),
+				Change::Saved(fs.list(_2.0, &god).unwrap()),
+			])
+			.unwrap();
 
-		assert!(is_dir(&fs, Uid::new(ROOT_ID), "/", Uid::new(NO_PARENT_ID)));
-		assert!(is_dir(&fs, _1.0, "1", Uid::new(ROOT_ID)));
-		assert!(is_dir(&fs, _1_1.0, "1_1", _1.0));
-		assert!(is_dir(&fs, _1_2.0, "1_2", _1.0));
-		assert!(is_dir(&fs, _1_1_1.0, "1_1_1", _1_1.0));
-		assert!(is_file(&fs, _1_1_1_atxt.0, "a", _1_1_1.0));
+		assert!(other.rejected().is_empty());
+		assert!(other.ls_dir(_1.0).unwrap().is_empty());
+		assert_eq!(other.ls_dir(_2.0).unwrap()[0].name, "b");
+		assert_eq!(other.node_by_id(_1_atxt.0), fs.node_by_id(_1_atxt.0));
+
+		// an older revision is reported and ignored
+		other.apply_changes(&[Change::Saved(_1_atxt.1)]).unwrap();
+
+		assert_eq!(
+			other.rejected(),
+			[Error::RolledBack {
+				id: _1_atxt.0,
+				revision: 1,
+				seen: 2
+			}]
+		);
+		assert_eq!(other.ls_dir(_2.0).unwrap()[0].name, "b");
+
+		// a child not listed yet is left out, until its dir is listed again
+		let _2_ctxt = fs.touch_mut(_2.0, 10, "c", "txt", &god).unwrap();
+
+		other
+			.apply_changes(&[Change::Saved(_2_ctxt.1.clone())])
+			.unwrap();
+
+		assert!(other.rejected().contains(&Error::Locked {
+			id: _2_ctxt.0,
+			stage: Stage::Listing,
+			cause: Cause::default(),
+		}));
+		assert_eq!(other.ls_dir(_2.0).unwrap().len(), 1);
+
+		other
+			.apply_changes(&[
+				Change::Saved(_2_ctxt.1),
+				Change::Saved(fs.list(_2.0, &god).unwrap()),
+			])
+			.unwrap();
+
+		assert_eq!(other.rejected().len(), 1);
+		assert_eq!(other.ls_dir(_2.0).unwrap().len(), 2);
+
+		// a listed child gone missing unloads its dir, so that it's refetched
+		other.take_events();
+
+		assert_eq!(
+			other.apply_changes(&[Change::Deleted(_1_atxt.0)]),
+			Err(Error::Unlisted(_2.0))
+		);
+		assert!(other.node_by_id(_2.0).unwrap().dirty);
+		assert!(other.node_by_id(_2_ctxt.0).is_none());
+		assert!(other.take_events().contains(&Event::Dirty(_2.0)));
+
+		fs.delete_node(_1.0).unwrap();
+		other
+			.apply_changes(&[
+				Change::Deleted(_1.0),
+				Change::Saved(fs.list(Uid::new(ROOT_ID), &god).unwrap()),
+			])
+			.unwrap();
+
+		assert!(other.rejected().is_empty());
+		assert_eq!(other.ls_root().len(), 1);
+		assert_eq!(other.node_by_id(_1.0), None);
 	}
 
 	#[test]
-	fn test_from_locked_nodes_for_root() {
+	fn test_rollback() {
 		let seed = Seed::generate();
 		let god = Identity::generate(Uid::new(0));
-		let (mut fs, root) = FileSystem::new(&seed, &god);
+		let (mut fs, _) = FileSystem::new(&seed, &god);
 
 		let _1 = fs.mkdir_mut(Uid::new(ROOT_ID), "1", &god).unwrap();
-		let _1_1 = fs.mkdir_mut(_1.0, "1_1", &god).unwrap();
-		let _1_2 = fs.mkdir_mut(_1.0, "1_2", &god).unwrap();
-		let _1_1_1 = fs.mkdir_mut(_1_1.0, "1_1_1", &god).unwrap();
-		let _1_1_1_atxt = fs.touch_mut(_1_1_1.0, 0, "a", "txt", &god).unwrap();
+		let _1_atxt = fs.touch_mut(_1.0, 10, "a", "txt", &god).unwrap();
+		let root = fs.list(Uid::new(ROOT_ID), &god).unwrap();
+		let _1_v1 = fs.list(_1.0, &god).unwrap();
+		let mut renamed = fs.node_by_id(_1_atxt.0).unwrap().clone();
+		renamed.name = "renamed".to_string();
+		renamed.revision += 1;
+		let locked = fs.lock(&renamed, &god).unwrap();
+		fs.update_node(renamed).unwrap();
+		let _1_v2 = fs.list(_1.0, &god).unwrap();
+
+		let bundles: Seeds = vec![(Uid::new(ROOT_ID), seed)].into_iter().collect();
+		let mut restored =
+			FileSystem::from_locked_nodes(&[root.clone(), _1_v2, locked], &bundles).unwrap();
+
+		assert_eq!(restored.revisions().get(_1_atxt.0), 2);
+
+		restored
+			.add_or_update_subtree(std::slice::from_ref(&_1_atxt.1), _1.0)
+			.unwrap();
 
-		let locked_nodes = vec![root, _1.1, _1_1.1, _1_2.1, _1_1_1.1, _1_1_1_atxt.1];
+		let rolled_back = Error::RolledBack {
+			id: _1_atxt.0,
+			revision: 1,
+			seen: 2,
+		};
 
-		let bundles = vec![(Uid::new(ROOT_ID), seed.clone())]
-			.into_iter()
-			.collect();
-		let restored = FileSystem::from_locked_nodes(&locked_nodes, &bundles);
+		// reported, but the last good version is kept
+		assert_eq!(restored.rejected(), std::slice::from_ref(&rolled_back));
+		assert_eq!(restored.node_by_id(_1_atxt.0).unwrap().name, "renamed");
+		assert_eq!(restored.revisions().get(_1_atxt.0), 2);
 
-		assert_eq!(fs, restored);
+		// across sessions
+		let mut relaunched =
+			FileSystem::from_locked_nodes(&[root, _1_v1.clone(), _1_atxt.1], &bundles).unwrap();
+
+		assert!(relaunched.rejected().is_empty());
+
+		relaunched.track_revisions(restored.revisions());
+
+		assert!(relaunched.rejected().contains(&rolled_back));
+		assert_eq!(relaunched.revisions().get(_1_atxt.0), 2);
+
+		// dirs are revisioned as well, listings included
+		restored.apply_changes(&[Change::Saved(_1_v1)]).unwrap();
+
+		assert!(restored.rejected().contains(&Error::RolledBack {
+			id: _1.0,
+			revision: 2,
+			seen: 3
+		}));
 	}
 
 	fn eval_share(fs: &mut FileSystem, id: Uid, parent_id: Uid) -> bool {
@@ -939,10 +2893,14 @@ mod tests {
 		let _1_1_1_atxt = fs.touch_mut(_1_1_1.0, 0, "a", "txt", &god).unwrap();
 
 		let share = fs.share_node(_1_1_1_atxt.0).unwrap();
-		let locked_nodes = vec![root, _1_1_1_atxt.1, _1.1, _1_1_1.1, _1_2.1, _1_1.1];
+		let locked_nodes = relisted(
+			&mut fs,
+			&[root, _1_1_1_atxt.1, _1.1, _1_1_1.1, _1_2.1, _1_1.1],
+			&god,
+		);
 		let bundles = vec![(_1_1_1_atxt.0, share)].into_iter().collect();
 
-		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles);
+		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles).unwrap();
 
 		assert!(is_file(&fs_partial, _1_1_1_atxt.0, "a", _1_1_1.0));
 		assert_eq!(
@@ -969,16 +2927,20 @@ mod tests {
 		let _1_1_1_b_share = fs.share_node(_1_1_1_btxt.0).unwrap();
 		let _1_1_a_share = fs.share_node(_1_1_ctxt.0).unwrap();
 
-		let locked_nodes = vec![
-			root,
-			_1.1,
-			_1_2.1,
-			_1_1.1,
-			_1_1_1_atxt.1,
-			_1_1_1.1,
-			_1_1_1_btxt.1,
-			_1_1_ctxt.1,
-		];
+		let locked_nodes = relisted(
+			&mut fs,
+			&[
+				root,
+				_1.1,
+				_1_2.1,
+				_1_1.1,
+				_1_1_1_atxt.1,
+				_1_1_1.1,
+				_1_1_1_btxt.1,
+				_1_1_ctxt.1,
+			],
+			&god,
+		);
 		let bundles = vec![
 			(_1_1_1_atxt.0, _1_1_1_a_share),
 			(_1_1_ctxt.0, _1_1_a_share),
@@ -987,7 +2949,7 @@ mod tests {
 		.into_iter()
 		.collect();
 
-		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles);
+		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles).unwrap();
 
 		assert!(is_file(&fs_partial, _1_1_1_atxt.0, "a", _1_1_1.0));
 		assert!(is_file(&fs_partial, _1_1_1_btxt.0, "b", _1_1_1.0));
@@ -1017,21 +2979,25 @@ mod tests {
 		let _1_1_1_share = fs.share_node(_1_1_1.0).unwrap();
 		let _1_2_share = fs.share_node(_1_2.0).unwrap();
 
-		let locked_nodes = vec![
-			root,
-			_1.1,
-			_1_2.1,
-			_1_1.1,
-			_1_1_1_atxt.1,
-			_1_1_1.1,
-			_1_1_1_btxt.1,
-			_1_1_atxt.1,
-		];
+		let locked_nodes = relisted(
+			&mut fs,
+			&[
+				root,
+				_1.1,
+				_1_2.1,
+				_1_1.1,
+				_1_1_1_atxt.1,
+				_1_1_1.1,
+				_1_1_1_btxt.1,
+				_1_1_atxt.1,
+			],
+			&god,
+		);
 		let bundles = vec![(_1_1_1.0, _1_1_1_share), (_1_2.0, _1_2_share)]
 			.into_iter()
 			.collect();
 
-		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles);
+		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles).unwrap();
 
 		assert!(is_dir(&fs_partial, _1_1_1.0, "1_1_1", _1_1.0));
 		assert!(is_dir(&fs_partial, _1_2.0, "1_2", _1.0));
@@ -1061,17 +3027,21 @@ mod tests {
 		let _1_1_1_b_share = fs.share_node(_1_1_1_btxt.0).unwrap();
 		let _1_2_share = fs.share_node(_1_2.0).unwrap();
 
-		let locked_nodes = vec![
-			root,
-			_1.1,
-			_1_2.1,
-			_1_1.1,
-			_1_1_1_1.1,
-			_1_1_1_atxt.1,
-			_1_1_1.1,
-			_1_1_1_btxt.1,
-			_1_1_atxt.1,
-		];
+		let locked_nodes = relisted(
+			&mut fs,
+			&[
+				root,
+				_1.1,
+				_1_2.1,
+				_1_1.1,
+				_1_1_1_1.1,
+				_1_1_1_atxt.1,
+				_1_1_1.1,
+				_1_1_1_btxt.1,
+				_1_1_atxt.1,
+			],
+			&god,
+		);
 		let bundles = vec![
 			(_1_1_1_atxt.0, _1_1_1_a_share),
 			(_1_1_1_btxt.0, _1_1_1_b_share),
@@ -1080,7 +3050,7 @@ mod tests {
 		.into_iter()
 		.collect();
 
-		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles);
+		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles).unwrap();
 
 		assert!(is_dir(&fs_partial, _1_2.0, "1_2", _1.0));
 		assert!(is_file(&fs_partial, _1_1_1_atxt.0, "a", _1_1_1.0));
@@ -1112,23 +3082,33 @@ mod tests {
 		let _1_1_1_b_share = fs.share_node(_1_1_1_btxt.0).unwrap();
 		let _1_2_share = fs.share_node(_1_2.0).unwrap();
 
-		assert_eq!(fs.share_node(Uid::new(9999999)), Err(Error::NotFound));
+		assert_eq!(
+			fs.share_node(Uid::new(9999999)),
+			Err(Error::NotFound(Uid::new(9999999)))
+		);
 		assert_eq!(
 			fs.mkdir_mut(_1_1_1_atxt.0, "bad", &god),
-			Err(Error::BadOperation)
+			Err(Error::BadOperation {
+				id: _1_1_1_atxt.0,
+				reason: "not a dir"
+			})
 		);
 
-		let locked_nodes = vec![
-			root,
-			_1.1,
-			_1_2.1,
-			_1_1.1,
-			_1_1_1_1.1,
-			_1_1_1_atxt.1,
-			_1_1_1.1,
-			_1_1_1_btxt.1,
-			_1_1_atxt.1,
-		];
+		let locked_nodes = relisted(
+			&mut fs,
+			&[
+				root,
+				_1.1,
+				_1_2.1,
+				_1_1.1,
+				_1_1_1_1.1,
+				_1_1_1_atxt.1,
+				_1_1_1.1,
+				_1_1_1_btxt.1,
+				_1_1_atxt.1,
+			],
+			&god,
+		);
 		let bundles = vec![
 			(_1_1_1_atxt.0, _1_1_1_a_share.clone()),
 			(_1_1_1_btxt.0, _1_1_1_b_share.clone()),
@@ -1137,7 +3117,7 @@ mod tests {
 		.into_iter()
 		.collect();
 
-		let mut fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles);
+		let mut fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles).unwrap();
 
 		assert!(is_dir(&fs_partial, _1_2.0, "1_2", _1.0));
 		assert!(is_file(&fs_partial, _1_1_1_atxt.0, "a", _1_1_1.0));
@@ -1150,7 +3130,7 @@ mod tests {
 		.contains(&id)));
 
 		// _1 is not in the hierarchy
-		assert_eq!(fs_partial.share_node(_1.0), Err(Error::NotFound));
+		assert_eq!(fs_partial.share_node(_1.0), Err(Error::NotFound(_1.0)));
 		// these are is in the cache, so can be shared
 		assert_eq!(fs_partial.share_node(_1_2.0), Ok(_1_2_share));
 		assert_eq!(fs_partial.share_node(_1_1_1_atxt.0), Ok(_1_1_1_a_share));
@@ -1257,6 +3237,92 @@ mod tests {
 		assert_eq!(fs_copy.node_by_id(_1_1_1_1.0), None);
 	}
 
+	#[test]
+	fn test_events() {
+		let god = Identity::generate(Uid::new(0));
+		let (mut fs, _) = FileSystem::new(&Seed::generate(), &god);
+		let _1 = fs.mkdir_mut(Uid::new(ROOT_ID), "1", &god).unwrap();
+		let _1_atxt = fs.touch_mut(_1.0, 0, "a", "txt", &god).unwrap();
+		let _1_2 = fs.mkdir_mut(_1.0, "2", &god).unwrap();
+
+		assert_eq!(
+			fs.take_events(),
+			vec![
+				Event::Added {
+					id: _1.0,
+					parent_id: Uid::new(ROOT_ID)
+				},
+				Event::Added {
+					id: _1_atxt.0,
+					parent_id: _1.0
+				},
+				Event::Added {
+					id: _1_2.0,
+					parent_id: _1.0
+				},
+			]
+		);
+		assert!(fs.take_events().is_empty());
+
+		let mut fs_copy = fs.clone();
+
+		// rename a, replace 2 with 3 elsewhere
+		let mut atxt = fs.node_by_id(_1_atxt.0).unwrap().clone();
+		atxt.name = "b".to_string();
+		atxt.revision = 2;
+
+		let atxt_v2 = fs.lock(&atxt, &god).unwrap();
+		let _1_3 = fs.mkdir_mut(_1.0, "3", &god).unwrap();
+
+		fs.update_node(atxt).unwrap();
+		fs.delete_node(_1_2.0).unwrap();
+
+		assert_eq!(
+			fs.take_events(),
+			vec![
+				Event::Added {
+					id: _1_3.0,
+					parent_id: _1.0
+				},
+				Event::Renamed {
+					id: _1_atxt.0,
+					name: "b".to_string()
+				},
+				Event::Removed {
+					id: _1_2.0,
+					parent_id: _1.0
+				},
+			]
+		);
+
+		fs_copy
+			.add_or_update_subtree(&[atxt_v2, _1_3.1], _1.0)
+			.unwrap();
+
+		assert_eq!(
+			fs_copy.take_events(),
+			vec![
+				Event::Renamed {
+					id: _1_atxt.0,
+					name: "b".to_string()
+				},
+				Event::Added {
+					id: _1_3.0,
+					parent_id: _1.0
+				},
+				Event::Removed {
+					id: _1_2.0,
+					parent_id: _1.0
+				},
+			]
+		);
+
+		fs_copy.mark_dirty(_1.0).unwrap();
+		fs_copy.mark_dirty(_1.0).unwrap();
+
+		assert_eq!(fs_copy.take_events(), vec![Event::Dirty(_1.0)]);
+	}
+
 	#[test]
 	fn test_share_parents_and_children() {
 		let seed = Seed::generate();
@@ -1277,17 +3343,21 @@ mod tests {
 		let _1_2_share = fs.share_node(_1_2.0).unwrap();
 		let _1_share = fs.share_node(_1.0).unwrap();
 
-		let locked_nodes = vec![
-			root,
-			_1.1,
-			_1_2.1,
-			_1_1.1,
-			_1_1_1_1.1,
-			_1_1_1_atxt.1,
-			_1_1_1.1,
-			_1_1_1_btxt.1,
-			_1_1_atxt.1,
-		];
+		let locked_nodes = relisted(
+			&mut fs,
+			&[
+				root,
+				_1.1,
+				_1_2.1,
+				_1_1.1,
+				_1_1_1_1.1,
+				_1_1_1_atxt.1,
+				_1_1_1.1,
+				_1_1_1_btxt.1,
+				_1_1_atxt.1,
+			],
+			&god,
+		);
 		let bundles = vec![
 			(_1_1_1_atxt.0, _1_1_1_a_share),
 			(_1_1_1_btxt.0, _1_1_1_b_share),
@@ -1297,7 +3367,7 @@ mod tests {
 		.into_iter()
 		.collect();
 
-		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles);
+		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles).unwrap();
 
 		assert!(is_dir(&fs_partial, _1.0, "1", Uid::new(ROOT_ID)));
 		assert!(is_dir(&fs_partial, _1_2.0, "1_2", _1.0));
@@ -1328,17 +3398,21 @@ mod tests {
 		let _1_1_1_b_share = fs.share_node(_1_1_1_btxt.0).unwrap();
 		let root_share = fs.share_node(Uid::new(ROOT_ID)).unwrap();
 
-		let locked_nodes = vec![
-			root,
-			_1.1,
-			_1_2.1,
-			_1_1.1,
-			_1_1_1_1.1,
-			_1_1_1_atxt.1,
-			_1_1_1.1,
-			_1_1_1_btxt.1,
-			_1_1_atxt.1,
-		];
+		let locked_nodes = relisted(
+			&mut fs,
+			&[
+				root,
+				_1.1,
+				_1_2.1,
+				_1_1.1,
+				_1_1_1_1.1,
+				_1_1_1_atxt.1,
+				_1_1_1.1,
+				_1_1_1_btxt.1,
+				_1_1_atxt.1,
+			],
+			&god,
+		);
 		let bundles = vec![
 			(_1_1_1_atxt.0, _1_1_1_a_share),
 			(_1_1_1_btxt.0, _1_1_1_b_share),
@@ -1347,7 +3421,7 @@ mod tests {
 		.into_iter()
 		.collect();
 
-		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles);
+		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles).unwrap();
 
 		assert!(is_dir(
 			&fs_partial,
@@ -1386,18 +3460,22 @@ mod tests {
 		let _1_share = fs.share_node(_1.0).unwrap();
 		let root_share = fs.share_node(Uid::new(ROOT_ID)).unwrap();
 
-		let locked_nodes = vec![
-			root,
-			_1.1,
-			_2.1,
-			_1_2.1,
-			_1_1.1,
-			_1_1_1_1.1,
-			_1_1_1_atxt.1,
-			_1_1_1.1,
-			_1_1_1_btxt.1,
-			_1_1_atxt.1,
-		];
+		let locked_nodes = relisted(
+			&mut fs,
+			&[
+				root,
+				_1.1,
+				_2.1,
+				_1_2.1,
+				_1_1.1,
+				_1_1_1_1.1,
+				_1_1_1_atxt.1,
+				_1_1_1.1,
+				_1_1_1_btxt.1,
+				_1_1_atxt.1,
+			],
+			&god,
+		);
 		let bundles = vec![
 			(_1_1_1_atxt.0, _1_1_1_a_share),
 			(_1_1_1_btxt.0, _1_1_1_b_share),
@@ -1408,7 +3486,7 @@ mod tests {
 		.into_iter()
 		.collect();
 
-		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles);
+		let fs_partial = FileSystem::from_locked_nodes(&locked_nodes, &bundles).unwrap();
 
 		assert!(is_dir(
 			&fs_partial,
@@ -1430,11 +3508,184 @@ mod tests {
 			.all(|id| [_1.0, _2.0].contains(&id)));
 	}
 
+	#[test]
+	fn test_decode_legacy_file_info() {
+		let aes = Aes::new();
+		let legacy = format!(
+			r#"{{"size":4294967295,"key_iv":{},"ext":"mp4"}}"#,
+			serde_json::to_string(&aes).unwrap()
+		);
+		let info: FileInfo = serde_json::from_str(&legacy).unwrap();
+
+		assert_eq!(info.size, u32::MAX as u64);
+		assert_eq!(info.chunk_size, None);
+		assert_eq!(info.chunk_size(), CHUNK_SIZE);
+		assert_eq!(info.digest, None);
+		assert_eq!(info.key_iv, aes);
+	}
+
+	#[test]
+	fn test_entry_hash_covers_digest() {
+		let info = FileInfo {
+			size: 10,
+			key_iv: Aes::new(),
+			ext: "txt".to_string(),
+			chunk_size: Some(CHUNK_SIZE),
+			digest: None,
+		};
+		let legacy = LockedEntry::File { info: info.clone() }.hash();
+
+		// legacy entries are hashed as before
+		assert_eq!(
+			legacy,
+			hmac::Digest(
+				Sha256::digest([info.key_iv.as_bytes().as_slice(), info.ext.as_bytes()].concat())
+					.into()
+			)
+		);
+
+		let with_digest = FileInfo {
+			digest: Some(hmac::Digest([1u8; hmac::Digest::SIZE])),
+			..info.clone()
+		};
+		let hash = LockedEntry::File {
+			info: with_digest.clone(),
+		}
+		.hash();
+
+		assert_ne!(hash, legacy);
+		assert_ne!(
+			hash,
+			LockedEntry::File {
+				info: FileInfo {
+					digest: Some(hmac::Digest([2u8; hmac::Digest::SIZE])),
+					..with_digest.clone()
+				}
+			}
+			.hash()
+		);
+		assert_ne!(
+			hash,
+			LockedEntry::File {
+				info: FileInfo {
+					size: 11,
+					..with_digest
+				}
+			}
+			.hash()
+		);
+	}
+
+	#[test]
+	fn test_file_info_beyond_u32() {
+		let info = FileInfo {
+			size: 5 * 1024 * 1024 * 1024 + 1,
+			key_iv: Aes::new(),
+			ext: "mkv".to_string(),
+			chunk_size: Some(CHUNK_SIZE),
+			digest: None,
+		};
+		let serialized = serde_json::to_string(&info).unwrap();
+
+		assert_eq!(serde_json::from_str::<FileInfo>(&serialized).unwrap(), info);
+		assert_eq!(info.chunk_count(), 5 * 1024 + 1);
+		assert_eq!(
+			FileInfo {
+				size: 0,
+				..info.clone()
+			}
+			.chunk_count(),
+			1
+		);
+		assert_eq!(
+			FileInfo {
+				size: CHUNK_SIZE as u64,
+				..info
+			}
+			.chunk_count(),
+			1
+		);
+	}
+
+	#[test]
+	fn test_zero_chunk_size() {
+		let seed = Seed::generate();
+		let god = Identity::generate(Uid::new(0));
+		let (mut fs, root) = FileSystem::new(&seed, &god);
+
+		let atxt = fs
+			.touch_mut(Uid::new(ROOT_ID), 10, "a", "txt", &god)
+			.unwrap();
+		let mut zero = fs.node_by_id(atxt.0).unwrap().clone();
+
+		if let Entry::File { ref mut info } = zero.entry {
+			info.chunk_size = Some(0);
+		}
+
+		let locked = fs.lock(&zero, &god).unwrap();
+
+		fs.update_node(zero).unwrap();
+
+		let bundles: Seeds = vec![(Uid::new(ROOT_ID), seed)].into_iter().collect();
+		let listed = relisted(&mut fs, &[root], &god);
+		let restored =
+			FileSystem::from_locked_nodes(&[&listed[..], &[locked]].concat(), &bundles).unwrap();
+
+		assert_eq!(
+			restored.rejected(),
+			[Error::Locked {
+				id: atxt.0,
+				stage: Stage::Decode,
+				cause: Cause::default(),
+			}]
+		);
+		assert!(restored.node_by_id(atxt.0).is_none());
+	}
+
+	#[test]
+	fn test_seeded_env_is_deterministic() {
+		use rand_chacha::{rand_core::SeedableRng, ChaCha20Rng};
+
+		let build = || {
+			let env = Rc::new(Env::new(
+				ChaCha20Rng::from_seed([7u8; 32]),
+				FixedClock(1_700_000_000_000),
+			));
+			let god = Identity::generate_with(Uid::new(0), &mut **env.rng());
+			let seed = Seed::generate_with(&mut **env.rng());
+			let (mut fs, root) = FileSystem::new_with_env(&seed, &god, env);
+			let (_, dir) = fs.mkdir_mut(Uid::new(ROOT_ID), "dir", &god).unwrap();
+			let (_, file) = fs
+				.touch_mut(Uid::new(ROOT_ID), 10, "file", "txt", &god)
+				.unwrap();
+
+			(god, fs, vec![root, dir, file])
+		};
+		let (god0, fs0, nodes0) = build();
+		let (god1, fs1, nodes1) = build();
+
+		assert_eq!(god0, god1);
+		assert_eq!(fs0, fs1);
+		assert_eq!(nodes0, nodes1);
+		assert_eq!(
+			fs0.node_by_id(nodes0[1].id).unwrap().created_at,
+			1_700_000_000_000
+		);
+	}
+
 	#[test]
 	fn test_ls_root_empty() {
 		let fs = FileSystem {
 			roots: vec![],
 			cached_seeds: HashMap::new(),
+			env: Rc::new(Env::system()),
+			format: Format::Json,
+			rejected: vec![],
+			revisions: Revisions::new(),
+			bases: HashMap::new(),
+			listings: HashMap::new(),
+			events: vec![],
+			index: Index::default(),
 		};
 		let root_entries = fs.ls_root();
 
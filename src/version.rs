@@ -0,0 +1,157 @@
+// every persisted type carries a layout version; data written before versioning was introduced
+// has none and decodes as version 0
+//
+// decoding dispatches on the version, so that once a layout changes, its older versions can still
+// be read and then migrated (see FileSystem::migrate, LockedShare::migrate, identity::Encrypted::
+// migrate and password_lock::migrate_with); versions newer than this build understands are rejected
+// rather than misinterpreted
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::wire;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+	BadEncoding(wire::Error),
+	// written by a newer client
+	Unsupported(u32),
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::BadEncoding(e) => write!(f, "{}", e),
+			Error::Unsupported(v) => write!(f, "unsupported version {}", v),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::BadEncoding(e) => Some(e),
+			Error::Unsupported(_) => None,
+		}
+	}
+}
+
+pub trait Versioned: DeserializeOwned {
+	// the version new objects are written with
+	const VERSION: u32;
+
+	fn version(&self) -> u32;
+
+	fn is_outdated(&self) -> bool {
+		self.version() < Self::VERSION
+	}
+
+	// for objects decoded as a part of another one rather than with decode
+	fn check_version(&self) -> Result<(), Error> {
+		if self.version() > Self::VERSION {
+			Err(Error::Unsupported(self.version()))
+		} else {
+			Ok(())
+		}
+	}
+
+	// decodes a payload of a known version; override once a layout changes
+	fn decode_version(_version: u32, bytes: &[u8]) -> Result<Self, Error> {
+		wire::decode(bytes).map_err(Error::BadEncoding)
+	}
+}
+
+#[derive(Deserialize)]
+struct Header {
+	#[serde(default)]
+	version: u32,
+}
+
+pub fn decode<T: Versioned>(bytes: &[u8]) -> Result<T, Error> {
+	let Header { version } = wire::decode(bytes).map_err(Error::BadEncoding)?;
+
+	if version > T::VERSION {
+		Err(Error::Unsupported(version))
+	} else {
+		T::decode_version(version, bytes)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde::{Deserialize, Serialize};
+
+	use super::{decode, Error, Versioned};
+	use crate::wire::{self, Format};
+
+	// v0 had a name only, v1 split it into first and last
+	#[derive(Serialize, Deserialize, Debug, PartialEq)]
+	struct Person {
+		#[serde(default)]
+		version: u32,
+		first: String,
+		last: String,
+	}
+
+	#[derive(Deserialize)]
+	struct PersonV0 {
+		name: String,
+	}
+
+	impl Versioned for Person {
+		const VERSION: u32 = 1;
+
+		fn version(&self) -> u32 {
+			self.version
+		}
+
+		fn decode_version(version: u32, bytes: &[u8]) -> Result<Self, Error> {
+			match version {
+				0 => {
+					let v0: PersonV0 = wire::decode(bytes).map_err(Error::BadEncoding)?;
+					let (first, last) = v0.name.split_once(' ').unwrap_or((&v0.name, ""));
+
+					Ok(Person {
+						version: 0,
+						first: first.to_string(),
+						last: last.to_string(),
+					})
+				}
+				_ => wire::decode(bytes).map_err(Error::BadEncoding),
+			}
+		}
+	}
+
+	#[test]
+	fn test_dispatch_on_version() {
+		let v0: Person = decode(br#"{"name":"ada lovelace"}"#).unwrap();
+
+		assert!(v0.is_outdated());
+		assert_eq!(v0.first, "ada");
+		assert_eq!(v0.last, "lovelace");
+
+		let v1 = Person {
+			version: 1,
+			first: "alan".to_string(),
+			last: "turing".to_string(),
+		};
+
+		for format in [Format::Json, Format::Cbor] {
+			let decoded: Person = decode(&wire::encode(&v1, format)).unwrap();
+
+			assert_eq!(decoded, v1);
+			assert!(!decoded.is_outdated());
+		}
+	}
+
+	#[test]
+	fn test_reject_newer() {
+		assert_eq!(
+			decode::<Person>(br#"{"version":2,"first":"a","last":"b"}"#),
+			Err(Error::Unsupported(2))
+		);
+		assert!(matches!(
+			decode::<Person>(b"[]"),
+			Err(Error::BadEncoding(wire::Error::BadEncoding(_)))
+		));
+	}
+}
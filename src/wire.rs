@@ -0,0 +1,195 @@
+// encoding of everything that goes over the wire or into a ciphertext; json is the default and is
+// what older clients understand, cbor is a compact alternative for large vaults
+//
+// binary payloads start with a version byte, which json text can never start with, so decoding
+// needs no hints: json and any known binary version are recognized as is
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::error::Cause;
+
+// version byte of cbor-encoded payloads
+pub const CBOR_V1: u8 = 0x01;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Error {
+	// not a json nor a known binary encoding of the requested type; caused by the serde error
+	BadEncoding(Cause),
+	// a binary payload of a newer version
+	UnsupportedVersion(u8),
+}
+
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Error::BadEncoding(_) => f.write_str("bad encoding"),
+			Error::UnsupportedVersion(v) => write!(f, "unsupported binary version {}", v),
+		}
+	}
+}
+
+impl std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self {
+			Error::BadEncoding(cause) => cause.source(),
+			Error::UnsupportedVersion(_) => None,
+		}
+	}
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+	#[default]
+	Json,
+	Cbor,
+}
+
+impl Format {
+	pub const JSON_CONTENT_TYPE: &'static str = "application/json";
+	pub const CBOR_CONTENT_TYPE: &'static str = "application/vnd.qvault.v1+cbor";
+
+	pub fn content_type(&self) -> &'static str {
+		match self {
+			Format::Json => Self::JSON_CONTENT_TYPE,
+			Format::Cbor => Self::CBOR_CONTENT_TYPE,
+		}
+	}
+
+	pub fn from_content_type(content_type: &str) -> Option<Self> {
+		match content_type.split(';').next().map(str::trim) {
+			Some(Self::JSON_CONTENT_TYPE) => Some(Format::Json),
+			Some(Self::CBOR_CONTENT_TYPE) => Some(Format::Cbor),
+			_ => None,
+		}
+	}
+
+	// picks the most compact format a peer supports; json is always understood
+	pub fn negotiate(supported_by_peer: &[Format]) -> Self {
+		if supported_by_peer.contains(&Format::Cbor) {
+			Format::Cbor
+		} else {
+			Format::Json
+		}
+	}
+}
+
+pub fn encode<T: Serialize + ?Sized>(val: &T, format: Format) -> Vec<u8> {
+	match format {
+		Format::Json => serde_json::to_vec(val).unwrap(),
+		Format::Cbor => {
+			let mut bytes = vec![CBOR_V1];
+			ciborium::into_writer(val, &mut bytes).unwrap();
+
+			bytes
+		}
+	}
+}
+
+pub fn format_of(bytes: &[u8]) -> Result<Format, Error> {
+	match bytes.first() {
+		Some(&CBOR_V1) => Ok(Format::Cbor),
+		// control characters other than json whitespace are reserved for binary versions
+		Some(&b) if b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r') => {
+			Err(Error::UnsupportedVersion(b))
+		}
+		_ => Ok(Format::Json),
+	}
+}
+
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+	match format_of(bytes)? {
+		Format::Json => {
+			serde_json::from_slice(bytes).map_err(|e| Error::BadEncoding(Cause::new(e)))
+		}
+		Format::Cbor => {
+			ciborium::from_reader(&bytes[1..]).map_err(|e| Error::BadEncoding(Cause::new(e)))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::error::Error as _;
+
+	use super::{decode, encode, format_of, Error, Format, CBOR_V1};
+	use crate::{
+		aes_gcm::Aes,
+		error::Cause,
+		id::Uid,
+		identity::Identity,
+		seeds::{Bundle, Seed},
+	};
+
+	#[test]
+	fn test_encode_decode() {
+		let ident = Identity::generate(Uid::new(123));
+		let json = encode(&ident, Format::Json);
+		let cbor = encode(&ident, Format::Cbor);
+
+		assert_eq!(format_of(&json), Ok(Format::Json));
+		assert_eq!(format_of(&cbor), Ok(Format::Cbor));
+		assert_eq!(decode::<Identity>(&json).unwrap(), ident);
+		assert_eq!(decode::<Identity>(&cbor).unwrap(), ident);
+		// no base64 overhead
+		assert!(cbor.len() * 4 < json.len() * 3 + 64);
+	}
+
+	#[test]
+	fn test_uid_keyed_maps() {
+		let mut bundle = Bundle::new();
+		bundle.set_fs(Uid::new(1), Seed::generate());
+		bundle.set_db(Uid::new(u64::MAX), Seed::generate());
+
+		assert_eq!(
+			decode::<Bundle>(&encode(&bundle, Format::Cbor)).unwrap(),
+			bundle
+		);
+		assert_eq!(
+			decode::<Bundle>(&encode(&bundle, Format::Json)).unwrap(),
+			bundle
+		);
+	}
+
+	#[test]
+	fn test_json_with_leading_whitespace() {
+		let aes = Aes::new();
+		let json = [b"\n\t ".as_slice(), &encode(&aes, Format::Json)].concat();
+
+		assert_eq!(decode::<Aes>(&json).unwrap(), aes);
+	}
+
+	#[test]
+	fn test_bad_encoding() {
+		let cbor = encode(&Aes::new(), Format::Cbor);
+		let bad = Error::BadEncoding(Cause::default());
+
+		assert_eq!(decode::<Aes>(&[]), Err(bad.clone()));
+		assert!(decode::<Aes>(&[]).unwrap_err().source().is_some());
+		assert_eq!(decode::<Aes>(b"{}"), Err(bad.clone()));
+		assert_eq!(decode::<Aes>(&cbor[..cbor.len() - 1]), Err(bad.clone()));
+		assert_eq!(decode::<Uid>(&cbor), Err(bad));
+		assert_eq!(
+			decode::<Aes>(&[[CBOR_V1 + 1].as_slice(), &cbor[1..]].concat()),
+			Err(Error::UnsupportedVersion(CBOR_V1 + 1))
+		);
+	}
+
+	#[test]
+	fn test_negotiate() {
+		assert_eq!(Format::negotiate(&[]), Format::Json);
+		assert_eq!(Format::negotiate(&[Format::Json]), Format::Json);
+		assert_eq!(
+			Format::negotiate(&[Format::Json, Format::Cbor]),
+			Format::Cbor
+		);
+		assert_eq!(
+			Format::from_content_type("application/vnd.qvault.v1+cbor"),
+			Some(Format::Cbor)
+		);
+		assert_eq!(
+			Format::from_content_type("application/json; charset=utf-8"),
+			Some(Format::Json)
+		);
+		assert_eq!(Format::from_content_type("text/plain"), None);
+	}
+}
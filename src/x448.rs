@@ -1,9 +1,10 @@
-use rand::RngCore;
+use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 
 use crate::{
 	aes_gcm,
 	base64_blobs::{deserialize_vec_base64, serialize_vec_base64},
+	env::Rng,
 	hkdf,
 	key_pair::{KeyPair, KeyPairSize},
 	private_key::{PrivateKey, SharedKey},
@@ -32,6 +33,16 @@ pub enum Error {
 	BadKey,
 }
 
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			Error::BadKey => "bad key",
+		})
+	}
+}
+
+impl std::error::Error for Error {}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Encrypted {
 	// encrypted message
@@ -54,12 +65,15 @@ fn aes_from_dh_keys(sk: &PrivateKeyX448, pk: &PublicKeyX448) -> aes_gcm::Aes {
 
 impl PrivateKeyX448 {
 	pub fn generate() -> Self {
+		Self::generate_with(&mut OsRng)
+	}
+
+	pub fn generate_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
 		use x448::Secret;
 
 		let mut bytes = [0u8; KeyTypeX448::PRIV];
-		let mut csprng = rand::thread_rng();
 
-		csprng.fill_bytes(&mut bytes);
+		rng.fill_bytes(&mut bytes);
 
 		let secret = Secret::from(bytes);
 
@@ -85,7 +99,11 @@ impl PublicKeyX448 {
 	}
 
 	pub fn encrypt_serialized(&self, pt: &[u8]) -> Encrypted {
-		let kp = KeyPairX448::generate();
+		self.encrypt_serialized_with(pt, &mut OsRng)
+	}
+
+	pub fn encrypt_serialized_with<R: Rng + ?Sized>(&self, pt: &[u8], rng: &mut R) -> Encrypted {
+		let kp = KeyPairX448::generate_with(rng);
 		let aes = aes_from_dh_keys(kp.private_key(), &self);
 		let ct = aes.encrypt(pt);
 
@@ -123,7 +141,11 @@ impl From<&PublicKeyX448> for x448::PublicKey {
 
 impl KeyPairX448 {
 	pub fn generate() -> Self {
-		let private = PrivateKeyX448::generate();
+		Self::generate_with(&mut OsRng)
+	}
+
+	pub fn generate_with<R: Rng + ?Sized>(rng: &mut R) -> Self {
+		let private = PrivateKeyX448::generate_with(rng);
 		let public = PublicKeyX448::from_private(&private);
 
 		Self::new(private, public)